@@ -1,72 +1,134 @@
 use super::utils;
 use crate::error::ExecErr;
-use crate::pager::{Page, PAGE_SIZE};
+use crate::pager::{self, Page};
 use std::fmt;
 use std::io;
 use std::io::BufRead;
 
 use super::node::{IS_ROOT_SIZE, NODE_TYPE_SIZE, PARENT_SIZE};
-const CHILD_NUM: usize = PARENT_SIZE;
-const HEADER_SIZE: usize = NODE_TYPE_SIZE + IS_ROOT_SIZE + PARENT_SIZE + CHILD_NUM;
+const KEY_NUM: usize = PARENT_SIZE;
+const RIGHT_SIZE: usize = PARENT_SIZE;
+const HEADER_SIZE: usize = NODE_TYPE_SIZE + IS_ROOT_SIZE + PARENT_SIZE + KEY_NUM + RIGHT_SIZE;
 const CHILD_SIZE: usize = PARENT_SIZE * 2;
-const CHILD_MAX: usize = (PAGE_SIZE - HEADER_SIZE) / CHILD_SIZE;
-const SPLIT_IDX: usize = CHILD_MAX / 2 + 1;
 
+/// Separator keys an intern node holds in a page of `page_size` bytes, each with the child
+/// left of it.
+pub const fn key_max(page_size: usize) -> usize {
+  (pager::usable_size(page_size) - HEADER_SIZE) / CHILD_SIZE
+}
+
+/// Children an intern node holds in a page of `page_size` bytes: one per separator, and the
+/// right child.
+pub const fn child_max(page_size: usize) -> usize {
+  key_max(page_size) + 1
+}
+
+/// An intern node of the B+tree: children in key order, each but the last paired with a
+/// separator key. The keys under a child are above the separator before it and not above
+/// its own, and those under `right` are above the last separator.
 #[derive(Debug)]
 pub struct Intern {
   pub is_root: bool,
   pub parent: Option<usize>,
 
   pub children: Vec<Child>,
+  pub right: usize,
 }
 
 #[derive(Debug, Clone)]
 pub struct Child {
   pub pg_idx: usize,
-  pub key_max: u32,
+  pub key: u32,
 }
 
 impl Child {
-  pub fn new(pg_idx: usize, key_max: u32) -> Self {
-    Self { pg_idx, key_max }
+  pub fn new(pg_idx: usize, key: u32) -> Self {
+    Self { pg_idx, key }
   }
 }
 
 impl Intern {
-  pub fn new(is_root: bool, parent: Option<usize>, children: Vec<Child>) -> Self {
-    assert!(children.len() >= 2);
+  pub fn new(is_root: bool, parent: Option<usize>, children: Vec<Child>, right: usize) -> Self {
+    assert!(!children.is_empty());
     Self {
       is_root,
       parent,
       children,
+      right,
     }
   }
 
-  pub fn new_from_page(page: &Page) -> Self {
+  pub fn new_from_page(page: &Page) -> Result<Self, String> {
     let mut reader = io::Cursor::new(page);
     reader.consume(1); // the first byte is for node-type
 
-    let is_root = utils::read_bool_from(&mut reader);
-    let parent = utils::read_u32_from(&mut reader).map(|x| x as usize);
-    let num_child = utils::read_u32_from(&mut reader).unwrap();
+    let is_root = utils::read_bool_from(&mut reader)?;
+    let parent = utils::read_u32_from(&mut reader)?.map(|x| x as usize);
+    let num_key = utils::read_some_u32_from(&mut reader, "key count")? as usize;
+    let max = key_max(page.len());
+    if !(1..=max).contains(&num_key) {
+      return Err(format!(
+        "{num_key} keys, outside the 1 to {max} an intern node holds"
+      ));
+    }
+    let right = utils::read_some_u32_from(&mut reader, "right child")? as usize;
 
-    let children: Vec<_> = (0..num_child)
-      .map(|_| {
-        let pg_idx = utils::read_u32_from(&mut reader).unwrap() as usize;
-        let key_max = utils::read_u32_from(&mut reader).unwrap();
-        Child { pg_idx, key_max }
-      })
-      .collect();
+    let mut children: Vec<Child> = Vec::with_capacity(num_key);
+    for i in 0..num_key {
+      let pg_idx = utils::read_some_u32_from(&mut reader, "child page")? as usize;
+      let key = utils::read_some_u32_from(&mut reader, "child key")?;
+      if children.last().is_some_and(|ch| ch.key >= key) {
+        return Err(format!("key {key} of child {i} is out of order"));
+      }
+      children.push(Child { pg_idx, key });
+    }
 
-    Self {
+    Ok(Self {
       is_root,
       parent,
       children,
+      right,
+    })
+  }
+
+  /// Read an intern node written in format 1, before separator keys: a child count, then
+  /// every child with the largest key under it. Those keys are the separators of all but the
+  /// last child, which becomes the right child.
+  pub fn new_from_legacy_page(page: &Page) -> Result<Self, String> {
+    let mut reader = io::Cursor::new(page);
+    reader.consume(1); // the first byte is for node-type
+
+    let is_root = utils::read_bool_from(&mut reader)?;
+    let parent = utils::read_u32_from(&mut reader)?.map(|x| x as usize);
+    let num_child = utils::read_some_u32_from(&mut reader, "child count")? as usize;
+    let max = child_max(page.len());
+    if !(2..=max).contains(&num_child) {
+      return Err(format!(
+        "{num_child} children, outside the 2 to {max} an intern node holds"
+      ));
+    }
+
+    let mut children: Vec<Child> = Vec::with_capacity(num_child);
+    for i in 0..num_child {
+      let pg_idx = utils::read_some_u32_from(&mut reader, "child page")? as usize;
+      let key = utils::read_some_u32_from(&mut reader, "child key")?;
+      if i + 1 < num_child && children.last().is_some_and(|ch| ch.key >= key) {
+        return Err(format!("key {key} of child {i} is out of order"));
+      }
+      children.push(Child { pg_idx, key });
     }
+    let right = children.pop().unwrap().pg_idx;
+
+    Ok(Self {
+      is_root,
+      parent,
+      children,
+      right,
+    })
   }
 
-  pub fn serialize(&self) -> Page {
-    let mut buf = [0u8; PAGE_SIZE];
+  pub fn serialize(&self, page_size: usize) -> Page {
+    let mut buf = vec![0u8; page_size];
     let mut writer = io::Cursor::new(&mut buf[..]);
 
     // write node-type: is_leaf as false
@@ -74,123 +136,148 @@ impl Intern {
     utils::write_bool_to(&mut writer, self.is_root);
     utils::write_opt_u32_to(&mut writer, self.parent.map(|x| x as u32));
     utils::write_opt_u32_to(&mut writer, Some(self.children.len() as u32));
+    utils::write_opt_u32_to(&mut writer, Some(self.right as u32));
 
-    for Child { pg_idx, key_max } in &self.children {
+    for Child { pg_idx, key } in &self.children {
       utils::write_opt_u32_to(&mut writer, Some(*pg_idx as u32));
-      utils::write_opt_u32_to(&mut writer, Some(*key_max));
+      utils::write_opt_u32_to(&mut writer, Some(*key));
     }
     buf
   }
 
-  pub fn insert_child(&mut self, pg_idx: usize, key_max: u32) -> Result<(), ExecErr> {
-    // TODO: remove 1000
-    if self.children.len() >= CHILD_MAX {
+  /// Number of children, the right one included.
+  pub fn child_count(&self) -> usize {
+    self.children.len() + 1
+  }
+
+  /// Page of child `idx`, where `idx` is the number of separators: the right child.
+  pub fn child(&self, idx: usize) -> usize {
+    self.children.get(idx).map_or(self.right, |ch| ch.pg_idx)
+  }
+
+  /// Pages of all children, in key order.
+  pub fn child_pages(&self) -> impl Iterator<Item = usize> + '_ {
+    (0..self.child_count()).map(|idx| self.child(idx))
+  }
+
+  /// Page of the child whose range holds `key`.
+  pub fn find_child(&self, key: u32) -> usize {
+    self.child(self.search_child_by_key(key))
+  }
+
+  /// Index of the child whose range holds `key`: the first with a separator not below it, or
+  /// the right child.
+  pub fn search_child_by_key(&self, key: u32) -> usize {
+    self.children.partition_point(|ch| ch.key < key)
+  }
+
+  /// Record that the child holding `key` split after it: the keys up to `key` stay, and those
+  /// above moved to `pg_idx`, right after it. The node is in a page of `page_size` bytes.
+  pub fn insert_child(&mut self, key: u32, pg_idx: usize, page_size: usize) -> Result<(), ExecErr> {
+    if self.children.len() >= key_max(page_size) {
       return Err(ExecErr::InternNodeFull("Intern node full".to_string()));
     }
-    let idx = self.search_insert_idx_by_key(key_max);
-    self.children.insert(idx, Child::new(pg_idx, key_max));
+    self.split_child(key, pg_idx);
     Ok(())
   }
 
-  pub fn insert_child_and_split(&mut self, pg_idx: usize, key_max: u32) -> Result<Self, ExecErr> {
-    let idx = self.search_insert_idx_by_key(key_max);
-    self.children.insert(idx, Child::new(pg_idx, key_max));
-    let children: Vec<_> = self.children.drain(SPLIT_IDX..).collect(); // TODO:
-
-    Ok(Self {
+  /// As `insert_child`, when the node is full: split it in two, keep the lower half, and
+  /// return the separator between the halves with the upper one, which goes after this node
+  /// in its parent.
+  pub fn insert_child_and_split(&mut self, key: u32, pg_idx: usize) -> (u32, Self) {
+    self.split_child(key, pg_idx);
+    let mid = self.children.len() / 2;
+    let mut upper: Vec<_> = self.children.drain(mid..).collect();
+    let Child { pg_idx: right, key } = upper.remove(0);
+    let node = Self {
       is_root: false,
       parent: self.parent,
-      children,
-    })
+      children: upper,
+      right: std::mem::replace(&mut self.right, right),
+    };
+    (key, node)
   }
 
-  pub fn find_child_and<F, T>(&self, key_max: u32, mut f: F) -> Result<T, ExecErr>
-  where
-    F: FnMut(&Child) -> T,
-  {
-    let idx = self.search_child_by_key(key_max);
-    Ok(f(&self.children[idx]))
-  }
-
-  #[allow(unused)]
-  pub fn find_mut_child_and<F, T>(&mut self, key_max: u32, mut f: F) -> Result<T, ExecErr>
-  where
-    F: FnMut(&mut Child) -> T,
-  {
-    let idx = self.search_child_by_key(key_max);
-    Ok(f(&mut self.children[idx]))
-  }
-
-  fn search_insert_idx_by_key(&self, key: u32) -> usize {
-    // binary search
-    let mut lower = 0;
-    let mut upper = self.children.len();
-    while lower < upper {
-      let mid = (lower + upper) / 2;
-      let key_mid = self.children[mid].key_max;
-      if key <= key_mid {
-        upper = mid;
-      } else {
-        lower = mid + 1;
+  fn split_child(&mut self, key: u32, pg_idx: usize) {
+    let idx = self.search_child_by_key(key);
+    match self.children.get_mut(idx) {
+      Some(ch) => {
+        let upper = std::mem::replace(&mut ch.key, key);
+        self.children.insert(idx + 1, Child::new(pg_idx, upper));
+      }
+      None => {
+        let lower = std::mem::replace(&mut self.right, pg_idx);
+        self.children.push(Child::new(lower, key));
       }
     }
-    lower
-  }
-
-  fn search_child_by_key(&self, key: u32) -> usize {
-    self
-      .search_insert_idx_by_key(key)
-      .min(self.children.len() - 1)
   }
 }
 
 impl fmt::Display for Intern {
   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-    write!(f, "intern (size {})", self.children.len(),)
+    write!(f, "intern (size {})", self.child_count())
   }
 }
 
 #[cfg(test)]
 mod tests {
   use super::*;
+  use crate::pager::DEFAULT_PAGE_SIZE;
 
+  /// A node over pages 0 to `keys.len()`, page `i` holding the keys up to `keys[i]`.
   fn setup(keys: &[u32]) -> Intern {
-    Intern {
-      is_root: false,
-      parent: None,
-      children: keys.iter().map(|&i| Child::new(i as usize, i)).collect(),
-    }
+    let children = keys.iter().enumerate().map(|(i, &k)| Child::new(i, k));
+    Intern::new(false, None, children.collect(), keys.len())
+  }
+
+  fn keys(nd: &Intern) -> Vec<u32> {
+    nd.children.iter().map(|ch| ch.key).collect()
+  }
+
+  fn pages(nd: &Intern) -> Vec<usize> {
+    nd.child_pages().collect()
+  }
+
+  #[test]
+  fn find_child_by_separator() {
+    let nd = setup(&[10, 20]);
+    assert_eq!(nd.find_child(0), 0);
+    assert_eq!(nd.find_child(10), 0);
+    assert_eq!(nd.find_child(11), 1);
+    assert_eq!(nd.find_child(20), 1);
+    assert_eq!(nd.find_child(u32::MAX), 2);
   }
 
   #[test]
-  fn insert_rightmost_child() {
-    let mut parent = setup(&[1, 3]);
-    parent.insert_child(4, 4).unwrap();
-    let keys: Vec<_> = parent.children.iter().map(|ch| ch.key_max).collect();
-    assert_eq!(keys, vec![1, 3, 4]);
+  fn split_right_child() {
+    let mut nd = setup(&[10, 20]);
+    nd.insert_child(30, 9, DEFAULT_PAGE_SIZE).unwrap();
+    assert_eq!(keys(&nd), vec![10, 20, 30]);
+    assert_eq!(pages(&nd), vec![0, 1, 2, 9]);
   }
 
   #[test]
-  fn insert_mid_child() {
-    let mut parent = setup(&[1, 3]);
-    parent.insert_child(2, 2).unwrap();
-    let keys: Vec<_> = parent.children.iter().map(|ch| ch.key_max).collect();
-    assert_eq!(keys, vec![1, 2, 3]);
+  fn split_mid_child() {
+    let mut nd = setup(&[10, 20]);
+    nd.insert_child(15, 9, DEFAULT_PAGE_SIZE).unwrap();
+    assert_eq!(keys(&nd), vec![10, 15, 20]);
+    assert_eq!(pages(&nd), vec![0, 1, 9, 2]);
   }
 
   #[test]
-  fn insert_leaftmost_child() {
-    let mut parent = setup(&[1, 3]);
-    parent.insert_child(0, 0).unwrap();
-    let keys: Vec<_> = parent.children.iter().map(|ch| ch.key_max).collect();
-    assert_eq!(keys, vec![0, 1, 3]);
+  fn split_leftmost_child() {
+    let mut nd = setup(&[10, 20]);
+    nd.insert_child(5, 9, DEFAULT_PAGE_SIZE).unwrap();
+    assert_eq!(keys(&nd), vec![5, 10, 20]);
+    assert_eq!(pages(&nd), vec![0, 9, 1, 2]);
   }
 
   #[test]
-  fn insert_rightmost_in_3_child_parent() {
-    let mut parent = setup(&[1, 2, 3]);
-    parent.insert_child(4, 4).unwrap();
-    let keys: Vec<_> = parent.children.iter().map(|ch| ch.key_max).collect();
-    assert_eq!(keys, vec![1, 2, 3, 4]);
+  fn split_full_node() {
+    let mut nd = setup(&[10, 20, 30]);
+    let (key, upper) = nd.insert_child_and_split(40, 9);
+    assert_eq!((keys(&nd), pages(&nd)), (vec![10, 20], vec![0, 1, 2]));
+    assert_eq!(key, 30);
+    assert_eq!((keys(&upper), pages(&upper)), (vec![40], vec![3, 9]));
   }
 }
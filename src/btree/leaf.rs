@@ -4,15 +4,20 @@ use std::io::{self, BufRead, Read, Write};
 use std::{fmt, mem};
 
 use super::node::{IS_ROOT_SIZE, NODE_TYPE_SIZE, PARENT_SIZE};
-use crate::pager::{Page, PAGE_SIZE};
-use crate::row::{RowBytes, ROW_SIZE};
+use crate::pager::{self, Page};
+use crate::row::{Row, RowBytes, ROW_SIZE};
 
 const NEXT_LEAF_SIZE: usize = mem::size_of::<u32>();
-const HEADER_SIZE: usize = NODE_TYPE_SIZE + IS_ROOT_SIZE + PARENT_SIZE + NEXT_LEAF_SIZE;
+const NUM_CELLS_SIZE: usize = mem::size_of::<u32>();
+const HEADER_SIZE: usize =
+  NODE_TYPE_SIZE + IS_ROOT_SIZE + PARENT_SIZE + NEXT_LEAF_SIZE + NUM_CELLS_SIZE;
 const CELL_KEY_SIZE: usize = mem::size_of::<u32>();
 const CELL_SIZE: usize = CELL_KEY_SIZE + ROW_SIZE;
-pub const MAX_CELLS: usize = (PAGE_SIZE - HEADER_SIZE) / CELL_SIZE;
-const SPLIT_IDX: usize = MAX_CELLS / 2 + 1;
+
+/// Cells a leaf holds in a page of `page_size` bytes.
+pub const fn max_cells(page_size: usize) -> usize {
+  (pager::usable_size(page_size) - HEADER_SIZE) / CELL_SIZE
+}
 
 #[derive(Debug)]
 pub struct Leaf {
@@ -39,37 +44,48 @@ impl Leaf {
     }
   }
 
-  pub fn new_from_page(page: &Page) -> Self {
+  pub fn new_from_page(page: &Page) -> Result<Self, String> {
     let mut reader = io::Cursor::new(page);
     reader.consume(1); // the first byte is for node-type
 
-    let is_root = utils::read_bool_from(&mut reader);
-    let parent = utils::read_u32_from(&mut reader).map(|x| x as usize);
-    let next = utils::read_u32_from(&mut reader).map(|x| x as usize);
-
-    let num_cells = utils::read_u32_from(&mut reader).unwrap();
-    let cells: Vec<_> = (0..num_cells)
-      .map(|_| {
-        let key = utils::read_u32_from(&mut reader).unwrap();
-        let row = {
-          let mut buf = [0; ROW_SIZE];
-          reader.read_exact(&mut buf).unwrap();
-          buf
-        };
-        Cell { key, row }
-      })
-      .collect();
+    let is_root = utils::read_bool_from(&mut reader)?;
+    let parent = utils::read_u32_from(&mut reader)?.map(|x| x as usize);
+    let next = utils::read_u32_from(&mut reader)?.map(|x| x as usize);
 
-    Self {
+    let num_cells = utils::read_some_u32_from(&mut reader, "cell count")? as usize;
+    let max = max_cells(page.len());
+    if num_cells > max {
+      return Err(format!(
+        "{num_cells} cells, more than the {max} a leaf holds"
+      ));
+    }
+    if num_cells == 0 && !is_root {
+      return Err("a leaf other than the root has no cells".to_string());
+    }
+    let mut cells: Vec<Cell> = Vec::with_capacity(num_cells);
+    for i in 0..num_cells {
+      let key = utils::read_some_u32_from(&mut reader, "cell key")?;
+      if cells.last().is_some_and(|c| c.key >= key) {
+        return Err(format!("key {key} of cell {i} is out of order"));
+      }
+      let mut row = [0; ROW_SIZE];
+      reader
+        .read_exact(&mut row)
+        .map_err(|_| format!("cell {i} runs past the page"))?;
+      Row::check(&row, key).map_err(|reason| format!("cell {i}: {reason}"))?;
+      cells.push(Cell { key, row });
+    }
+
+    Ok(Self {
       is_root,
       parent,
       next,
       cells,
-    }
+    })
   }
 
-  pub fn serialize(&self) -> Page {
-    let mut cache = [0u8; PAGE_SIZE];
+  pub fn serialize(&self, page_size: usize) -> Page {
+    let mut cache = vec![0u8; page_size];
     let mut writer = io::Cursor::new(&mut cache[..]);
 
     // write node-type, is_leaf as true
@@ -86,14 +102,16 @@ impl Leaf {
     cache
   }
 
-  pub fn insert_row(&mut self, key: u32, row: &RowBytes) -> Result<(), ExecErr> {
-    if self.cells.len() >= MAX_CELLS {
-      return Err(ExecErr::LeafNodeFull("Leaf full".to_string()));
-    }
+  /// Insert `row` under `key` into this leaf, of a page of `page_size` bytes.
+  pub fn insert_row(&mut self, key: u32, row: &RowBytes, page_size: usize) -> Result<(), ExecErr> {
+    // a duplicate is refused before a full leaf is split to make room for it
     let idx = self.search_cell_idx_by_key(key);
     if self.cells.get(idx).is_some_and(|c| c.key == key) {
       return Err(ExecErr::DuplicateKey("Duplicated key".to_string()));
     }
+    if self.cells.len() >= max_cells(page_size) {
+      return Err(ExecErr::LeafNodeFull("Leaf full".to_string()));
+    }
     self.cells.insert(idx, Cell { key, row: *row });
     Ok(())
   }
@@ -105,8 +123,14 @@ impl Leaf {
     pg_idx_new: usize,
   ) -> Result<Self, ExecErr> {
     let idx = self.search_cell_idx_by_key(key);
+    // appending past the end of the table, as ascending ids do, moves only the new row to
+    // the new leaf and leaves this one full, as SQLite's quickbalance does
+    let split = match idx == self.cells.len() && self.next.is_none() {
+      true => idx,
+      false => self.cells.len() / 2 + 1,
+    };
     self.cells.insert(idx, Cell { key, row: *row });
-    let cells: Vec<_> = self.cells.drain(SPLIT_IDX..).collect();
+    let cells: Vec<_> = self.cells.drain(split..).collect();
     let next_old = self.next.replace(pg_idx_new);
 
     Ok(Self {
@@ -165,3 +189,34 @@ impl fmt::Display for Leaf {
     write!(f, "{}", cell_str.join("\n"))
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::pager::{MAX_PAGE_SIZE, MIN_PAGE_SIZE};
+
+  #[test]
+  fn full_leaf_fits_its_page() {
+    let mut page_size = MIN_PAGE_SIZE;
+    while page_size <= MAX_PAGE_SIZE {
+      let usable = pager::usable_size(page_size);
+      let max = max_cells(page_size);
+      assert!(
+        HEADER_SIZE + max * CELL_SIZE <= usable,
+        "page size {page_size}"
+      );
+
+      // every byte of the cells is set, so one past `usable` would show in the reserved tail
+      let mut leaf = Leaf::new(true, None, None);
+      for key in 0..max as u32 {
+        leaf.insert_row(key, &[0xff; ROW_SIZE], page_size).unwrap();
+      }
+      let page = leaf.serialize(page_size);
+      assert!(
+        page[usable..].iter().all(|&b| b == 0),
+        "page size {page_size}"
+      );
+      page_size *= 2;
+    }
+  }
+}
@@ -1,9 +1,9 @@
 use super::intern::Intern;
 use super::leaf::Leaf;
-use crate::btree::utils;
 use crate::error::ExecErr;
 use crate::pager::Page;
-use std::{fmt, io, mem};
+use crate::table::ROOT;
+use std::{fmt, mem};
 
 pub const NODE_TYPE_SIZE: usize = mem::size_of::<u8>();
 pub const IS_ROOT_SIZE: usize = mem::size_of::<u8>();
@@ -22,7 +22,6 @@ impl Node {
     }
   }
 
-  #[allow(unused)]
   pub fn get_is_root(&self) -> bool {
     match self {
       Self::Intern(nd) => nd.is_root,
@@ -37,7 +36,6 @@ impl Node {
     }
   }
 
-  #[allow(unused)]
   pub fn get_parent(&self) -> Option<usize> {
     match self {
       Self::Intern(nd) => nd.parent,
@@ -52,20 +50,55 @@ impl Node {
     }
   }
 
-  pub fn new_from_page(page: &Page) -> Self {
-    let mut reader = io::Cursor::new(page);
-    let is_leaf = utils::read_bool_from(&mut reader);
-    if is_leaf {
-      Self::Leaf(Leaf::new_from_page(page))
-    } else {
-      Self::Intern(Intern::new_from_page(page))
-    }
+  /// Read the node stored in page `pid`, checking every field, so that a corrupt page is an
+  /// error rather than a panic later on. A child pointer back to the root or to the node
+  /// itself is corrupt too, as descending it would never reach a leaf.
+  pub fn new_from_page(pid: usize, page: &Page) -> Result<Self, ExecErr> {
+    Self::decode(pid, page, Intern::new_from_page)
+  }
+
+  /// Like `new_from_page`, for a page of a format 1 file, whose intern nodes differ.
+  pub fn new_from_legacy_page(pid: usize, page: &Page) -> Result<Self, ExecErr> {
+    Self::decode(pid, page, Intern::new_from_legacy_page)
+  }
+
+  fn decode(
+    pid: usize,
+    page: &Page,
+    intern: fn(&Page) -> Result<Intern, String>,
+  ) -> Result<Self, ExecErr> {
+    let node = match page[0] {
+      1 => Leaf::new_from_page(page).map(Self::Leaf),
+      0 => intern(page).and_then(|nd| {
+        let back = nd
+          .child_pages()
+          .enumerate()
+          .find(|&(_, pg)| pg == ROOT || pg == pid);
+        match back {
+          Some((i, pg)) => Err(format!("child {i} points back to page {pg}")),
+          None => Ok(Self::Intern(nd)),
+        }
+      }),
+      b => Err(format!("unknown node type {b}")),
+    };
+    node.map_err(|reason| ExecErr::Corrupt { page: pid, reason })
+  }
+
+  /// Pages this node points to: its parent, and its children or next leaf.
+  pub fn page_refs(&self) -> Vec<usize> {
+    let (parent, mut refs) = match self {
+      Self::Intern(nd) => (nd.parent, nd.child_pages().collect()),
+      Self::Leaf(nd) => (nd.parent, nd.next.into_iter().collect::<Vec<_>>()),
+    };
+    refs.extend(parent);
+    refs
   }
 
-  pub fn serialize(&self) -> Page {
+  /// The page of `page_size` bytes that holds this node.
+  pub fn serialize(&self, page_size: usize) -> Page {
     match self {
-      Self::Intern(nd) => nd.serialize(),
-      Self::Leaf(nd) => nd.serialize(),
+      Self::Intern(nd) => nd.serialize(page_size),
+      Self::Leaf(nd) => nd.serialize(page_size),
     }
   }
 
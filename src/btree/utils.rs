@@ -1,21 +1,39 @@
 use crate::pager::Page;
 use std::io::{Cursor, Read, Write};
 
-pub fn read_u32_from(reader: &mut Cursor<&Page>) -> Option<u32> {
+// Readers fail with the reason a page is corrupt, for `Node::new_from_page` to report.
+
+pub fn read_u32_from(reader: &mut Cursor<&Page>) -> Result<Option<u32>, String> {
   let mut buf = [0; 4];
-  reader.read_exact(&mut buf).unwrap();
+  reader
+    .read_exact(&mut buf)
+    .map_err(|_| format!("field at offset {} runs past the page", reader.position()))?;
   let res = u32::from_be_bytes(buf);
   if res == u32::MAX {
-    None
+    Ok(None)
   } else {
-    Some(res)
+    Ok(Some(res))
   }
 }
 
-pub fn read_bool_from(reader: &mut Cursor<&Page>) -> bool {
+/// Like `read_u32_from`, for fields that always hold a number.
+pub fn read_some_u32_from(reader: &mut Cursor<&Page>, field: &str) -> Result<u32, String> {
+  read_u32_from(reader)?.ok_or_else(|| format!("{field} is missing"))
+}
+
+pub fn read_bool_from(reader: &mut Cursor<&Page>) -> Result<bool, String> {
   let mut buf = [0];
-  reader.read_exact(&mut buf).unwrap();
-  buf[0] != 0
+  reader
+    .read_exact(&mut buf)
+    .map_err(|_| format!("field at offset {} runs past the page", reader.position()))?;
+  match buf[0] {
+    0 => Ok(false),
+    1 => Ok(true),
+    b => Err(format!(
+      "byte {b} at offset {} is not a boolean",
+      reader.position() - 1
+    )),
+  }
 }
 
 pub fn write_bool_to(writer: &mut Cursor<&mut [u8]>, val: bool) {
@@ -0,0 +1,340 @@
+use crate::error::{DbError, ExecErr};
+use crate::mapping::{self, RowDeserializer};
+use crate::statement::Statement;
+use crate::table::Table;
+use crate::value::Value;
+use crate::{btree, cursor, pager, prepare_statement, row, vdbe};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::path::Path;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// An open database file. Statements run against it return their rows to the caller, which
+/// decides what to do with them; nothing is printed.
+///
+/// Rows reach the file when the connection is closed or dropped. Only `close` reports an
+/// error in writing them.
+pub struct Connection {
+  pub(crate) table: Table,
+  pub(crate) id: u64, // unique in the process, to tell which statements are its own
+  closed: bool,       // whether `close` already wrote the pages back
+}
+
+/// Bytes a row takes in a leaf cell.
+pub const ROW_SIZE: usize = row::ROW_SIZE;
+/// Bytes in each page of a new database, unless `Options::page_size` chooses otherwise.
+pub const DEFAULT_PAGE_SIZE: usize = pager::DEFAULT_PAGE_SIZE;
+/// Rows a leaf page of `DEFAULT_PAGE_SIZE` holds.
+pub const LEAF_NODE_MAX_CELLS: usize = btree::leaf::max_cells(DEFAULT_PAGE_SIZE);
+
+/// Settings for a database file, applied when `Connection::open_with` creates it. An existing
+/// file keeps the settings it was created with.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Options {
+  /// Store a CRC-32C checksum in every page, checked whenever the page is read, to catch torn
+  /// writes and bit rot.
+  pub checksums: bool,
+  /// Bytes in each page, a power of two from 512 to 65536; `DEFAULT_PAGE_SIZE` when none.
+  /// Larger pages hold more rows per leaf and make the tree shallower.
+  pub page_size: Option<usize>,
+}
+
+impl Connection {
+  /// Open the database in `path`, creating the file if it does not exist.
+  pub fn open(path: impl AsRef<Path>) -> Result<Self, DbError> {
+    Self::open_with(path, Options::default())
+  }
+
+  /// Open the database in `path`, creating the file with `options` if it does not exist.
+  pub fn open_with(path: impl AsRef<Path>, options: Options) -> Result<Self, DbError> {
+    let page_size = options.page_size.unwrap_or(DEFAULT_PAGE_SIZE);
+    static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+    let table = Table::open_db(path, options.checksums, page_size).map_err(DbError::ExecErr)?;
+    Ok(Self {
+      table,
+      id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+      closed: false,
+    })
+  }
+
+  /// Whether the pages of the file carry checksums.
+  pub fn checksums(&self) -> bool {
+    self.table.checksums()
+  }
+
+  /// Bytes in each page of the file.
+  pub fn page_size(&self) -> usize {
+    self.table.page_size()
+  }
+
+  /// Rows a leaf page of the file holds.
+  pub fn leaf_max_cells(&self) -> usize {
+    btree::leaf::max_cells(self.page_size())
+  }
+
+  /// Write every cached page back to the file and close it.
+  pub fn close(mut self) -> Result<(), DbError> {
+    self.closed = true;
+    self.table.close_db().map_err(DbError::ExecErr)
+  }
+
+  /// Compile a single statement, to run with `Statement::step` once its parameters are bound.
+  pub fn prepare(&self, sql: &str) -> Result<Statement, DbError> {
+    self.compile(sql, true)
+  }
+
+  /// Compile a single statement to run as written, without binding: the values of an insert
+  /// are stored as the text they are, so `insert 1 :bob x@y` stores the username `:bob`.
+  pub fn prepare_literal(&self, sql: &str) -> Result<Statement, DbError> {
+    self.compile(sql, false)
+  }
+
+  fn compile(&self, sql: &str, params: bool) -> Result<Statement, DbError> {
+    let (stmt, variables) = prepare_statement(sql, params).map_err(DbError::PrepareErr)?;
+    let program = vdbe::compile(&stmt, variables, &self.table).map_err(DbError::ExecErr)?;
+    Ok(Statement::new(program, self.id))
+  }
+
+  /// Run a statement to completion, returning the number of rows it inserted. The rows a
+  /// query yields are discarded.
+  pub fn execute(&mut self, sql: &str) -> Result<usize, DbError> {
+    let mut stmt = self.prepare_literal(sql)?;
+    while stmt.step(self)?.is_some() {}
+    Ok(stmt.changes())
+  }
+
+  /// Run a query, returning its rows as they are produced.
+  pub fn query(&mut self, sql: &str) -> Result<Rows<'_>, DbError> {
+    let stmt = self.prepare_literal(sql)?;
+    Ok(Rows::new(self, stmt))
+  }
+
+  /// Run a query and read each of its rows as a `T`, matching struct fields to columns by name.
+  pub fn query_as<T: DeserializeOwned>(&mut self, sql: &str) -> Result<Vec<T>, DbError> {
+    self.query(sql)?.map(|row| row?.deserialize()).collect()
+  }
+
+  /// Insert `value`, a struct or map whose fields are named after the columns of the users
+  /// table. A missing `username` or `email` is stored empty.
+  pub fn insert<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<usize, DbError> {
+    let mut values = [None, None, None];
+    for (name, value) in mapping::to_columns(value)? {
+      let idx = row::COLUMNS
+        .iter()
+        .position(|col| col.eq_ignore_ascii_case(&name))
+        .ok_or_else(|| {
+          DbError::ExecErr(ExecErr::NoSuchColumn(format!("No such column: {name}.")))
+        })?;
+      values[idx] = Some(value);
+    }
+    let mut stmt = self.prepare("insert ? ? ?")?;
+    for (idx, value) in values.into_iter().enumerate() {
+      let value = match value {
+        Some(value) => value,
+        None if idx == 0 => {
+          return Err(DbError::ExecErr(ExecErr::MappingError(
+            "Missing field: id.".to_string(),
+          )))
+        }
+        None => Value::Null,
+      };
+      stmt.bind_value(idx + 1, value)?;
+    }
+    while stmt.step(self)?.is_some() {}
+    Ok(stmt.changes())
+  }
+
+  /// Load `(id, username, email)` rows into the empty users table, which is far faster than
+  /// inserting them one by one. The rows must come in ascending id order: the B-tree is built
+  /// bottom up, with every node filled to `fill` of its capacity, above 0 and at most 1.
+  /// Returns the number of rows loaded; on error, none are.
+  pub fn bulk_load<I, S>(&mut self, rows: I, fill: f64) -> Result<usize, DbError>
+  where
+    I: IntoIterator<Item = (u32, S, S)>,
+    S: AsRef<str>,
+  {
+    let rows = rows.into_iter().map(|(id, username, email)| {
+      row::Row::build(id, username.as_ref(), email.as_ref()).map_err(DbError::PrepareErr)
+    });
+    self.table.bulk_load(rows, fill)
+  }
+
+  /// Check the whole B-tree, as `pragma integrity_check` does, returning a line per problem
+  /// found; none when the file is sound.
+  pub fn integrity_check(&self) -> Vec<String> {
+    self.table.integrity_check()
+  }
+
+  /// The B-tree of the users table, a line per node.
+  pub fn btree(&self) -> Result<String, DbError> {
+    self.table.btree_to_str().map_err(DbError::ExecErr)
+  }
+
+  /// A cursor over the rows of the users table, invalid until it is positioned.
+  pub fn cursor(&mut self) -> Cursor<'_> {
+    Cursor {
+      conn: self,
+      cursor: cursor::Cursor::new(),
+      columns: row::COLUMNS.map(String::from).into(),
+    }
+  }
+}
+
+impl Drop for Connection {
+  /// Write every cached page back to the file, as `close` does, dropping any error.
+  fn drop(&mut self) {
+    if !self.closed {
+      let _ = self.table.close_db();
+    }
+  }
+}
+
+/// A cursor over the rows of the users table in key order, moved by seeking a key or stepping
+/// to a neighbouring row. It stays on its row while rows are inserted through it.
+pub struct Cursor<'c> {
+  conn: &'c mut Connection,
+  cursor: cursor::Cursor,
+  columns: Rc<[String]>,
+}
+
+impl Cursor<'_> {
+  /// Move to the first row.
+  pub fn first(&mut self) -> Result<(), DbError> {
+    Ok(self.cursor.first(&self.conn.table)?)
+  }
+
+  /// Move to the last row.
+  pub fn last(&mut self) -> Result<(), DbError> {
+    Ok(self.cursor.last(&self.conn.table)?)
+  }
+
+  /// Move to the first row with an id not below `id`.
+  pub fn seek_ge(&mut self, id: u32) -> Result<(), DbError> {
+    Ok(self.cursor.seek_ge(&self.conn.table, id)?)
+  }
+
+  /// Move to the last row with an id not above `id`.
+  pub fn seek_le(&mut self, id: u32) -> Result<(), DbError> {
+    Ok(self.cursor.seek_le(&self.conn.table, id)?)
+  }
+
+  /// Move to the next row, or off the end of the table after the last.
+  #[allow(clippy::should_implement_trait)] // a step, not an iterator: it yields no row
+  pub fn next(&mut self) -> Result<(), DbError> {
+    Ok(self.cursor.next(&self.conn.table)?)
+  }
+
+  /// Move to the previous row, or off the start of the table before the first.
+  pub fn prev(&mut self) -> Result<(), DbError> {
+    Ok(self.cursor.prev(&self.conn.table)?)
+  }
+
+  /// Whether the cursor is on a row.
+  pub fn is_valid(&self) -> bool {
+    self.cursor.is_valid()
+  }
+
+  /// The id of the row under the cursor.
+  pub fn key(&self) -> Option<u32> {
+    self.cursor.key()
+  }
+
+  /// The row under the cursor.
+  pub fn value(&mut self) -> Result<Option<Row>, DbError> {
+    if !self.cursor.is_valid() {
+      return Ok(None);
+    }
+    let row = self.cursor.value(&self.conn.table)?;
+    Ok(Some(Row {
+      columns: self.columns.clone(),
+      values: row.to_values(),
+    }))
+  }
+
+  /// Insert a row and move onto it.
+  pub fn insert(&mut self, id: u32, username: &str, email: &str) -> Result<(), DbError> {
+    let row = row::Row::build(id, username, email)?;
+    Ok(self.cursor.insert(&mut self.conn.table, &row)?)
+  }
+}
+
+/// The rows of a statement, read by stepping it. Iteration stops after the first error.
+pub struct Rows<'c> {
+  conn: &'c mut Connection,
+  stmt: Statement,
+  done: bool,
+}
+
+impl<'c> Rows<'c> {
+  pub(crate) fn new(conn: &'c mut Connection, stmt: Statement) -> Self {
+    Self {
+      conn,
+      stmt,
+      done: false,
+    }
+  }
+
+  pub fn column_names(&self) -> &[String] {
+    self.stmt.column_names()
+  }
+}
+
+impl Iterator for Rows<'_> {
+  type Item = Result<Row, DbError>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    if self.done {
+      return None;
+    }
+    let row = self.stmt.step(self.conn).transpose();
+    self.done = !matches!(row, Some(Ok(_)));
+    row.map(|res| {
+      res.map(|values| Row {
+        columns: self.stmt.columns(),
+        values,
+      })
+    })
+  }
+}
+
+/// A result row: its values, with the names of the columns they are in.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Row {
+  columns: Rc<[String]>,
+  values: Vec<Value>,
+}
+
+impl Row {
+  pub fn column_names(&self) -> &[String] {
+    &self.columns
+  }
+
+  pub fn values(&self) -> &[Value] {
+    &self.values
+  }
+
+  pub fn into_values(self) -> Vec<Value> {
+    self.values
+  }
+
+  /// The value of column `idx`, counted from 0.
+  pub fn get(&self, idx: usize) -> Option<&Value> {
+    self.values.get(idx)
+  }
+
+  /// The value of the first column named `name`, ignoring case.
+  pub fn get_by_name(&self, name: &str) -> Option<&Value> {
+    let idx = self
+      .columns
+      .iter()
+      .position(|col| col.eq_ignore_ascii_case(name))?;
+    self.values.get(idx)
+  }
+
+  /// Read the row as a `T`: a struct or map takes the values by column name, a tuple or
+  /// sequence takes them in order, and anything else takes the value of a single column.
+  pub fn deserialize<T: DeserializeOwned>(&self) -> Result<T, DbError> {
+    Ok(T::deserialize(RowDeserializer { row: self })?)
+  }
+}
@@ -1,15 +1,272 @@
+use crate::btree::node::Node;
+use crate::error::ExecErr;
+use crate::row::Row;
+use crate::table::{Table, ROOT};
+use crate::value::Value;
+
+/// A position in the B-tree of a table: a cell of a leaf, with the intern nodes and the index
+/// of the child taken in each on the way down from the root, to step into the neighbouring
+/// leaf in either direction. A cursor that steps off either end of the table, or seeks a key
+/// beyond those it holds, is invalid until it is positioned again.
+///
+/// A cursor borrows nothing: every method takes the table. Inserting moves cells between
+/// pages, so a cursor that finds the table changed since it was positioned seeks its key
+/// again before it moves on, and one that inserts through `insert` lands on the new row.
+#[derive(Debug, Clone, Default)]
 pub struct Cursor {
-  pub leaf_idx: usize,
-  pub cell_idx: usize,
-  pub at_end: bool,
+  path: Vec<(usize, usize)>, // intern page and index of the child taken, from the root down
+  leaf: usize,
+  cell: usize,
+  key: Option<u32>, // of the cell under the cursor, none when invalid
+  version: u64,     // of the table when the cursor was positioned
+}
+
+/// Which child or cell to take at each node on the way down.
+#[derive(Clone, Copy)]
+enum Toward {
+  First,
+  Last, // one past the last cell of the leaf
+  Key(u32),
+}
+
+enum Step {
+  Child(usize, usize), // index and page of the child taken
+  Cell(usize),
 }
 
 impl Cursor {
-  pub fn new(leaf_idx: usize, cell_idx: usize, at_end: bool) -> Self {
-    Self {
-      leaf_idx,
-      cell_idx,
-      at_end,
+  /// An invalid cursor, to be positioned by a seek.
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn is_valid(&self) -> bool {
+    self.key.is_some()
+  }
+
+  /// The key of the row under the cursor, none when it is invalid.
+  pub fn key(&self) -> Option<u32> {
+    self.key
+  }
+
+  /// The row under the cursor.
+  pub fn value(&mut self, table: &Table) -> Result<Row, ExecErr> {
+    let Some(key) = self.key else {
+      return Err(ExecErr::Misuse("Cursor is not on a row.".to_string()));
+    };
+    if self.version != table.version() {
+      self.seek_ge(table, key)?;
+    }
+    let (leaf, cell) = (self.leaf, self.cell);
+    let row = table.node_do(leaf, |nd| {
+      let leaf = nd.as_leaf()?;
+      leaf
+        .cells
+        .get(cell)
+        .map(|c| c.row)
+        .ok_or_else(|| ExecErr::CellNotFound(format!("Cell {cell} not found.")))
+    })??;
+    Ok(Row::deserialize_from(row))
+  }
+
+  /// Move to the row with the smallest key.
+  pub fn first(&mut self, table: &Table) -> Result<(), ExecErr> {
+    self.descend(table, ROOT, Toward::First)?;
+    self.settle_forward(table)
+  }
+
+  /// Move to the row with the largest key.
+  pub fn last(&mut self, table: &Table) -> Result<(), ExecErr> {
+    self.descend(table, ROOT, Toward::Last)?;
+    self.settle_backward(table)
+  }
+
+  /// Move to the row with the smallest key not below `key`.
+  pub fn seek_ge(&mut self, table: &Table, key: u32) -> Result<(), ExecErr> {
+    self.descend(table, ROOT, Toward::Key(key))?;
+    self.settle_forward(table)
+  }
+
+  /// Move to the row with the largest key not above `key`.
+  pub fn seek_le(&mut self, table: &Table, key: u32) -> Result<(), ExecErr> {
+    self.descend(table, ROOT, Toward::Key(key))?;
+    if self.cell_key(table)? == Some(key) {
+      self.cell += 1;
+    }
+    self.settle_backward(table)
+  }
+
+  /// Move to the first row, reading forwards, whose key is above `bound`, or equal to it
+  /// unless `strict`. A bound that is not a number leaves which rows pass to whoever checks
+  /// them, so the cursor moves to the first row.
+  pub fn seek_above(&mut self, table: &Table, bound: &Value, strict: bool) -> Result<(), ExecErr> {
+    let start = match *bound {
+      Value::Integer(n) if strict => Some(n.saturating_add(1)),
+      Value::Integer(n) => Some(n),
+      Value::Real(f) if strict => Some((f.floor() as i64).saturating_add(1)),
+      Value::Real(f) => Some(f.ceil() as i64),
+      _ => None,
+    };
+    match start.map(|k| u32::try_from(k.max(0))) {
+      Some(Err(_)) => *self = Self::new(), // beyond the largest possible key
+      Some(Ok(k)) => self.seek_ge(table, k)?,
+      None => self.first(table)?,
+    }
+    Ok(())
+  }
+
+  /// Like `seek_above`, reading backwards: move to the first row whose key is below `bound`,
+  /// or equal to it unless `strict`.
+  pub fn seek_below(&mut self, table: &Table, bound: &Value, strict: bool) -> Result<(), ExecErr> {
+    let start = match *bound {
+      Value::Integer(n) if strict => Some(n.saturating_sub(1)),
+      Value::Integer(n) => Some(n),
+      Value::Real(f) if strict => Some((f.ceil() as i64).saturating_sub(1)),
+      Value::Real(f) => Some(f.floor() as i64),
+      _ => None,
+    };
+    match start.map(|k| u32::try_from(k.min(u32::MAX as i64))) {
+      Some(Err(_)) => *self = Self::new(), // below the smallest possible key
+      Some(Ok(k)) => self.seek_le(table, k)?,
+      None => self.last(table)?,
     }
+    Ok(())
+  }
+
+  /// Move to the row with the next larger key. An invalid cursor stays so.
+  pub fn next(&mut self, table: &Table) -> Result<(), ExecErr> {
+    let Some(key) = self.key else { return Ok(()) };
+    if self.version != table.version() {
+      self.seek_ge(table, key)?;
+      if self.key != Some(key) {
+        return Ok(()); // the row is gone, and the cursor already on the next one
+      }
+    }
+    self.cell += 1;
+    self.settle_forward(table)
+  }
+
+  /// Move to the row with the next smaller key. An invalid cursor stays so.
+  pub fn prev(&mut self, table: &Table) -> Result<(), ExecErr> {
+    let Some(key) = self.key else { return Ok(()) };
+    if self.version != table.version() {
+      self.seek_le(table, key)?;
+      if self.key != Some(key) {
+        return Ok(());
+      }
+    }
+    self.settle_backward(table)
+  }
+
+  /// Insert `row` into `table` and move onto it.
+  pub fn insert(&mut self, table: &mut Table, row: &Row) -> Result<(), ExecErr> {
+    table.insert_row(row.key, row)?;
+    self.seek_ge(table, row.key)
+  }
+
+  /// Go down from page `pid` to a leaf, replacing the path below it.
+  fn descend(&mut self, table: &Table, mut pid: usize, toward: Toward) -> Result<(), ExecErr> {
+    if pid == ROOT {
+      self.path.clear();
+    }
+    loop {
+      let step = table.node_do(pid, |nd| match nd {
+        Node::Intern(nd) => {
+          let idx = match toward {
+            Toward::First => 0,
+            Toward::Last => nd.child_count() - 1,
+            Toward::Key(key) => nd.search_child_by_key(key),
+          };
+          Step::Child(idx, nd.child(idx))
+        }
+        Node::Leaf(nd) => Step::Cell(match toward {
+          Toward::First => 0,
+          Toward::Last => nd.size(),
+          Toward::Key(key) => nd.search_cell_idx_by_key(key),
+        }),
+      })?;
+      match step {
+        Step::Child(_, _) if self.path.len() > table.page_count() => {
+          return Err(table.too_deep(pid));
+        }
+        Step::Child(idx, child) => {
+          self.path.push((pid, idx));
+          pid = child;
+        }
+        Step::Cell(cell) => {
+          self.leaf = pid;
+          self.cell = cell;
+          return Ok(());
+        }
+      }
+    }
+  }
+
+  /// Settle on the cell at `self.cell`, or on the first of a later leaf past the end of this
+  /// one.
+  fn settle_forward(&mut self, table: &Table) -> Result<(), ExecErr> {
+    self.version = table.version();
+    loop {
+      self.key = self.cell_key(table)?;
+      if self.key.is_some() || !self.next_leaf(table)? {
+        return Ok(());
+      }
+    }
+  }
+
+  /// Settle on the cell before `self.cell`, or on the last of an earlier leaf before the
+  /// start of this one.
+  fn settle_backward(&mut self, table: &Table) -> Result<(), ExecErr> {
+    self.version = table.version();
+    loop {
+      if self.cell > 0 {
+        self.cell -= 1;
+        self.key = self.cell_key(table)?;
+        return Ok(());
+      }
+      if !self.prev_leaf(table)? {
+        self.key = None;
+        return Ok(());
+      }
+    }
+  }
+
+  /// Climb to the nearest intern node with a child after the one taken, and go down to the
+  /// first cell under it. False at the last leaf.
+  fn next_leaf(&mut self, table: &Table) -> Result<bool, ExecErr> {
+    while let Some((pid, idx)) = self.path.pop() {
+      let child = table.node_do(pid, |nd| {
+        let nd = nd.as_intern()?;
+        Ok::<_, ExecErr>((idx + 1 < nd.child_count()).then(|| nd.child(idx + 1)))
+      })??;
+      if let Some(child) = child {
+        self.path.push((pid, idx + 1));
+        self.descend(table, child, Toward::First)?;
+        return Ok(true);
+      }
+    }
+    Ok(false)
+  }
+
+  /// Climb to the nearest intern node with a child before the one taken, and go down to one
+  /// past the last cell under it. False at the first leaf.
+  fn prev_leaf(&mut self, table: &Table) -> Result<bool, ExecErr> {
+    while let Some((pid, idx)) = self.path.pop() {
+      if idx > 0 {
+        let child = table.node_do(pid, |nd| Ok::<_, ExecErr>(nd.as_intern()?.child(idx - 1)))??;
+        self.path.push((pid, idx - 1));
+        self.descend(table, child, Toward::Last)?;
+        return Ok(true);
+      }
+    }
+    Ok(false)
+  }
+
+  /// The key of cell `self.cell` of the leaf, none past its end.
+  fn cell_key(&self, table: &Table) -> Result<Option<u32>, ExecErr> {
+    let cell = self.cell;
+    table.node_do(self.leaf, |nd| {
+      Ok(nd.as_leaf()?.cells.get(cell).map(|c| c.key))
+    })?
   }
 }
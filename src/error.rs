@@ -1,14 +1,51 @@
 use std::fmt::{Display, Formatter};
+use std::io;
+
+// Result codes, numbered as SQLite numbers its primary result codes, so callers can match on
+// the kind of failure without parsing messages. See `DbError::code`.
+
+/// A generic error, such as a syntax error or an unknown table or column.
+pub const SQLITE_ERROR: i32 = 1;
+/// A bug: the engine reached a state it should not.
+pub const SQLITE_INTERNAL: i32 = 2;
+/// The operating system failed to read or write the database file.
+pub const SQLITE_IOERR: i32 = 10;
+/// The database file is malformed.
+pub const SQLITE_CORRUPT: i32 = 11;
+/// The database has no room left: the pager or a node is full.
+pub const SQLITE_FULL: i32 = 13;
+/// The database file could not be opened.
+pub const SQLITE_CANTOPEN: i32 = 14;
+/// A string is longer than its column allows.
+pub const SQLITE_TOOBIG: i32 = 18;
+/// A row breaks a constraint, such as a duplicate or negative id.
+pub const SQLITE_CONSTRAINT: i32 = 19;
+/// A value has the wrong type for where it goes.
+pub const SQLITE_MISMATCH: i32 = 20;
+/// The API was called out of order, such as binding to a running statement.
+pub const SQLITE_MISUSE: i32 = 21;
+/// A parameter number is out of range.
+pub const SQLITE_RANGE: i32 = 25;
+/// The file is not a database, or its header is damaged.
+pub const SQLITE_NOTADB: i32 = 26;
 
 #[derive(Debug)]
 pub enum MetaCmdErr {
   Unrecognized(String),
+  /// A bad argument to a command, or a file it could not read.
+  InvalidArgs(String),
+}
+
+impl MetaCmdErr {
+  pub fn code(&self) -> i32 {
+    SQLITE_ERROR
+  }
 }
 
 impl Display for MetaCmdErr {
   fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
     match self {
-      Self::Unrecognized(s) => write!(f, "{s}"),
+      Self::Unrecognized(s) | Self::InvalidArgs(s) => write!(f, "{s}"),
     }
   }
 }
@@ -23,6 +60,16 @@ pub enum PrepareErr {
   NegativeId(String),
 }
 
+impl PrepareErr {
+  pub fn code(&self) -> i32 {
+    match self {
+      Self::Unrecognized(_) | Self::SyntaxErr(_) => SQLITE_ERROR,
+      Self::StringTooLong(_) => SQLITE_TOOBIG,
+      Self::NegativeId(_) => SQLITE_CONSTRAINT,
+    }
+  }
+}
+
 impl Display for PrepareErr {
   fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
     match self {
@@ -37,41 +84,145 @@ impl std::error::Error for PrepareErr {}
 
 #[derive(Debug)]
 pub enum ExecErr {
-  TableFull(String),
   DuplicateKey(String),
   LeafNodeFull(String),
   InternNodeFull(String),
   PagerFull(String),
-  PagerFull2,
-  PageNumOutBound(String),
-  IoError(String),
+  /// What was being done, and the error the OS gave for it.
+  IoError(String, io::Error),
+  CantOpen(io::Error),
+  /// The file header is not one this library writes.
+  NotADatabase(String),
+  /// A page of the file fails a check made when it is read.
+  Corrupt {
+    page: usize,
+    reason: String,
+  },
   NodeError(String),
   CellNotFound(String),
-  PageUnload,
+  NoSuchTable(String),
+  NoSuchColumn(String),
+  ExprError(String),
+  /// A bound id below 0; a literal one fails to prepare as `PrepareErr::NegativeId`.
+  NegativeId(String),
+  /// Bound text longer than its column; a literal one fails as `PrepareErr::StringTooLong`.
+  StringTooLong(String),
+  /// A bound id that is not an integer.
+  DatatypeMismatch(String),
+  BindError(String),
+  Misuse(String),
+  MappingError(String),
+}
+
+impl ExecErr {
+  pub fn code(&self) -> i32 {
+    match self {
+      Self::LeafNodeFull(_) | Self::InternNodeFull(_) | Self::PagerFull(_) => SQLITE_FULL,
+      Self::DuplicateKey(_) | Self::NegativeId(_) => SQLITE_CONSTRAINT,
+      Self::StringTooLong(_) => SQLITE_TOOBIG,
+      Self::IoError(..) => SQLITE_IOERR,
+      Self::CantOpen(_) => SQLITE_CANTOPEN,
+      Self::NotADatabase(_) => SQLITE_NOTADB,
+      Self::Corrupt { .. } | Self::NodeError(_) | Self::CellNotFound(_) => SQLITE_CORRUPT,
+      Self::NoSuchTable(_) | Self::NoSuchColumn(_) | Self::ExprError(_) => SQLITE_ERROR,
+      Self::BindError(_) => SQLITE_RANGE,
+      Self::Misuse(_) => SQLITE_MISUSE,
+      Self::MappingError(_) | Self::DatatypeMismatch(_) => SQLITE_MISMATCH,
+    }
+  }
 }
 
 impl Display for ExecErr {
   fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
     match self {
-      Self::TableFull(s)
-      | Self::DuplicateKey(s)
+      Self::DuplicateKey(s)
       | Self::PagerFull(s)
       | Self::LeafNodeFull(s)
       | Self::InternNodeFull(s)
-      | Self::PageNumOutBound(s)
-      | Self::IoError(s)
+      | Self::IoError(s, _)
+      | Self::NotADatabase(s)
       | Self::NodeError(s)
-      | Self::CellNotFound(s) => write!(f, "{s}"),
-      _ => write!(f, ""),
+      | Self::CellNotFound(s)
+      | Self::NoSuchTable(s)
+      | Self::NoSuchColumn(s)
+      | Self::ExprError(s)
+      | Self::NegativeId(s)
+      | Self::StringTooLong(s)
+      | Self::DatatypeMismatch(s)
+      | Self::BindError(s)
+      | Self::Misuse(s)
+      | Self::MappingError(s) => write!(f, "{s}"),
+      Self::CantOpen(_) => write!(f, "Unable to open file."),
+      Self::Corrupt { page, reason } => {
+        write!(f, "Database file is corrupt: page {page}: {reason}.")
+      }
     }
   }
 }
 
-impl std::error::Error for ExecErr {}
+impl std::error::Error for ExecErr {
+  fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+    match self {
+      Self::IoError(_, e) | Self::CantOpen(e) => Some(e),
+      _ => None,
+    }
+  }
+}
 
+/// Any error the database reports. It displays as the error it wraps, and its `source` is that
+/// error's source, such as the `io::Error` behind a failed read.
 #[derive(Debug)]
 pub enum DbError {
   MetaCmdErr(MetaCmdErr),
   PrepareErr(PrepareErr),
   ExecErr(ExecErr),
 }
+
+impl DbError {
+  /// The result code, one of the `SQLITE_*` constants.
+  pub fn code(&self) -> i32 {
+    match self {
+      Self::MetaCmdErr(e) => e.code(),
+      Self::PrepareErr(e) => e.code(),
+      Self::ExecErr(e) => e.code(),
+    }
+  }
+}
+
+impl Display for DbError {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    match self {
+      Self::MetaCmdErr(e) => write!(f, "{e}"),
+      Self::PrepareErr(e) => write!(f, "{e}"),
+      Self::ExecErr(e) => write!(f, "{e}"),
+    }
+  }
+}
+
+impl std::error::Error for DbError {
+  fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+    match self {
+      Self::MetaCmdErr(e) => e.source(),
+      Self::PrepareErr(e) => e.source(),
+      Self::ExecErr(e) => e.source(),
+    }
+  }
+}
+
+impl From<MetaCmdErr> for DbError {
+  fn from(e: MetaCmdErr) -> Self {
+    Self::MetaCmdErr(e)
+  }
+}
+
+impl From<PrepareErr> for DbError {
+  fn from(e: PrepareErr) -> Self {
+    Self::PrepareErr(e)
+  }
+}
+
+impl From<ExecErr> for DbError {
+  fn from(e: ExecErr) -> Self {
+    Self::ExecErr(e)
+  }
+}
@@ -0,0 +1,368 @@
+mod aggregate;
+mod compound;
+mod cte;
+mod eval;
+mod plan;
+mod sort;
+mod source;
+mod subquery;
+mod window;
+
+use crate::error::ExecErr;
+use crate::sql::ast::{Expr, OrderingTerm, ResultColumn, Select};
+use crate::table::Table;
+use crate::value::Value;
+use crate::vdbe::{planner, Rows};
+use aggregate::Grouper;
+use eval::{eval, Env};
+use sort::Sorter;
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+pub use cte::Recursion;
+pub use eval::{binary, bool_value, resolve_column, Column};
+pub use plan::query_plan;
+pub use source::Source;
+
+/// State shared by a statement and all of its subqueries.
+pub struct Ctx<'t> {
+  pub table: &'t Table,
+  pub params: &'t [Value], // bound to the statement's parameters, from number 1
+  subqueries: &'t Subqueries,
+}
+
+/// The rows of the uncorrelated subqueries a statement ran, kept to be reused until it runs
+/// again.
+pub type Subqueries = RefCell<HashMap<*const Select, subquery::Cached>>;
+
+/// Where a query runs: the statement context, the row of the enclosing query when it is a
+/// correlated subquery, and the CTEs it can read from.
+#[derive(Clone, Copy)]
+pub struct Scope<'a> {
+  pub ctx: &'a Ctx<'a>,
+  pub outer: Option<&'a Env<'a>>,
+  pub ctes: Option<&'a cte::Frame<'a>>,
+}
+
+/// Call `f` with the scope the top level of `select` runs in, which includes its own CTEs.
+pub fn in_scope<R>(
+  select: &Select,
+  table: &Table,
+  params: &[Value],
+  subqueries: &Subqueries,
+  f: impl FnOnce(Scope) -> R,
+) -> R {
+  let ctx = Ctx {
+    table,
+    params,
+    subqueries,
+  };
+  let frame = cte::frame_for(select, None);
+  f(Scope {
+    ctx: &ctx,
+    outer: None,
+    ctes: frame.as_ref(),
+  })
+}
+
+/// Run a SELECT in the statement context `ctx`, handing each result row to `emit`.
+pub fn execute_select<F>(select: &Select, ctx: &Ctx, mut emit: F) -> Result<(), ExecErr>
+where
+  F: FnMut(Vec<Value>),
+{
+  let scope = Scope {
+    ctx,
+    outer: None,
+    ctes: None,
+  };
+  run_select(select, scope, &mut |row| {
+    emit(row);
+    true
+  })
+}
+
+/// Names of the columns `select` produces: the alias of a result column, else the name of the
+/// column it is, else `columnN`.
+pub fn column_names(select: &Select, table: &Table) -> Result<Vec<String>, ExecErr> {
+  let subqueries = Subqueries::default();
+  let ctx = Ctx {
+    table,
+    params: &[],
+    subqueries: &subqueries,
+  };
+  let scope = Scope {
+    ctx: &ctx,
+    outer: None,
+    ctes: None,
+  };
+  source::result_names(select, scope)
+}
+
+/// The value of `expr` in `scope` for the joined `row` of `columns`.
+pub fn eval_row(
+  expr: &Expr,
+  scope: Scope,
+  columns: &[Column],
+  row: &[Value],
+) -> Result<Value, ExecErr> {
+  eval(expr, &Env::new(scope, columns, row))
+}
+
+/// Compute the rows a cursor of a compiled query reads, other than a streamed recursive CTE.
+pub fn materialize(rows: &Rows, scope: Scope) -> Result<Vec<Vec<Value>>, ExecErr> {
+  match rows {
+    Rows::Cte { name, columns } => match cte::lookup(scope.ctes, name) {
+      Some(cte::Found::Cte {
+        cte,
+        frame,
+        recursive,
+      }) => cte::materialize(cte, &frame, scope, columns, recursive),
+      Some(cte::Found::WorkTable { rows, .. }) => Ok(rows.to_vec()),
+      None => Err(ExecErr::NoSuchTable(format!("No such table: {name}."))),
+    },
+    Rows::Recursive { .. } => unreachable!("a recursive CTE read by the outermost loop streams"),
+    Rows::Subquery(select) => collect(select, scope),
+    Rows::Stat => Ok(
+      scope
+        .ctx
+        .table
+        .stats()
+        .map_or(vec![], |stats| stats.to_rows()),
+    ),
+  }
+}
+
+/// Run a SELECT in `scope`; `emit` returns false once it wants no more rows.
+fn run_select(
+  select: &Select,
+  scope: Scope,
+  emit: &mut dyn FnMut(Vec<Value>) -> bool,
+) -> Result<(), ExecErr> {
+  let frame = cte::frame_for(select, scope.ctes);
+  let scope = Scope {
+    ctes: frame.as_ref().or(scope.ctes),
+    ..scope
+  };
+  let paging = Paging {
+    order_by: &select.order_by,
+    limit: select.limit,
+    offset: select.offset,
+  };
+  if select.compound.is_empty() {
+    return run_query(select, scope, paging, emit);
+  }
+
+  let mut rows = compound::rows(select, &select.compound, scope)?;
+  if !paging.order_by.is_empty() {
+    let names: Vec<_> = source::result_names(select, scope)?
+      .into_iter()
+      .map(Some)
+      .collect();
+    Sorter::new(paging.order_by, &names, true)?.sort(&mut rows);
+  }
+  paging.emit(rows, emit);
+  Ok(())
+}
+
+/// The ORDER BY, LIMIT and OFFSET a query runs with. They belong to the statement, so the
+/// cores of a compound select run without them.
+#[derive(Clone, Copy, Default)]
+struct Paging<'s> {
+  order_by: &'s [OrderingTerm],
+  limit: Option<usize>,
+  offset: usize,
+}
+
+impl Paging<'_> {
+  fn emit(&self, rows: Vec<Vec<Value>>, emit: &mut dyn FnMut(Vec<Value>) -> bool) {
+    let rows = rows.into_iter().skip(self.offset);
+    for row in rows.take(self.limit.unwrap_or(usize::MAX)) {
+      if !emit(row) {
+        break;
+      }
+    }
+  }
+}
+
+/// Run a single select core in `scope`, whose CTEs it already includes.
+fn run_query(
+  select: &Select,
+  scope: Scope,
+  paging: Paging,
+  emit: &mut dyn FnMut(Vec<Value>) -> bool,
+) -> Result<(), ExecErr> {
+  let table = scope.ctx.table;
+  let source = Source::plan(select, scope)?;
+  let columns = &source.columns;
+  let mut remaining = paging.limit.unwrap_or(usize::MAX);
+  if remaining == 0 {
+    return Ok(());
+  }
+  // rows already in the order asked for need no sorting
+  let order_by = match streams(select, &source) {
+    true => &[][..],
+    false => paging.order_by,
+  };
+  let sorter = Sorter::new(order_by, &output_names(select, columns), false)?;
+  let hidden = sorter.hidden();
+  let windows = window::calls(select, &hidden);
+
+  if select.is_aggregate() || hidden.iter().any(|e| e.contains_aggregate()) {
+    if source.is_single_table() && sorter.is_empty() && windows.is_empty() {
+      if let Some(row) = aggregate::try_from_btree(select, table)? {
+        if paging.offset == 0 {
+          emit(row);
+        }
+        return Ok(());
+      }
+    }
+    let mut grouper = Grouper::new(select, columns, scope, &hidden);
+    source.scan(scope, 0, |row| {
+      grouper.step(row)?;
+      Ok(true)
+    })?;
+    let groups = grouper.finish()?;
+    let aggregates = groups.aggregates();
+    let envs: Vec<_> = groups
+      .rows()
+      .zip(&aggregates)
+      .map(|(row, aggregates)| Env {
+        aggregates,
+        ..Env::new(scope, columns, row)
+      })
+      .collect();
+    let mut rows = project_all(select, &envs, &hidden, &windows)?;
+    sorter.sort(&mut rows);
+    paging.emit(rows, emit);
+    return Ok(());
+  }
+
+  if !sorter.is_empty() || !windows.is_empty() {
+    let mut inputs = vec![];
+    source.scan(scope, 0, |row| {
+      inputs.push(row);
+      Ok(true)
+    })?;
+    let envs: Vec<_> = inputs
+      .iter()
+      .map(|row| Env::new(scope, columns, row))
+      .collect();
+    let mut rows = project_all(select, &envs, &hidden, &windows)?;
+    sorter.sort(&mut rows);
+    paging.emit(rows, emit);
+    return Ok(());
+  }
+
+  // the rows OFFSET skips are stepped over without reading their columns
+  source.scan(scope, paging.offset, |row| {
+    let more = emit(project(&select.columns, &Env::new(scope, columns, &row))?);
+    remaining -= 1;
+    Ok(more && remaining > 0)
+  })
+}
+
+/// Whether the core `select` yields its rows as its loops produce them: it neither groups nor
+/// computes window functions, and an ORDER BY it has is on the key of the single users table
+/// it reads, which the loop reads in that order.
+pub fn streams(select: &Select, source: &Source) -> bool {
+  let sorted = select.order_by.is_empty()
+    || (select.compound.is_empty()
+      && planner::key_order(select).is_some()
+      && source.is_single_table());
+  sorted
+    && !select.is_aggregate()
+    && select
+      .exprs()
+      .iter()
+      .all(|e| !e.contains_aggregate() && !e.contains_window())
+}
+
+/// Project the buffered rows of a query: the select list, followed by the hidden ORDER BY
+/// columns. Window functions are computed first, over all of the rows.
+fn project_all(
+  select: &Select,
+  envs: &[Env],
+  hidden: &[&Expr],
+  windows: &[&Expr],
+) -> Result<Vec<Vec<Value>>, ExecErr> {
+  let values = window::compute(windows, envs)?;
+  let mut rows = vec![];
+  for (env, windows) in envs.iter().zip(values) {
+    let env = Env {
+      windows: &windows,
+      ..*env
+    };
+    let mut row = project(&select.columns, &env)?;
+    for expr in hidden {
+      row.push(eval(expr, &env)?);
+    }
+    rows.push(row);
+  }
+  Ok(rows)
+}
+
+/// For every column `project` produces, the alias ORDER BY can refer to it by.
+fn output_names(select: &Select, columns: &[Column]) -> Vec<Option<String>> {
+  if select.columns.is_empty() {
+    return vec![None; columns.len()];
+  }
+  let mut names = vec![];
+  for col in &select.columns {
+    match col {
+      ResultColumn::Star => names.extend(columns.iter().map(|_| None)),
+      ResultColumn::TableStar(table) => names.extend(
+        columns
+          .iter()
+          .filter(|col| {
+            col
+              .table
+              .as_deref()
+              .is_some_and(|t| t.eq_ignore_ascii_case(table))
+          })
+          .map(|_| None),
+      ),
+      ResultColumn::Expr { alias, .. } => names.push(alias.clone()),
+    }
+  }
+  names
+}
+
+/// Run a SELECT in `scope` and keep all of its rows.
+fn collect(select: &Select, scope: Scope) -> Result<Vec<Vec<Value>>, ExecErr> {
+  let mut rows = vec![];
+  run_select(select, scope, &mut |row| {
+    rows.push(row);
+    true
+  })?;
+  Ok(rows)
+}
+
+fn project(result_columns: &[ResultColumn], env: &Env) -> Result<Vec<Value>, ExecErr> {
+  if result_columns.is_empty() {
+    return Ok(env.row.to_vec());
+  }
+  let mut out = vec![];
+  for col in result_columns {
+    match col {
+      ResultColumn::Star => out.extend_from_slice(env.row),
+      ResultColumn::TableStar(table) => {
+        let mut found = false;
+        for (col, val) in env.columns.iter().zip(env.row) {
+          if col
+            .table
+            .as_deref()
+            .is_some_and(|t| t.eq_ignore_ascii_case(table))
+          {
+            out.push(val.clone());
+            found = true;
+          }
+        }
+        if !found {
+          return Err(ExecErr::NoSuchTable(format!("No such table: {table}.")));
+        }
+      }
+      ResultColumn::Expr { expr, .. } => out.push(eval(expr, env)?),
+    }
+  }
+  Ok(out)
+}
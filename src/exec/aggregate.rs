@@ -0,0 +1,345 @@
+use super::eval::{eval, resolve_column, Column, Env};
+use super::Scope;
+use crate::error::ExecErr;
+use crate::sql::ast::{AggFunc, Expr, ResultColumn, Select};
+use crate::table::Table;
+use crate::value::{GroupKey, Value};
+use std::collections::HashMap;
+
+/// Hash-based GROUP BY: input rows are folded into per-group accumulators in one pass,
+/// groups come out in the order they were first seen.
+pub struct Grouper<'s> {
+  columns: &'s [Column],
+  scope: Scope<'s>,
+  group_by: Vec<Expr>,
+  having: Option<Expr>,
+  calls: Vec<Expr>, // distinct aggregate calls in the select list, HAVING and ORDER BY
+  index: HashMap<Vec<GroupKey>, usize>,
+  groups: Vec<Group>,
+}
+
+struct Group {
+  row: Vec<Value>, // last row of the group, for bare columns
+  accs: Vec<Accumulator>,
+}
+
+pub enum Accumulator {
+  CountStar(i64),
+  Count(i64),
+  Sum(Value),
+  Avg { sum: f64, n: i64 },
+  Min(Value),
+  Max(Value),
+}
+
+impl<'s> Grouper<'s> {
+  pub fn new(
+    select: &'s Select,
+    columns: &'s [Column],
+    scope: Scope<'s>,
+    hidden: &[&Expr],
+  ) -> Self {
+    let group_by: Vec<_> = select
+      .group_by
+      .iter()
+      .map(|e| resolve_aliases(e, select, columns))
+      .collect();
+    let having = select
+      .having
+      .as_ref()
+      .map(|e| resolve_aliases(e, select, columns));
+
+    let mut calls = vec![];
+    for col in &select.columns {
+      if let ResultColumn::Expr { expr, .. } = col {
+        collect_calls(expr, &mut calls);
+      }
+    }
+    if let Some(having) = &having {
+      collect_calls(having, &mut calls);
+    }
+    for expr in hidden {
+      collect_calls(expr, &mut calls);
+    }
+    Self {
+      columns,
+      scope,
+      group_by,
+      having,
+      calls,
+      index: HashMap::new(),
+      groups: vec![],
+    }
+  }
+
+  pub fn step(&mut self, row: Vec<Value>) -> Result<(), ExecErr> {
+    let env = Env::new(self.scope, self.columns, &row);
+    let key = self
+      .group_by
+      .iter()
+      .map(|e| eval(e, &env).map(|v| GroupKey::from(&v)))
+      .collect::<Result<Vec<_>, _>>()?;
+
+    let args = self
+      .calls
+      .iter()
+      .map(|call| match call {
+        Expr::Aggregate { arg: Some(arg), .. } => eval(arg, &env),
+        _ => Ok(Value::Null),
+      })
+      .collect::<Result<Vec<_>, _>>()?;
+
+    let gid = match self.index.get(&key) {
+      Some(&gid) => gid,
+      None => {
+        self.groups.push(self.new_group(vec![]));
+        self.index.insert(key, self.groups.len() - 1);
+        self.groups.len() - 1
+      }
+    };
+    let group = &mut self.groups[gid];
+    for (acc, arg) in group.accs.iter_mut().zip(args) {
+      acc.step(arg)?;
+    }
+    group.row = row;
+    Ok(())
+  }
+
+  /// Finalize every group and keep those HAVING accepts.
+  pub fn finish(mut self) -> Result<Groups, ExecErr> {
+    // an aggregate query without GROUP BY yields exactly one row, even for empty input
+    if self.groups.is_empty() && self.group_by.is_empty() {
+      let nulls = vec![Value::Null; self.columns.len()];
+      self.groups.push(self.new_group(nulls));
+    }
+
+    let mut groups = vec![];
+    for group in self.groups {
+      let values: Vec<_> = group.accs.iter().map(Accumulator::finish).collect();
+      if let Some(having) = &self.having {
+        let aggregates: Vec<_> = self.calls.iter().zip(values.iter().cloned()).collect();
+        let env = Env {
+          aggregates: &aggregates,
+          ..Env::new(self.scope, self.columns, &group.row)
+        };
+        if eval(having, &env)?.truthy() != Some(true) {
+          continue;
+        }
+      }
+      groups.push((group.row, values));
+    }
+    Ok(Groups {
+      calls: self.calls,
+      groups,
+    })
+  }
+
+  fn new_group(&self, row: Vec<Value>) -> Group {
+    let accs = self
+      .calls
+      .iter()
+      .map(|call| match call {
+        Expr::Aggregate { func, arg } => Accumulator::new(*func, arg.is_none()),
+        _ => unreachable!(),
+      })
+      .collect();
+    Group { row, accs }
+  }
+}
+
+/// The groups of an aggregate query that passed HAVING: the last input row of each, and the
+/// values of the aggregate calls.
+pub struct Groups {
+  calls: Vec<Expr>,
+  groups: Vec<(Vec<Value>, Vec<Value>)>,
+}
+
+impl Groups {
+  pub fn rows(&self) -> impl Iterator<Item = &[Value]> {
+    self.groups.iter().map(|(row, _)| &row[..])
+  }
+
+  /// The aggregate values of every group, paired with their calls as `Env::aggregates` wants.
+  pub fn aggregates(&self) -> Vec<Vec<(&Expr, Value)>> {
+    self
+      .groups
+      .iter()
+      .map(|(_, values)| self.calls.iter().zip(values.iter().cloned()).collect())
+      .collect()
+  }
+}
+
+/// Answer `COUNT(*)`, `MIN(id)` and `MAX(id)` over the whole table from the B-tree structure
+/// alone: cell counts of the leaves and the two outermost keys. Returns `None` when the query
+/// has any other shape.
+pub fn try_from_btree(select: &Select, table: &Table) -> Result<Option<Vec<Value>>, ExecErr> {
+  if select.where_clause.is_some() || !select.group_by.is_empty() || select.having.is_some() {
+    return Ok(None);
+  }
+  let mut calls = vec![];
+  for col in &select.columns {
+    match col {
+      ResultColumn::Expr {
+        expr: Expr::Aggregate { func, arg },
+        ..
+      } => {
+        let on_key = match arg.as_deref() {
+          None => true,
+          Some(Expr::Column { name, .. }) => name.eq_ignore_ascii_case("id"),
+          Some(_) => false,
+        };
+        match func {
+          AggFunc::Count | AggFunc::Min | AggFunc::Max if on_key => calls.push(*func),
+          _ => return Ok(None),
+        }
+      }
+      _ => return Ok(None),
+    }
+  }
+
+  let key_value = |key: Option<u32>| key.map_or(Value::Null, |k| Value::Integer(k as i64));
+  let mut row = vec![];
+  for func in calls {
+    row.push(match func {
+      AggFunc::Count => Value::Integer(table.count_rows()? as i64),
+      AggFunc::Min => key_value(table.first_key()?),
+      AggFunc::Max => key_value(table.last_key()?),
+      _ => unreachable!(),
+    });
+  }
+  Ok(Some(row))
+}
+
+/// GROUP BY and HAVING may name a result column by its alias, unless a table column of that
+/// name exists.
+fn resolve_aliases(expr: &Expr, select: &Select, columns: &[Column]) -> Expr {
+  match expr {
+    Expr::Column { table: None, name } if resolve_column(columns, None, name).is_err() => select
+      .columns
+      .iter()
+      .find_map(|col| match col {
+        ResultColumn::Expr {
+          expr,
+          alias: Some(alias),
+        } if alias.eq_ignore_ascii_case(name) => Some(expr.clone()),
+        _ => None,
+      })
+      .unwrap_or_else(|| expr.clone()),
+    Expr::Unary(op, e) => Expr::Unary(*op, Box::new(resolve_aliases(e, select, columns))),
+    Expr::IsNull { expr, negated } => Expr::IsNull {
+      expr: Box::new(resolve_aliases(expr, select, columns)),
+      negated: *negated,
+    },
+    Expr::Binary(op, l, r) => Expr::Binary(
+      *op,
+      Box::new(resolve_aliases(l, select, columns)),
+      Box::new(resolve_aliases(r, select, columns)),
+    ),
+    Expr::InList {
+      expr,
+      list,
+      negated,
+    } => Expr::InList {
+      expr: Box::new(resolve_aliases(expr, select, columns)),
+      list: list
+        .iter()
+        .map(|e| resolve_aliases(e, select, columns))
+        .collect(),
+      negated: *negated,
+    },
+    Expr::InSelect {
+      expr,
+      select: sub,
+      negated,
+    } => Expr::InSelect {
+      expr: Box::new(resolve_aliases(expr, select, columns)),
+      select: sub.clone(),
+      negated: *negated,
+    },
+    Expr::Literal(_)
+    | Expr::Variable(_)
+    | Expr::Column { .. }
+    | Expr::Aggregate { .. }
+    | Expr::Window { .. }
+    | Expr::Exists(_)
+    | Expr::Subquery(_) => expr.clone(),
+  }
+}
+
+fn collect_calls(expr: &Expr, calls: &mut Vec<Expr>) {
+  match expr {
+    Expr::Aggregate { .. } => {
+      if !calls.contains(expr) {
+        calls.push(expr.clone());
+      }
+    }
+    other => other
+      .children()
+      .into_iter()
+      .for_each(|e| collect_calls(e, calls)),
+  }
+}
+
+impl Accumulator {
+  pub fn new(func: AggFunc, star: bool) -> Self {
+    match func {
+      AggFunc::Count if star => Self::CountStar(0),
+      AggFunc::Count => Self::Count(0),
+      AggFunc::Sum => Self::Sum(Value::Null),
+      AggFunc::Avg => Self::Avg { sum: 0.0, n: 0 },
+      AggFunc::Min => Self::Min(Value::Null),
+      AggFunc::Max => Self::Max(Value::Null),
+    }
+  }
+
+  /// COUNT(*) counts every row; all other aggregates skip NULL inputs.
+  pub fn step(&mut self, arg: Value) -> Result<(), ExecErr> {
+    if let Self::CountStar(n) = self {
+      *n += 1;
+      return Ok(());
+    }
+    if arg.is_null() {
+      return Ok(());
+    }
+    match self {
+      Self::CountStar(_) => unreachable!(),
+      Self::Count(n) => *n += 1,
+      Self::Sum(total) => {
+        let arg = arg.to_numeric();
+        // unlike other arithmetic, SUM of integers fails on overflow, as SQLite's does
+        *total = match (&*total, arg) {
+          (Value::Null, arg) => arg,
+          (Value::Integer(a), Value::Integer(b)) => Value::Integer(
+            a.checked_add(b)
+              .ok_or_else(|| ExecErr::ExprError("integer overflow".to_string()))?,
+          ),
+          (total, arg) => total.add(&arg),
+        };
+      }
+      Self::Avg { sum, n } => {
+        *sum += arg.as_f64().unwrap();
+        *n += 1;
+      }
+      Self::Min(min) => {
+        if min.is_null() || arg.cmp_total(min).is_lt() {
+          *min = arg;
+        }
+      }
+      Self::Max(max) => {
+        if max.is_null() || arg.cmp_total(max).is_gt() {
+          *max = arg;
+        }
+      }
+    }
+    Ok(())
+  }
+
+  pub fn finish(&self) -> Value {
+    match self {
+      Self::CountStar(n) | Self::Count(n) => Value::Integer(*n),
+      Self::Sum(v) | Self::Min(v) | Self::Max(v) => v.clone(),
+      Self::Avg { n: 0, .. } => Value::Null,
+      Self::Avg { sum, n } => Value::Real(sum / *n as f64),
+    }
+  }
+}
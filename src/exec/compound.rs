@@ -0,0 +1,61 @@
+use super::source::result_names;
+use super::{run_query, Paging, Scope};
+use crate::error::ExecErr;
+use crate::sql::ast::{CompoundArm, CompoundOp, Select};
+use crate::value::{GroupKey, Value};
+use std::collections::HashSet;
+
+/// Rows of the core of `first` combined, left to right, with those of `arms`. UNION, INTERSECT
+/// and EXCEPT drop duplicate rows; UNION ALL keeps every row. Rows come out in the order they
+/// were first produced.
+pub fn rows(
+  first: &Select,
+  arms: &[CompoundArm],
+  scope: Scope,
+) -> Result<Vec<Vec<Value>>, ExecErr> {
+  let width = result_names(first, scope)?.len();
+  let mut rows = core_rows(first, scope)?;
+  for arm in arms {
+    if result_names(&arm.select, scope)?.len() != width {
+      return Err(ExecErr::ExprError(format!(
+        "SELECTs to the left and right of {} do not have the same number of result columns.",
+        arm.op.name()
+      )));
+    }
+    let right = core_rows(&arm.select, scope)?;
+    rows = match arm.op {
+      CompoundOp::UnionAll => {
+        rows.extend(right);
+        rows
+      }
+      CompoundOp::Union => distinct(rows.into_iter().chain(right)),
+      CompoundOp::Intersect => {
+        let right: HashSet<_> = right.iter().map(|row| key(row)).collect();
+        distinct(rows.into_iter().filter(|row| right.contains(&key(row))))
+      }
+      CompoundOp::Except => {
+        let right: HashSet<_> = right.iter().map(|row| key(row)).collect();
+        distinct(rows.into_iter().filter(|row| !right.contains(&key(row))))
+      }
+    };
+  }
+  Ok(rows)
+}
+
+fn core_rows(select: &Select, scope: Scope) -> Result<Vec<Vec<Value>>, ExecErr> {
+  let mut rows = vec![];
+  run_query(select, scope, Paging::default(), &mut |row| {
+    rows.push(row);
+    true
+  })?;
+  Ok(rows)
+}
+
+pub fn key(row: &[Value]) -> Vec<GroupKey> {
+  row.iter().map(GroupKey::from).collect()
+}
+
+fn distinct(rows: impl Iterator<Item = Vec<Value>>) -> Vec<Vec<Value>> {
+  let mut seen = HashSet::new();
+  rows.filter(|row| seen.insert(key(row))).collect()
+}
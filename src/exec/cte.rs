@@ -0,0 +1,339 @@
+use super::plan::{self, Plan};
+use super::sort::Sorter;
+use super::{collect, compound, run_query, Paging, Scope};
+use crate::error::ExecErr;
+use crate::sql::ast::{CompoundArm, CompoundOp, Cte, Select, TableSource, With};
+use crate::value::{GroupKey, Value};
+use std::collections::{HashSet, VecDeque};
+
+/// One link of the chain of names a query can read from besides `users`: the CTEs of a WITH
+/// clause, or the working table of a recursive CTE while its step runs. Inner links shadow
+/// outer ones.
+#[derive(Clone, Copy)]
+pub struct Frame<'a> {
+  binding: Binding<'a>,
+  parent: Option<&'a Frame<'a>>,
+}
+
+#[derive(Clone, Copy)]
+enum Binding<'a> {
+  With {
+    with: &'a With,
+    visible: usize, // only the first `visible` CTEs can be named
+  },
+  WorkTable {
+    name: &'a str,
+    columns: &'a [String],
+    rows: &'a [Vec<Value>],
+  },
+}
+
+pub enum Found<'a> {
+  Cte {
+    cte: &'a Cte,
+    frame: Frame<'a>, // what the body of the CTE can itself read from
+    recursive: bool,
+  },
+  WorkTable {
+    columns: &'a [String],
+    rows: &'a [Vec<Value>],
+  },
+}
+
+/// The frame a query's own WITH clause adds on top of `parent`, if it has one.
+pub fn frame_for<'a>(select: &'a Select, parent: Option<&'a Frame<'a>>) -> Option<Frame<'a>> {
+  select.with.as_ref().map(|with| Frame {
+    binding: Binding::With {
+      with,
+      visible: with.ctes.len(),
+    },
+    parent,
+  })
+}
+
+/// Find what `name` refers to, innermost frame first. The body of a CTE sees the CTEs defined
+/// before it, so `WITH users AS (SELECT * FROM users ...)` reads the real table.
+pub fn lookup<'a>(mut frames: Option<&'a Frame<'a>>, name: &str) -> Option<Found<'a>> {
+  while let Some(frame) = frames {
+    match frame.binding {
+      Binding::With { with, visible } => {
+        let found = with.ctes[..visible]
+          .iter()
+          .position(|cte| cte.name.eq_ignore_ascii_case(name));
+        if let Some(idx) = found {
+          let cte = &with.ctes[idx];
+          let recursive = with.recursive
+            && cte
+              .select
+              .compound
+              .iter()
+              .any(|arm| reads_table(&arm.select, &cte.name));
+          return Some(Found::Cte {
+            cte,
+            frame: Frame {
+              binding: Binding::With { with, visible: idx },
+              parent: frame.parent,
+            },
+            recursive,
+          });
+        }
+      }
+      Binding::WorkTable {
+        name: own,
+        columns,
+        rows,
+      } if own.eq_ignore_ascii_case(name) => {
+        return Some(Found::WorkTable { columns, rows });
+      }
+      Binding::WorkTable { .. } => {}
+    }
+    frames = frame.parent;
+  }
+  None
+}
+
+/// Whether the FROM clause of `select` names `table`.
+fn reads_table(select: &Select, table: &str) -> bool {
+  let Some(from) = &select.from else {
+    return false;
+  };
+  std::iter::once(&from.base)
+    .chain(from.joins.iter().map(|join| &join.table))
+    .any(|t| matches!(&t.source, TableSource::Named(name) if name.eq_ignore_ascii_case(table)))
+}
+
+/// Compute the rows of a CTE whose body runs in `frame`.
+pub fn materialize(
+  cte: &Cte,
+  frame: &Frame,
+  scope: Scope,
+  columns: &[String],
+  recursive: bool,
+) -> Result<Vec<Vec<Value>>, ExecErr> {
+  let mut rows = vec![];
+  if recursive {
+    let mut recursion = Recursion::default();
+    while let Some(row) = recursion.step(cte, frame, scope, columns)? {
+      rows.push(row);
+    }
+  } else {
+    let scope = Scope {
+      ctes: Some(frame),
+      ..scope
+    };
+    rows = collect(&cte.select, scope)?;
+    check_width(cte, columns, &rows)?;
+  }
+  Ok(rows)
+}
+
+/// A recursive CTE being computed a row at a time. This runs as in SQLite: the rows of the
+/// anchor (the arms before the first one reading the CTE) seed a queue; each row taken off the
+/// queue joins the result, and before the next one is taken, the recursive arms run with the
+/// CTE's name bound to just that row, queueing whatever they produce. This stops when the
+/// queue is empty or LIMIT rows were taken. ORDER BY decides which queued row is taken next,
+/// and UNION never queues a row twice.
+#[derive(Default)]
+pub struct Recursion {
+  started: bool,
+  queue: VecDeque<Vec<Value>>,
+  seen: HashSet<Vec<GroupKey>>, // rows queued so far, for UNION
+  taken: usize,
+  last: Option<Vec<Value>>, // taken, but its recursive arms have not run yet
+}
+
+impl Recursion {
+  /// The next row of the recursive CTE `name`, of `columns`, as `scope` sees it.
+  pub fn next(
+    &mut self,
+    scope: Scope,
+    name: &str,
+    columns: &[String],
+  ) -> Result<Option<Vec<Value>>, ExecErr> {
+    match lookup(scope.ctes, name) {
+      Some(Found::Cte { cte, frame, .. }) => self.step(cte, &frame, scope, columns),
+      _ => unreachable!("{name} was planned as a recursive CTE"),
+    }
+  }
+
+  fn step(
+    &mut self,
+    cte: &Cte,
+    frame: &Frame,
+    scope: Scope,
+    columns: &[String],
+  ) -> Result<Option<Vec<Value>>, ExecErr> {
+    let scope = Scope {
+      ctes: Some(frame),
+      ..scope
+    };
+    let select = &cte.select;
+    let (anchor, steps) = split(cte)?;
+    let distinct = steps[0].op == CompoundOp::Union;
+    let names: Vec<_> = columns.iter().cloned().map(Some).collect();
+    let sorter = Sorter::new(&select.order_by, &names, true)?;
+    let limit = select
+      .limit
+      .map_or(usize::MAX, |n| n.saturating_add(select.offset));
+
+    if !self.started {
+      self.started = true;
+      self.enqueue(
+        cte,
+        columns,
+        distinct,
+        compound::rows(select, anchor, scope)?,
+      )?;
+    }
+    loop {
+      if let Some(row) = self.last.take() {
+        let frame = Frame {
+          binding: Binding::WorkTable {
+            name: &cte.name,
+            columns,
+            rows: std::slice::from_ref(&row),
+          },
+          parent: scope.ctes,
+        };
+        let scope = Scope {
+          ctes: Some(&frame),
+          ..scope
+        };
+        let mut new = vec![];
+        for step in steps {
+          run_query(&step.select, scope, Paging::default(), &mut |row| {
+            new.push(row);
+            true
+          })?;
+        }
+        self.enqueue(cte, columns, distinct, new)?;
+      }
+      if self.taken >= limit {
+        return Ok(None);
+      }
+      let next = if sorter.is_empty() {
+        self.queue.pop_front()
+      } else {
+        let queue = &self.queue;
+        let least = (0..queue.len()).reduce(|a, b| {
+          if sorter.compare(&queue[b], &queue[a]).is_lt() {
+            b
+          } else {
+            a
+          }
+        });
+        least.and_then(|idx| self.queue.remove(idx))
+      };
+      let Some(row) = next else {
+        return Ok(None);
+      };
+      self.taken += 1;
+      self.last = Some(row.clone());
+      if self.taken > select.offset {
+        return Ok(Some(row));
+      }
+    }
+  }
+
+  fn enqueue(
+    &mut self,
+    cte: &Cte,
+    columns: &[String],
+    distinct: bool,
+    rows: Vec<Vec<Value>>,
+  ) -> Result<(), ExecErr> {
+    check_width(cte, columns, &rows)?;
+    for row in rows {
+      if !distinct || self.seen.insert(compound::key(&row)) {
+        self.queue.push_back(row);
+      }
+    }
+    Ok(())
+  }
+}
+
+/// The arms of a recursive CTE: the anchor, and the recursive arms from the first one reading
+/// the CTE on. As in SQLite, every arm after that one has to read the CTE too: one that does
+/// not would run again for each row taken.
+fn split(cte: &Cte) -> Result<(&[CompoundArm], &[CompoundArm]), ExecErr> {
+  let select = &cte.select;
+  if reads_table(select, &cte.name) {
+    return Err(ExecErr::ExprError(format!(
+      "Recursive table {} has no anchor.",
+      cte.name
+    )));
+  }
+  let split = select
+    .compound
+    .iter()
+    .position(|arm| reads_table(&arm.select, &cte.name))
+    .unwrap();
+  let (anchor, steps) = select.compound.split_at(split);
+  if !matches!(steps[0].op, CompoundOp::Union | CompoundOp::UnionAll) {
+    return Err(ExecErr::ExprError(format!(
+      "Recursive reference to {} has to follow UNION or UNION ALL, not {}.",
+      cte.name,
+      steps[0].op.name()
+    )));
+  }
+  if steps.iter().any(|arm| !reads_table(&arm.select, &cte.name)) {
+    return Err(ExecErr::ExprError(format!(
+      "Circular reference: {}.",
+      cte.name
+    )));
+  }
+  Ok((anchor, steps))
+}
+
+/// Describe how `materialize` computes a CTE: a recursive one runs its anchor once as the
+/// setup, then its recursive arms once per row taken off the queue.
+pub fn describe(
+  cte: &Cte,
+  frame: &Frame,
+  scope: Scope,
+  columns: &[String],
+  recursive: bool,
+  depth: usize,
+  plan: &mut Plan,
+) -> Result<(), ExecErr> {
+  let scope = Scope {
+    ctes: Some(frame),
+    ..scope
+  };
+  let select = &cte.select;
+  if !recursive {
+    return plan::describe(select, scope, depth, plan);
+  }
+  let (anchor, steps) = split(cte)?;
+  plan.push((depth, "SETUP".to_string()));
+  plan::describe_compound(select, anchor, scope, depth + 1, plan)?;
+  plan.push((depth, "RECURSIVE STEP".to_string()));
+  let frame = Frame {
+    binding: Binding::WorkTable {
+      name: &cte.name,
+      columns,
+      rows: &[],
+    },
+    parent: scope.ctes,
+  };
+  let scope = Scope {
+    ctes: Some(&frame),
+    ..scope
+  };
+  for step in steps {
+    plan::describe_core(&step.select, scope, depth + 1, plan)?;
+  }
+  Ok(())
+}
+
+fn check_width(cte: &Cte, columns: &[String], rows: &[Vec<Value>]) -> Result<(), ExecErr> {
+  match rows.first() {
+    Some(row) if row.len() != columns.len() => Err(ExecErr::ExprError(format!(
+      "Table {} has {} values for {} columns.",
+      cte.name,
+      row.len(),
+      columns.len()
+    ))),
+    _ => Ok(()),
+  }
+}
@@ -0,0 +1,228 @@
+use super::{subquery, Scope};
+use crate::error::ExecErr;
+use crate::sql::ast::{BinaryOp, Expr, UnaryOp};
+use crate::value::{GroupKey, Value};
+use std::cmp::Ordering;
+
+/// A column of the rows flowing through the executor, qualified by the table (or alias) it
+/// comes from.
+#[derive(Debug, Clone)]
+pub struct Column {
+  pub table: Option<String>,
+  pub name: String,
+}
+
+impl Column {
+  fn matches(&self, table: Option<&str>, name: &str) -> bool {
+    let table_matches = match (table, &self.table) {
+      (None, _) => true,
+      (Some(t), Some(own)) => t.eq_ignore_ascii_case(own),
+      (Some(_), None) => false,
+    };
+    table_matches && self.name.eq_ignore_ascii_case(name)
+  }
+}
+
+/// Position of the column a (possibly qualified) name refers to.
+pub fn resolve_column(
+  columns: &[Column],
+  table: Option<&str>,
+  name: &str,
+) -> Result<usize, ExecErr> {
+  let mut found = columns
+    .iter()
+    .enumerate()
+    .filter(|(_, col)| col.matches(table, name))
+    .map(|(idx, _)| idx);
+  match (found.next(), found.next()) {
+    (Some(idx), None) => Ok(idx),
+    (Some(_), Some(_)) => Err(ExecErr::ExprError(format!(
+      "Ambiguous column name: {name}."
+    ))),
+    (None, _) => {
+      let full_name = match table {
+        Some(t) => format!("{t}.{name}"),
+        None => name.to_string(),
+      };
+      Err(ExecErr::NoSuchColumn(format!(
+        "No such column: {full_name}."
+      )))
+    }
+  }
+}
+
+/// Everything an expression can refer to while being evaluated for one row.
+pub struct Env<'a> {
+  pub scope: Scope<'a>,
+  pub columns: &'a [Column],
+  pub row: &'a [Value],
+  pub aggregates: &'a [(&'a Expr, Value)], // finalized aggregate calls of the current group
+  pub windows: &'a [(&'a Expr, Value)],    // window function calls of the current row
+}
+
+impl<'a> Env<'a> {
+  pub fn new(scope: Scope<'a>, columns: &'a [Column], row: &'a [Value]) -> Self {
+    Self {
+      scope,
+      columns,
+      row,
+      aggregates: &[],
+      windows: &[],
+    }
+  }
+
+  /// Value of a column, looked up in the enclosing queries when this one has no such column.
+  fn lookup(&self, table: Option<&str>, name: &str) -> Result<Value, ExecErr> {
+    match resolve_column(self.columns, table, name) {
+      Ok(idx) => Ok(self.row[idx].clone()),
+      Err(ExecErr::NoSuchColumn(_)) if self.scope.outer.is_some() => {
+        self.scope.outer.unwrap().lookup(table, name)
+      }
+      Err(e) => Err(e),
+    }
+  }
+}
+
+pub fn eval(expr: &Expr, env: &Env) -> Result<Value, ExecErr> {
+  match expr {
+    Expr::Literal(v) => Ok(v.clone()),
+    // parameters are bound before a statement runs; only naming its columns goes without
+    Expr::Variable(n) => Ok(
+      env
+        .scope
+        .ctx
+        .params
+        .get(n - 1)
+        .cloned()
+        .unwrap_or(Value::Null),
+    ),
+    Expr::Column { table, name } => env.lookup(table.as_deref(), name),
+    Expr::Unary(op, e) => {
+      let v = eval(e, env)?;
+      match op {
+        UnaryOp::Neg => Ok(v.neg()),
+        UnaryOp::Not => Ok(bool_value(v.truthy().map(|b| !b))),
+      }
+    }
+    Expr::Binary(op, l, r) => eval_binary(*op, l, r, env),
+    Expr::IsNull { expr, negated } => {
+      let is_null = eval(expr, env)?.is_null();
+      Ok(bool_value(Some(is_null != *negated)))
+    }
+    Expr::InList {
+      expr,
+      list,
+      negated,
+    } => {
+      let val = eval(expr, env)?;
+      let mut res = Some(false);
+      for item in list {
+        match val.compare(&eval(item, env)?) {
+          Some(Ordering::Equal) => {
+            res = Some(true);
+            break;
+          }
+          None => res = None,
+          Some(_) => {}
+        }
+      }
+      Ok(bool_value(res.map(|b| b != *negated)))
+    }
+    Expr::InSelect {
+      expr,
+      select,
+      negated,
+    } => {
+      let val = eval(expr, env)?;
+      let rows = subquery::run(select, env, subquery::Kind::In)?;
+      let res = if rows.is_empty() {
+        Some(false)
+      } else if val.is_null() {
+        None
+      } else if rows.keys.contains(&GroupKey::from(&val)) {
+        Some(true)
+      } else if rows.has_null {
+        None
+      } else {
+        Some(false)
+      };
+      Ok(bool_value(res.map(|b| b != *negated)))
+    }
+    Expr::Exists(select) => {
+      let rows = subquery::run(select, env, subquery::Kind::Exists)?;
+      Ok(bool_value(Some(!rows.is_empty())))
+    }
+    Expr::Subquery(select) => {
+      let rows = subquery::run(select, env, subquery::Kind::Scalar)?;
+      Ok(rows.first_value())
+    }
+    Expr::Aggregate { func, .. } => env
+      .aggregates
+      .iter()
+      .find(|(agg, _)| *agg == expr)
+      .map(|(_, v)| v.clone())
+      .ok_or_else(|| {
+        ExecErr::ExprError(format!("Misuse of aggregate function {}().", func.name()))
+      }),
+    Expr::Window { func, .. } => env
+      .windows
+      .iter()
+      .find(|(call, _)| *call == expr)
+      .map(|(_, v)| v.clone())
+      .ok_or_else(|| ExecErr::ExprError(format!("Misuse of window function {}().", func.name()))),
+  }
+}
+
+fn eval_binary(op: BinaryOp, l: &Expr, r: &Expr, env: &Env) -> Result<Value, ExecErr> {
+  use BinaryOp::*;
+  let lhs = eval(l, env)?;
+  // AND/OR short-circuit when the left side decides the result
+  match (op, lhs.truthy()) {
+    (And, Some(false)) => return Ok(bool_value(Some(false))),
+    (Or, Some(true)) => return Ok(bool_value(Some(true))),
+    _ => {}
+  }
+  let rhs = eval(r, env)?;
+  binary(op, &lhs, &rhs)
+}
+
+/// Apply a binary operator to two evaluated operands.
+pub fn binary(op: BinaryOp, lhs: &Value, rhs: &Value) -> Result<Value, ExecErr> {
+  use BinaryOp::*;
+  match op {
+    And | Or => {
+      let (a, b) = (lhs.truthy(), rhs.truthy());
+      let res = match (op, a, b) {
+        (And, _, Some(false)) => Some(false),
+        (And, Some(true), Some(true)) => Some(true),
+        (Or, _, Some(true)) => Some(true),
+        (Or, Some(false), Some(false)) => Some(false),
+        _ => None,
+      };
+      Ok(bool_value(res))
+    }
+    Eq => Ok(cmp_value(lhs, rhs, |o| o == Ordering::Equal)),
+    Ne => Ok(cmp_value(lhs, rhs, |o| o != Ordering::Equal)),
+    Lt => Ok(cmp_value(lhs, rhs, |o| o == Ordering::Less)),
+    Le => Ok(cmp_value(lhs, rhs, |o| o != Ordering::Greater)),
+    Gt => Ok(cmp_value(lhs, rhs, |o| o == Ordering::Greater)),
+    Ge => Ok(cmp_value(lhs, rhs, |o| o != Ordering::Less)),
+    Add => Ok(lhs.add(rhs)),
+    Sub => Ok(lhs.sub(rhs)),
+    Mul => Ok(lhs.mul(rhs)),
+    Div => Ok(lhs.div(rhs)),
+    Rem => Ok(lhs.rem(rhs)),
+    Concat => Ok(lhs.concat(rhs)),
+  }
+}
+
+fn cmp_value(lhs: &Value, rhs: &Value, f: impl Fn(Ordering) -> bool) -> Value {
+  bool_value(lhs.compare(rhs).map(f))
+}
+
+pub fn bool_value(b: Option<bool>) -> Value {
+  match b {
+    None => Value::Null,
+    Some(b) => Value::Integer(i64::from(b)),
+  }
+}
@@ -0,0 +1,114 @@
+use super::source::Source;
+use super::{cte, streams, subquery, Ctx, Scope, Subqueries};
+use crate::error::ExecErr;
+use crate::sql::ast::{CompoundArm, CompoundOp, Expr, Select};
+use crate::table::Table;
+
+/// Lines of EXPLAIN QUERY PLAN, each with the depth it nests at.
+pub type Plan = Vec<(usize, String)>;
+
+/// How the executor runs `select`, in the words SQLite uses: a line per loop over a table,
+/// per materialized CTE or subquery, and per sort.
+pub fn query_plan(select: &Select, table: &Table) -> Result<Plan, ExecErr> {
+  let subqueries = Subqueries::default();
+  let ctx = Ctx {
+    table,
+    params: &[],
+    subqueries: &subqueries,
+  };
+  let scope = Scope {
+    ctx: &ctx,
+    outer: None,
+    ctes: None,
+  };
+  let mut plan = vec![];
+  describe(select, scope, 0, &mut plan)?;
+  Ok(plan)
+}
+
+pub fn describe(
+  select: &Select,
+  scope: Scope,
+  depth: usize,
+  plan: &mut Plan,
+) -> Result<(), ExecErr> {
+  let frame = cte::frame_for(select, scope.ctes);
+  let scope = Scope {
+    ctes: frame.as_ref().or(scope.ctes),
+    ..scope
+  };
+  describe_compound(select, &select.compound, scope, depth, plan)?;
+  if !select.order_by.is_empty() && !streams(select, &Source::plan(select, scope)?) {
+    plan.push((depth, "USE TEMP B-TREE FOR ORDER BY".to_string()));
+  }
+  Ok(())
+}
+
+/// The core of `first` combined with `arms`, or just the core when there are none.
+pub fn describe_compound(
+  first: &Select,
+  arms: &[CompoundArm],
+  scope: Scope,
+  depth: usize,
+  plan: &mut Plan,
+) -> Result<(), ExecErr> {
+  if arms.is_empty() {
+    return describe_core(first, scope, depth, plan);
+  }
+  plan.push((depth, "COMPOUND QUERY".to_string()));
+  plan.push((depth + 1, "LEFT-MOST SUBQUERY".to_string()));
+  describe_core(first, scope, depth + 2, plan)?;
+  for arm in arms {
+    let line = match arm.op {
+      CompoundOp::UnionAll => arm.op.name().to_string(),
+      op => format!("{} USING TEMP B-TREE", op.name()),
+    };
+    plan.push((depth + 1, line));
+    describe_core(&arm.select, scope, depth + 2, plan)?;
+  }
+  Ok(())
+}
+
+/// A single core: its loops, then grouping, then the subqueries of its expressions.
+pub fn describe_core(
+  select: &Select,
+  scope: Scope,
+  depth: usize,
+  plan: &mut Plan,
+) -> Result<(), ExecErr> {
+  Source::plan(select, scope)?.describe(scope, depth, plan)?;
+  if !select.group_by.is_empty() {
+    plan.push((depth, "USE TEMP B-TREE FOR GROUP BY".to_string()));
+  }
+  for expr in select.exprs() {
+    describe_subqueries(expr, scope, depth, plan)?;
+  }
+  Ok(())
+}
+
+fn describe_subqueries(
+  expr: &Expr,
+  scope: Scope,
+  depth: usize,
+  plan: &mut Plan,
+) -> Result<(), ExecErr> {
+  let (kind, select) = match expr {
+    Expr::Subquery(select) | Expr::Exists(select) => ("SCALAR SUBQUERY", select),
+    Expr::InSelect { expr, select, .. } => {
+      describe_subqueries(expr, scope, depth, plan)?;
+      ("LIST SUBQUERY", select)
+    }
+    other => {
+      for child in other.children() {
+        describe_subqueries(child, scope, depth, plan)?;
+      }
+      return Ok(());
+    }
+  };
+  let correlated = match subquery::is_correlated(select, scope) {
+    true => "CORRELATED ",
+    false => "",
+  };
+  plan.push((depth, format!("{correlated}{kind}")));
+  describe(select, scope, depth + 1, plan)
+}
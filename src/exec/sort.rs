@@ -0,0 +1,104 @@
+use crate::error::ExecErr;
+use crate::sql::ast::{Expr, OrderingTerm};
+use crate::value::Value;
+use std::cmp::Ordering;
+
+/// ORDER BY, applied to buffered rows. A term naming a result column, by alias or by position,
+/// sorts on that column; any other term is an expression over the input row, which the
+/// producer of the rows appends to each of them as a hidden column.
+pub struct Sorter<'s> {
+  terms: Vec<(Key<'s>, bool)>, // and whether the term is DESC
+  hidden: usize,
+}
+
+enum Key<'s> {
+  Result(usize),
+  Hidden(usize, &'s Expr),
+}
+
+impl<'s> Sorter<'s> {
+  /// `names` holds the name a term can refer to each result column by. With `strict`, as for
+  /// compound selects, every term has to be a result column.
+  pub fn new(
+    order_by: &'s [OrderingTerm],
+    names: &[Option<String>],
+    strict: bool,
+  ) -> Result<Self, ExecErr> {
+    let mut terms = vec![];
+    let mut hidden = 0;
+    for term in order_by {
+      let key = match &term.expr {
+        Expr::Literal(Value::Integer(pos)) => {
+          if *pos < 1 || *pos as usize > names.len() {
+            return Err(ExecErr::ExprError(format!(
+              "ORDER BY term out of range - should be between 1 and {}.",
+              names.len()
+            )));
+          }
+          Key::Result(*pos as usize - 1)
+        }
+        Expr::Column { table: None, name } if position(names, name).is_some() => {
+          Key::Result(position(names, name).unwrap())
+        }
+        _ if strict => {
+          return Err(ExecErr::ExprError(
+            "ORDER BY term does not match any column in the result set.".to_string(),
+          ))
+        }
+        expr => {
+          hidden += 1;
+          Key::Hidden(hidden - 1, expr)
+        }
+      };
+      terms.push((key, term.desc));
+    }
+    Ok(Self { terms, hidden })
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.terms.is_empty()
+  }
+
+  /// The expressions whose values have to follow the result columns of every row.
+  pub fn hidden(&self) -> Vec<&'s Expr> {
+    self
+      .terms
+      .iter()
+      .filter_map(|(key, _)| match key {
+        Key::Hidden(_, expr) => Some(*expr),
+        Key::Result(_) => None,
+      })
+      .collect()
+  }
+
+  /// Stable sort of `rows`, which then lose their hidden columns.
+  pub fn sort(&self, rows: &mut [Vec<Value>]) {
+    rows.sort_by(|a, b| self.compare(a, b));
+    for row in rows {
+      row.truncate(row.len() - self.hidden);
+    }
+  }
+
+  /// Order of two rows, with NULLs first in ascending order as in SQLite.
+  pub fn compare(&self, a: &[Value], b: &[Value]) -> Ordering {
+    let width = a.len() - self.hidden;
+    for (key, desc) in &self.terms {
+      let idx = match key {
+        Key::Result(idx) => *idx,
+        Key::Hidden(idx, _) => width + idx,
+      };
+      let ord = a[idx].cmp_total(&b[idx]);
+      let ord = if *desc { ord.reverse() } else { ord };
+      if ord.is_ne() {
+        return ord;
+      }
+    }
+    Ordering::Equal
+  }
+}
+
+fn position(names: &[Option<String>], name: &str) -> Option<usize> {
+  names
+    .iter()
+    .position(|n| n.as_deref().is_some_and(|n| n.eq_ignore_ascii_case(name)))
+}
@@ -0,0 +1,262 @@
+use super::cte::{self, Found, Frame};
+use super::eval::Column;
+use super::plan::{self, Plan};
+use super::Scope;
+use crate::error::ExecErr;
+use crate::row;
+use crate::sql::ast::{Cte, Expr, JoinKind, ResultColumn, Select, TableRef, TableSource};
+use crate::stats;
+use crate::value::Value;
+use crate::vdbe::{self, planner, Rows, Vm};
+use std::rc::Rc;
+
+/// The FROM clause of a query, run as nested loops in the order and by the access paths that
+/// the planner chooses: a loop over the users table may seek by primary key, other tables are
+/// scanned, and each term of WHERE and of the join predicates is checked by the first loop
+/// that has the rows it reads. The loops are compiled as those of a compiled query are, and
+/// run as a program of their own.
+pub struct Source<'s> {
+  tables: Vec<Access<'s>>, // in FROM order
+  pub plan: planner::Plan<'s>,
+  pub columns: Vec<Column>,
+}
+
+enum Access<'s> {
+  Users,
+  Cte {
+    cte: &'s Cte,
+    frame: Frame<'s>,
+    columns: Vec<String>,
+    recursive: bool,
+  },
+  Subquery(&'s Rc<Select>),
+  WorkTable(&'s str, Vec<String>), // the row a recursive CTE steps from, by name and columns
+  Stat,                            // sqlite_stat1, as of the last ANALYZE
+}
+
+impl<'s> Source<'s> {
+  /// Plan the FROM clause of `select`, whose own CTEs `scope` already includes.
+  pub fn plan(select: &'s Select, scope: Scope<'s>) -> Result<Self, ExecErr> {
+    let mut tables = vec![];
+    let mut accesses = vec![];
+    match &select.from {
+      // the bare `select` shorthand reads the whole users table
+      None if select.columns.is_empty() => {
+        accesses.push(Access::Users);
+        tables.push(planner::Table {
+          name: "users".to_string(),
+          qualifier: Some("users".to_string()),
+          columns: users_columns(),
+          users: true,
+          kind: JoinKind::Inner,
+          on: None,
+        });
+      }
+      // SELECT without FROM produces a single empty row
+      None => {}
+      Some(from) => {
+        let joins = from.joins.iter().map(|j| (&j.table, j.kind, j.on.as_ref()));
+        for (table, kind, on) in std::iter::once((&from.base, JoinKind::Inner, None)).chain(joins) {
+          let (access, table) = Self::table(table, kind, on, scope)?;
+          accesses.push(access);
+          tables.push(table);
+        }
+      }
+    }
+    let plan = planner::Plan::for_tables(select, tables, scope.ctx.table.stats())?;
+    Ok(Self {
+      tables: accesses,
+      columns: plan.columns.clone(),
+      plan,
+    })
+  }
+
+  /// How to read `table`, and what the planner needs to know of it.
+  fn table(
+    table: &'s TableRef,
+    kind: JoinKind,
+    on: Option<&'s Expr>,
+    scope: Scope<'s>,
+  ) -> Result<(Access<'s>, planner::Table<'s>), ExecErr> {
+    let (access, columns) = match &table.source {
+      TableSource::Subquery(select) => (Access::Subquery(select), result_names(select, scope)?),
+      TableSource::Named(name) => match cte::lookup(scope.ctes, name) {
+        Some(Found::Cte {
+          cte,
+          frame,
+          recursive,
+        }) => {
+          let columns = if cte.columns.is_empty() {
+            let scope = Scope {
+              ctes: Some(&frame),
+              ..scope
+            };
+            result_names(&cte.select, scope)?
+          } else {
+            cte.columns.clone()
+          };
+          let access = Access::Cte {
+            cte,
+            frame,
+            columns: columns.clone(),
+            recursive,
+          };
+          (access, columns)
+        }
+        Some(Found::WorkTable { columns, .. }) => {
+          (Access::WorkTable(name, columns.to_vec()), columns.to_vec())
+        }
+        None if name.eq_ignore_ascii_case("users") => (Access::Users, users_columns()),
+        // sqlite_stat1 exists once ANALYZE ran
+        None => match scope.ctx.table.stats() {
+          Some(_) if name.eq_ignore_ascii_case("sqlite_stat1") => {
+            (Access::Stat, stats::COLUMNS.map(str::to_string).to_vec())
+          }
+          _ => return Err(ExecErr::NoSuchTable(format!("No such table: {name}."))),
+        },
+      },
+    };
+    let qualifier = table.qualifier();
+    let table = planner::Table {
+      name: qualifier.unwrap_or("(subquery)").to_string(),
+      qualifier: qualifier.map(str::to_string),
+      columns,
+      users: matches!(access, Access::Users),
+      kind,
+      on,
+    };
+    Ok((access, table))
+  }
+
+  /// Whether the query reads the users table alone, so that the B-tree itself can answer
+  /// some questions about its rows.
+  pub fn is_single_table(&self) -> bool {
+    matches!(self.tables[..], [Access::Users])
+  }
+
+  pub fn reads_work_table(&self) -> bool {
+    self
+      .tables
+      .iter()
+      .any(|access| matches!(access, Access::WorkTable(..)))
+  }
+
+  /// The rows the cursor of table `t` reads, unless it is the users table. A recursive CTE
+  /// read by the `outermost` loop is computed as it is read, so that a query which stops
+  /// early, as at its LIMIT, stops the recursion too.
+  pub fn rows(&self, t: usize, outermost: bool) -> Option<Rows> {
+    Some(match &self.tables[t] {
+      Access::Users => return None,
+      Access::Cte {
+        cte,
+        columns,
+        recursive,
+        ..
+      } => {
+        let (name, columns) = (cte.name.clone(), columns.clone());
+        match recursive & outermost {
+          true => Rows::Recursive { name, columns },
+          false => Rows::Cte { name, columns },
+        }
+      }
+      Access::WorkTable(name, columns) => Rows::Cte {
+        name: name.to_string(),
+        columns: columns.clone(),
+      },
+      Access::Subquery(select) => Rows::Subquery(Rc::clone(select)),
+      Access::Stat => Rows::Stat,
+    })
+  }
+
+  /// Add the loops of this FROM clause to `plan`, outermost first, each preceded by how the
+  /// rows of a CTE or subquery it reads are computed.
+  pub fn describe(&self, scope: Scope, depth: usize, plan: &mut Plan) -> Result<(), ExecErr> {
+    if self.tables.is_empty() {
+      plan.push((depth, "SCAN CONSTANT ROW".to_string()));
+    }
+    for &t in &self.plan.order {
+      let level = &self.plan.levels[t];
+      match &self.tables[t] {
+        Access::Users | Access::WorkTable(..) | Access::Stat => {}
+        Access::Cte {
+          cte,
+          frame,
+          columns,
+          recursive,
+        } => {
+          plan.push((depth, format!("MATERIALIZE {}", cte.name)));
+          cte::describe(cte, frame, scope, columns, *recursive, depth + 1, plan)?;
+        }
+        Access::Subquery(select) => {
+          plan.push((depth, format!("MATERIALIZE {}", level.name)));
+          plan::describe(select, scope, depth + 1, plan)?;
+        }
+      }
+      plan.push((depth, level.describe()));
+    }
+    Ok(())
+  }
+
+  /// Feed the joined rows that pass WHERE to `f` until it returns false. The first `skip`
+  /// rows are stepped over without reading their columns.
+  pub fn scan<F>(&self, scope: Scope, skip: usize, mut f: F) -> Result<(), ExecErr>
+  where
+    F: FnMut(Vec<Value>) -> Result<bool, ExecErr>,
+  {
+    let mut vm = Vm::new(vdbe::scan(self, skip)?);
+    while let Some(row) = vm.step_in(scope)? {
+      if !f(row)? {
+        break;
+      }
+    }
+    Ok(())
+  }
+}
+
+fn users_columns() -> Vec<String> {
+  row::COLUMNS.map(str::to_string).to_vec()
+}
+
+/// Names of the columns `select` produces, as seen by a query reading it as a table: the
+/// alias of a result column, else the name of the column it is, else `columnN`.
+pub fn result_names(select: &Select, scope: Scope) -> Result<Vec<String>, ExecErr> {
+  let frame = cte::frame_for(select, scope.ctes);
+  let scope = Scope {
+    ctes: frame.as_ref().or(scope.ctes),
+    ..scope
+  };
+  let source = Source::plan(select, scope)?;
+  let source_names = |table: Option<&str>| {
+    source
+      .columns
+      .iter()
+      .filter(|col| match table {
+        None => true,
+        Some(t) => col
+          .table
+          .as_deref()
+          .is_some_and(|own| own.eq_ignore_ascii_case(t)),
+      })
+      .map(|col| col.name.clone())
+      .collect::<Vec<_>>()
+  };
+  if select.columns.is_empty() {
+    return Ok(source_names(None));
+  }
+  let mut names = vec![];
+  for (i, col) in select.columns.iter().enumerate() {
+    match col {
+      ResultColumn::Star => names.extend(source_names(None)),
+      ResultColumn::TableStar(table) => names.extend(source_names(Some(table))),
+      ResultColumn::Expr {
+        alias: Some(alias), ..
+      } => names.push(alias.clone()),
+      ResultColumn::Expr {
+        expr: Expr::Column { name, .. },
+        ..
+      } => names.push(name.clone()),
+      ResultColumn::Expr { .. } => names.push(format!("column{}", i + 1)),
+    }
+  }
+  Ok(names)
+}
@@ -0,0 +1,169 @@
+use super::eval::{resolve_column, Column, Env};
+use super::source::Source;
+use super::{cte, run_select, Scope};
+use crate::error::ExecErr;
+use crate::sql::ast::{Expr, ResultColumn, Select};
+use crate::value::{GroupKey, Value};
+use std::collections::HashSet;
+use std::rc::Rc;
+
+/// How the enclosing expression consumes a subquery.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Kind {
+  Exists, // only needs to know whether there is a row
+  Scalar, // only needs the first row, of a single column
+  In,     // needs every row, of a single column
+}
+
+pub struct Rows {
+  rows: Vec<Vec<Value>>,
+  pub keys: HashSet<GroupKey>, // non-NULL values of the column, for `IN`
+  pub has_null: bool,
+}
+
+/// Per-statement memo of subqueries. An uncorrelated subquery runs once and its rows are
+/// reused for every row of the enclosing query.
+pub enum Cached {
+  Correlated,
+  Rows(Rc<Rows>),
+}
+
+impl Rows {
+  pub fn is_empty(&self) -> bool {
+    self.rows.is_empty()
+  }
+
+  pub fn first_value(&self) -> Value {
+    self.rows.first().map_or(Value::Null, |row| row[0].clone())
+  }
+}
+
+pub fn run(select: &Select, env: &Env, kind: Kind) -> Result<Rc<Rows>, ExecErr> {
+  let ctx = env.scope.ctx;
+  let key = select as *const Select;
+  let correlated = match ctx.subqueries.borrow().get(&key) {
+    Some(Cached::Rows(rows)) => return Ok(rows.clone()),
+    Some(Cached::Correlated) => true,
+    None => is_correlated(select, env.scope),
+  };
+
+  let scope = Scope {
+    ctx,
+    outer: Some(env),
+    ctes: env.scope.ctes,
+  };
+  let limit = if kind == Kind::In { usize::MAX } else { 1 };
+  let mut rows = vec![];
+  run_select(select, scope, &mut |row| {
+    rows.push(row);
+    rows.len() < limit
+  })?;
+
+  if kind != Kind::Exists {
+    if let Some(row) = rows.first().filter(|row| row.len() != 1) {
+      return Err(ExecErr::ExprError(format!(
+        "Sub-select returns {} columns - expected 1.",
+        row.len()
+      )));
+    }
+  }
+  let mut res = Rows {
+    rows,
+    keys: HashSet::new(),
+    has_null: false,
+  };
+  if kind == Kind::In {
+    for row in &res.rows {
+      match &row[0] {
+        Value::Null => res.has_null = true,
+        val => {
+          res.keys.insert(GroupKey::from(val));
+        }
+      }
+    }
+  }
+
+  let res = Rc::new(res);
+  let entry = if correlated {
+    Cached::Correlated
+  } else {
+    Cached::Rows(res.clone())
+  };
+  ctx.subqueries.borrow_mut().insert(key, entry);
+  Ok(res)
+}
+
+/// Whether a subquery refers to columns of an enclosing query, directly or through one of its
+/// own subqueries or CTEs. Reading the working table of a recursive CTE counts too, since that
+/// changes between runs.
+pub fn is_correlated(select: &Select, scope: Scope) -> bool {
+  escapes(select, scope, &mut vec![])
+}
+
+fn escapes(select: &Select, scope: Scope, scopes: &mut Vec<Vec<Column>>) -> bool {
+  let frame = cte::frame_for(select, scope.ctes);
+  let scope = Scope {
+    ctes: frame.as_ref().or(scope.ctes),
+    ..scope
+  };
+  let arms = select.compound.iter().map(|arm| &arm.select);
+  if select
+    .table_selects()
+    .into_iter()
+    .chain(arms)
+    .any(|sub| escapes(sub, scope, scopes))
+  {
+    return true;
+  }
+  let Ok(source) = Source::plan(select, scope) else {
+    return true; // the error surfaces when the subquery runs
+  };
+  if source.reads_work_table() {
+    return true;
+  }
+  let aliases: Vec<_> = select
+    .columns
+    .iter()
+    .filter_map(|col| match col {
+      ResultColumn::Expr {
+        alias: Some(alias), ..
+      } => Some(alias.clone()),
+      _ => None,
+    })
+    .collect();
+  scopes.push(source.columns);
+  let res = select
+    .exprs()
+    .into_iter()
+    .any(|e| expr_escapes(e, scope, scopes, &aliases));
+  scopes.pop();
+  res
+}
+
+fn expr_escapes(
+  expr: &Expr,
+  scope: Scope,
+  scopes: &mut Vec<Vec<Column>>,
+  aliases: &[String],
+) -> bool {
+  match expr {
+    Expr::Column { table, name } => {
+      let is_alias = table.is_none() && aliases.iter().any(|a| a.eq_ignore_ascii_case(name));
+      let resolves = scopes.iter().any(|cols| {
+        !matches!(
+          resolve_column(cols, table.as_deref(), name),
+          Err(ExecErr::NoSuchColumn(_))
+        )
+      });
+      !is_alias && !resolves
+    }
+    Expr::InSelect { expr, select, .. } => {
+      expr_escapes(expr, scope, scopes, aliases) || escapes(select, scope, scopes)
+    }
+    Expr::Exists(select) | Expr::Subquery(select) => escapes(select, scope, scopes),
+    other => other
+      .children()
+      .into_iter()
+      .any(|e| expr_escapes(e, scope, scopes, aliases)),
+  }
+}
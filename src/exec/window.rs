@@ -0,0 +1,252 @@
+use super::aggregate::Accumulator;
+use super::eval::{eval, Env};
+use crate::error::ExecErr;
+use crate::sql::ast::{Expr, FrameBound, Over, ResultColumn, Select, WindowFrame, WindowFunc};
+use crate::value::{GroupKey, Value};
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+/// The distinct window function calls of the select list and of the hidden ORDER BY columns.
+pub fn calls<'s>(select: &'s Select, hidden: &[&'s Expr]) -> Vec<&'s Expr> {
+  let mut calls = vec![];
+  for col in &select.columns {
+    if let ResultColumn::Expr { expr, .. } = col {
+      collect_calls(expr, &mut calls);
+    }
+  }
+  for expr in hidden {
+    collect_calls(expr, &mut calls);
+  }
+  calls
+}
+
+fn collect_calls<'s>(expr: &'s Expr, calls: &mut Vec<&'s Expr>) {
+  match expr {
+    Expr::Window { .. } => {
+      if !calls.contains(&expr) {
+        calls.push(expr);
+      }
+    }
+    other => other
+      .children()
+      .into_iter()
+      .for_each(|e| collect_calls(e, calls)),
+  }
+}
+
+/// Evaluate window function `calls` over the rows of `envs`, giving for every row the values
+/// of all calls, as `Env::windows` wants them.
+pub fn compute<'e>(
+  calls: &[&'e Expr],
+  envs: &[Env],
+) -> Result<Vec<Vec<(&'e Expr, Value)>>, ExecErr> {
+  let mut out: Vec<Vec<_>> = envs.iter().map(|_| vec![]).collect();
+  for &call in calls {
+    let Expr::Window { func, args, over } = call else {
+      unreachable!()
+    };
+    let values = compute_call(*func, args, over, envs)?;
+    for (row, val) in out.iter_mut().zip(values) {
+      row.push((call, val));
+    }
+  }
+  Ok(out)
+}
+
+/// One call: rows are split into partitions, each partition is sorted by the window's ORDER BY,
+/// and the function runs over it in that order.
+fn compute_call(
+  func: WindowFunc,
+  args: &[Expr],
+  over: &Over,
+  envs: &[Env],
+) -> Result<Vec<Value>, ExecErr> {
+  let mut partitions: Vec<Vec<usize>> = vec![];
+  let mut index = HashMap::new();
+  let mut sort_keys = vec![];
+  let mut arg_values = vec![];
+  for (i, env) in envs.iter().enumerate() {
+    let key = over
+      .partition_by
+      .iter()
+      .map(|e| eval(e, env).map(|v| GroupKey::from(&v)))
+      .collect::<Result<Vec<_>, _>>()?;
+    let pid = *index.entry(key).or_insert_with(|| {
+      partitions.push(vec![]);
+      partitions.len() - 1
+    });
+    partitions[pid].push(i);
+    sort_keys.push(
+      over
+        .order_by
+        .iter()
+        .map(|term| eval(&term.expr, env))
+        .collect::<Result<Vec<_>, _>>()?,
+    );
+    arg_values.push(
+      args
+        .iter()
+        .map(|e| eval(e, env))
+        .collect::<Result<Vec<_>, _>>()?,
+    );
+  }
+
+  let compare = |a: usize, b: usize| {
+    let terms = over.order_by.iter().enumerate();
+    for (k, term) in terms {
+      let ord = sort_keys[a][k].cmp_total(&sort_keys[b][k]);
+      let ord = if term.desc { ord.reverse() } else { ord };
+      if ord.is_ne() {
+        return ord;
+      }
+    }
+    Ordering::Equal
+  };
+  let frame = over.frame.unwrap_or(WindowFrame {
+    range: true,
+    start: FrameBound::UnboundedPreceding,
+    end: FrameBound::CurrentRow,
+  });
+
+  let mut out = vec![Value::Null; envs.len()];
+  for mut part in partitions {
+    part.sort_by(|&a, &b| compare(a, b));
+    let peers = Peers::new(&part, compare);
+    let mut dense_rank = 0;
+    let mut acc = RunningAggregate::default();
+    for (pos, &row) in part.iter().enumerate() {
+      if peers.first[pos] == pos {
+        dense_rank += 1;
+      }
+      let arg = |n: usize| arg_values[row].get(n).cloned().unwrap_or(Value::Null);
+      out[row] = match func {
+        WindowFunc::RowNumber => Value::Integer(pos as i64 + 1),
+        WindowFunc::Rank => Value::Integer(peers.first[pos] as i64 + 1),
+        WindowFunc::DenseRank => Value::Integer(dense_rank),
+        WindowFunc::Lag | WindowFunc::Lead => {
+          let offset = match arg_values[row].get(1) {
+            Some(v) => match v.to_numeric() {
+              Value::Integer(n) => n,
+              _ => {
+                return Err(ExecErr::ExprError(format!(
+                  "Second argument to {}() must be an integer.",
+                  func.name()
+                )))
+              }
+            },
+            None => 1,
+          };
+          let target = match func {
+            WindowFunc::Lag => pos as i64 - offset,
+            _ => pos as i64 + offset,
+          };
+          match usize::try_from(target).ok().and_then(|t| part.get(t)) {
+            Some(&other) => arg_values[other][0].clone(),
+            None => arg(2),
+          }
+        }
+        WindowFunc::FirstValue => match frame_rows(&frame, pos, &part, &peers) {
+          Some((start, _)) => arg_values[part[start]][0].clone(),
+          None => Value::Null,
+        },
+        WindowFunc::LastValue => match frame_rows(&frame, pos, &part, &peers) {
+          Some((_, end)) => arg_values[part[end]][0].clone(),
+          None => Value::Null,
+        },
+        WindowFunc::Aggregate(agg) => {
+          let star = args.is_empty();
+          let (start, end) = match frame_rows(&frame, pos, &part, &peers) {
+            Some((start, end)) => (start, end + 1),
+            None => (pos, pos),
+          };
+          acc.advance(
+            start,
+            end,
+            || Accumulator::new(agg, star),
+            |p| arg_values[part[p]].first().cloned().unwrap_or(Value::Null),
+          )?
+        }
+      };
+    }
+  }
+  Ok(out)
+}
+
+/// For every position of a sorted partition, the first and last position of the rows that
+/// sort equal to it.
+struct Peers {
+  first: Vec<usize>,
+  last: Vec<usize>,
+}
+
+impl Peers {
+  fn new(part: &[usize], compare: impl Fn(usize, usize) -> Ordering) -> Self {
+    let n = part.len();
+    let mut first = vec![0; n];
+    let mut last = vec![0; n];
+    for pos in 0..n {
+      let peer = pos > 0 && compare(part[pos - 1], part[pos]).is_eq();
+      first[pos] = if peer { first[pos - 1] } else { pos };
+    }
+    for pos in (0..n).rev() {
+      let peer = pos + 1 < n && compare(part[pos], part[pos + 1]).is_eq();
+      last[pos] = if peer { last[pos + 1] } else { pos };
+    }
+    Self { first, last }
+  }
+}
+
+/// The positions, inclusive, the frame of the row at `pos` spans; None when it is empty.
+fn frame_rows(
+  frame: &WindowFrame,
+  pos: usize,
+  part: &[usize],
+  peers: &Peers,
+) -> Option<(usize, usize)> {
+  let bound = |bound: FrameBound, start: bool| -> i64 {
+    match bound {
+      FrameBound::UnboundedPreceding => 0,
+      FrameBound::Preceding(n) => pos as i64 - n as i64,
+      FrameBound::CurrentRow if frame.range && start => peers.first[pos] as i64,
+      FrameBound::CurrentRow if frame.range => peers.last[pos] as i64,
+      FrameBound::CurrentRow => pos as i64,
+      FrameBound::Following(n) => pos as i64 + n as i64,
+      FrameBound::UnboundedFollowing => part.len() as i64 - 1,
+    }
+  };
+  let start = bound(frame.start, true).max(0);
+  let end = bound(frame.end, false).min(part.len() as i64 - 1);
+  (start <= end).then_some((start as usize, end as usize))
+}
+
+/// An aggregate over the frame `[start, end)` of successive rows. Frames that only grow at
+/// the end, like the default running one, are folded in incrementally; any other move starts
+/// over.
+#[derive(Default)]
+struct RunningAggregate {
+  acc: Option<Accumulator>,
+  start: usize,
+  end: usize,
+}
+
+impl RunningAggregate {
+  fn advance(
+    &mut self,
+    start: usize,
+    end: usize,
+    new: impl Fn() -> Accumulator,
+    arg: impl Fn(usize) -> Value,
+  ) -> Result<Value, ExecErr> {
+    if self.acc.is_none() || start != self.start || end < self.end {
+      self.acc = Some(new());
+      self.start = start;
+      self.end = start;
+    }
+    let acc = self.acc.as_mut().unwrap();
+    for pos in self.end..end {
+      acc.step(arg(pos))?;
+    }
+    self.end = end;
+    Ok(acc.finish())
+  }
+}
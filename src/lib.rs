@@ -1,69 +1,47 @@
 mod btree;
+mod connection;
 mod cursor;
 pub mod error;
+mod exec;
+mod mapping;
 mod pager;
 mod row;
+mod sql;
+mod statement;
+mod stats;
 mod table;
+mod value;
+mod vdbe;
 
 use std::num::IntErrorKind;
-use std::process;
+use std::rc::Rc;
 
 use lazy_static::lazy_static;
 use regex::Regex;
 
-use error::{DbError, ExecErr, MetaCmdErr, PrepareErr};
-use row::Row;
+use error::PrepareErr;
+use sql::ast::{Expr, Statement as Stmt, Variables};
 
-pub use table::Table;
+pub use connection::{
+  Connection, Cursor, Options, Row, Rows, DEFAULT_PAGE_SIZE, LEAF_NODE_MAX_CELLS, ROW_SIZE,
+};
+pub use statement::Statement;
+pub use value::Value;
+pub use vdbe::Explain;
 
-pub fn run_cmd(cmd_str: &str, table: &mut Table) -> Result<(), DbError> {
-  if cmd_str.starts_with('.') {
-    return do_meta_command(cmd_str, table).map_err(DbError::MetaCmdErr);
-  }
-
-  let statement = prepare_statement(cmd_str).map_err(DbError::PrepareErr)?;
-
-  execute_statement(&statement, table).map_err(DbError::ExecErr)
-}
-
-fn do_meta_command(cmd_str: &str, table: &Table) -> Result<(), MetaCmdErr> {
-  match cmd_str {
-    ".exit" => {
-      table.close_db().unwrap_or_else(|e| {
-        eprintln!("{e:?}");
-        process::exit(1);
-      });
-      process::exit(0);
-    }
-    ".constants" => {
-      println!("Constants:");
-      print_constants();
-    }
-    ".btree" => {
-      println!("Tree:");
-      println!("{}", table.btree_to_str());
-    }
-    _ => {
-      return Err(MetaCmdErr::Unrecognized(format!(
-        "Unrecognized command {cmd_str:?}."
-      )));
-    }
-  }
-  Ok(())
-}
-
-enum Statement {
-  Insert(Box<Row>),
-  Select,
-}
-
-fn prepare_statement(cmd_str: &str) -> Result<Statement, PrepareErr> {
+/// Parse a statement, along with the parameters it takes. With `params`, the values of an
+/// insert are parameters when written as one, so `insert ? :name ?3` takes three; without,
+/// they are text as written, so `insert 1 :bob x@y` stores `:bob`.
+pub(crate) fn prepare_statement(
+  cmd_str: &str,
+  params: bool,
+) -> Result<(Stmt, Variables), PrepareErr> {
   lazy_static! {
     static ref RE_INSERT: Regex = Regex::new(
       r"(?x)
             insert
             \s+
-            (-?\d+)      # id
+            (-?\d+|\?\d*|:\w+)      # id
             \s+
             ([^\s]+)    # username
             \s+
@@ -76,46 +54,85 @@ fn prepare_statement(cmd_str: &str) -> Result<Statement, PrepareErr> {
   match cmd_str {
     s if s.starts_with("insert") => match RE_INSERT.captures(cmd_str) {
       Some(cap) => {
-        let id = match cap[1].parse::<u32>() {
-          Ok(v) => v,
-          Err(e) if e.kind() == &IntErrorKind::InvalidDigit => {
-            return Err(PrepareErr::NegativeId("ID must be positive.".to_string()))
+        let mut variables = Variables::default();
+        let mut variable = |field: &str| -> Result<Option<Expr>, PrepareErr> {
+          if !params || !is_variable(field) {
+            return Ok(None);
           }
-          Err(_) => return Err(PrepareErr::SyntaxErr(syntax_err)),
+          let number = variables.number(field).map_err(PrepareErr::SyntaxErr)?;
+          Ok(Some(Expr::Variable(number)))
+        };
+        let (id, username, email) = (variable(&cap[1])?, variable(&cap[2])?, variable(&cap[3])?);
+        let key = match id {
+          Some(_) => 0,
+          None => match cap[1].parse::<u32>() {
+            Ok(v) => v,
+            Err(e) if e.kind() == &IntErrorKind::InvalidDigit && cap[1].starts_with('-') => {
+              return Err(PrepareErr::NegativeId("ID must be positive.".to_string()))
+            }
+            Err(_) => return Err(PrepareErr::SyntaxErr(syntax_err)),
+          },
         };
-        Ok(Statement::Insert(Box::new(Row::build(
-          id, &cap[2], &cap[3],
-        )?)))
+        // check the literal values now, bound ones are checked as they are inserted
+        let literal = |value: &Option<Expr>, text| if value.is_some() { "" } else { text };
+        row::Row::build(key, literal(&username, &cap[2]), literal(&email, &cap[3]))?;
+        let text = |value: Option<Expr>, text: &str| {
+          value.unwrap_or_else(|| Expr::Literal(Value::Text(text.to_string())))
+        };
+        let values = [
+          id.unwrap_or(Expr::Literal(Value::Integer(key.into()))),
+          text(username, &cap[2]),
+          text(email, &cap[3]),
+        ];
+        Ok((Stmt::Insert(Box::new(values)), variables))
       }
       None => Err(PrepareErr::SyntaxErr(syntax_err)),
     },
-    s if s.starts_with("select") => Ok(Statement::Select),
+    s if s.starts_with("explain") => {
+      let mut rest = s["explain".len()..].trim_start();
+      let mut query_plan = false;
+      if let Some(more) = rest.strip_prefix("query") {
+        rest = more
+          .trim_start()
+          .strip_prefix("plan")
+          .ok_or_else(|| PrepareErr::SyntaxErr(syntax_err.clone()))?
+          .trim_start();
+        query_plan = true;
+      }
+      match prepare_statement(rest, params)? {
+        (Stmt::Explain { .. }, _) => Err(PrepareErr::SyntaxErr(syntax_err)),
+        (stmt, variables) => Ok((
+          Stmt::Explain {
+            query_plan,
+            stmt: Box::new(stmt),
+          },
+          variables,
+        )),
+      }
+    }
+    s if s.starts_with("analyze") => match s["analyze".len()..].trim() {
+      "" | "users" => Ok((Stmt::Analyze, Variables::default())),
+      _ => Err(PrepareErr::SyntaxErr(syntax_err)),
+    },
+    s if s.starts_with("pragma") => match s["pragma".len()..].trim() {
+      "integrity_check" => Ok((Stmt::IntegrityCheck, Variables::default())),
+      name => Err(PrepareErr::SyntaxErr(format!("Unknown pragma: {name}."))),
+    },
+    s if s.starts_with("select") || s.starts_with("with") => {
+      let (select, variables) = sql::parse_select(cmd_str)?;
+      Ok((Stmt::Select(Rc::new(select)), variables))
+    }
     _ => Err(PrepareErr::Unrecognized(format!(
       "Unrecognized keyword at start of {cmd_str:?}."
     ))),
   }
 }
 
-fn execute_statement(stmt: &Statement, table: &mut Table) -> Result<(), ExecErr> {
-  use Statement::*;
-  match stmt {
-    Insert(row) => table.insert_row(row.key, row),
-    Select => execute_select(table),
+/// Whether an insert value is written as a parameter: `?`, `?NNN` or `:name`.
+fn is_variable(field: &str) -> bool {
+  match field.split_at(1) {
+    ("?", digits) => digits.chars().all(|c| c.is_ascii_digit()),
+    (":", name) => !name.is_empty() && name.chars().all(|c| c.is_alphanumeric() || c == '_'),
+    _ => false,
   }
 }
-
-fn execute_select(table: &mut Table) -> Result<(), ExecErr> {
-  let mut cursor = table.new_cursor_by_key(0); // cursor at start of table
-  while !cursor.at_end {
-    let row = table.select_row(&cursor);
-    println!("{row}");
-    table.advance_cursor(&mut cursor);
-  }
-  Ok(())
-}
-
-fn print_constants() {
-  use btree::leaf::MAX_CELLS;
-  println!("ROW_SIZE:                  {}", row::ROW_SIZE);
-  println!("LEAF_NODE_MAX_CELLS:       {}", MAX_CELLS);
-}
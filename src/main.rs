@@ -1,18 +1,19 @@
-use sqlite_rs::Table;
+use sqlite_rs::error::{DbError, MetaCmdErr};
+use sqlite_rs::{Connection, Explain, Options, Value};
 use std::io::Write;
 use std::process;
 
 fn main() {
   let args: Vec<_> = std::env::args().skip(1).collect();
-  let filename = parse_args(&args).unwrap_or_else(|e| {
-                                    eprintln!("{e}");
-                                    process::exit(1);
-                                  });
+  let (filename, options) = parse_args(&args).unwrap_or_else(|e| {
+                                               eprintln!("{e}");
+                                               process::exit(1);
+                                             });
 
-  let mut table = Table::open_db(filename).unwrap_or_else(|e| {
-                                            eprintln!("{e}");
-                                            process::exit(1);
-                                          });
+  let mut conn = Connection::open_with(filename, options).unwrap_or_else(|e| {
+                                                           eprintln!("{e}");
+                                                           process::exit(1);
+                                                         });
 
   loop {
     let mut cmd_line = String::new();
@@ -20,22 +21,179 @@ fn main() {
     print_prompt();
     read_command(&mut cmd_line);
 
-    use sqlite_rs::error::DbError;
-    match sqlite_rs::run_cmd(&cmd_line, &mut table) {
+    let res = match cmd_line.as_str() {
+      ".exit" => break,
+      cmd if cmd.starts_with('.') => do_meta_command(cmd, &mut conn),
+      cmd => run_statement(cmd, &mut conn),
+    };
+    match res {
       Ok(_) => println!("Executed."),
-      Err(DbError::MetaCmdErr(e)) => eprintln!("{e}"),
-      Err(DbError::PrepareErr(e)) => eprintln!("{e}"),
-      Err(DbError::ExecErr(e)) => eprintln!("{e}"),
+      Err(e) => eprintln!("{e}"),
     }
   }
+  conn.close().unwrap_or_else(|e| {
+                eprintln!("{e}");
+                process::exit(1);
+              });
 }
 
-fn parse_args(args: &[String]) -> Result<&str, String> {
-  if args.is_empty() {
-    Err("Must supply a database filename.".to_string())
+fn do_meta_command(cmd_str: &str, conn: &mut Connection) -> Result<(), DbError> {
+  let mut words = cmd_str.split_whitespace();
+  match words.next().unwrap_or_default() {
+    ".constants" => {
+      println!("Constants:");
+      println!("ROW_SIZE:                  {}", sqlite_rs::ROW_SIZE);
+      println!("PAGE_SIZE:                 {}", conn.page_size());
+      println!("LEAF_NODE_MAX_CELLS:       {}", conn.leaf_max_cells());
+    }
+    ".check" => {
+      let problems = conn.integrity_check();
+      if problems.is_empty() {
+        println!("ok");
+      }
+      for line in problems {
+        println!("{line}");
+      }
+    }
+    ".btree" => {
+      let tree = conn.btree()?;
+      println!("Tree:");
+      println!("{tree}");
+    }
+    ".import" => import(&words.collect::<Vec<_>>(), conn)?,
+    _ => {
+      return Err(MetaCmdErr::Unrecognized(format!("Unrecognized command {cmd_str:?}.")).into());
+    }
+  }
+  Ok(())
+}
+
+/// `.import [--sorted [--fill F]] FILE`: insert the rows of FILE, a line of `id,username,email`
+/// each. With `--sorted`, the rows must come in ascending id order into an empty table, and
+/// are bulk loaded with every node of the tree filled to F of its capacity, 1 by default.
+fn import(args: &[&str], conn: &mut Connection) -> Result<(), DbError> {
+  let usage = || MetaCmdErr::InvalidArgs("Usage: .import [--sorted [--fill F]] FILE".to_string());
+  let (mut sorted, mut fill, mut filename) = (false, None, None);
+  let mut args = args.iter();
+  while let Some(&arg) = args.next() {
+    match arg {
+      "--sorted" => sorted = true,
+      "--fill" => {
+        let value = args.next().and_then(|f| f.parse::<f64>().ok()).ok_or_else(usage)?;
+        fill = Some(value);
+      }
+      _ if filename.is_none() && !arg.starts_with("--") => filename = Some(arg),
+      _ => return Err(usage().into()),
+    }
+  }
+  let filename = filename.ok_or_else(usage)?;
+  if fill.is_some() && !sorted {
+    return Err(usage().into());
+  }
+
+  let text = std::fs::read_to_string(filename).map_err(|e| {
+                                                  MetaCmdErr::InvalidArgs(format!("Cannot read {filename}: {e}."))
+                                                })?;
+  let mut rows = vec![];
+  for (n, line) in text.lines().enumerate().filter(|(_, line)| !line.trim().is_empty()) {
+    let fields: Vec<_> = line.split(',').map(str::trim).collect();
+    match (&fields[..], fields[0].parse::<u32>()) {
+      ([_, username, email], Ok(id)) => rows.push((id, *username, *email)),
+      _ => {
+        let msg = format!("{filename}:{}: expected id,username,email.", n + 1);
+        return Err(MetaCmdErr::InvalidArgs(msg).into());
+      }
+    }
+  }
+
+  if sorted {
+    conn.bulk_load(rows, fill.unwrap_or(1.0))?;
   } else {
-    Ok(&args[0])
+    let mut stmt = conn.prepare("insert ? ? ?")?;
+    for (id, username, email) in rows {
+      stmt.reset();
+      stmt.bind_int(1, id.into())?;
+      stmt.bind_text(2, username)?;
+      stmt.bind_text(3, email)?;
+      while stmt.step(conn)?.is_some() {}
+    }
+  }
+  Ok(())
+}
+
+/// Run a statement, printing the rows it yields.
+fn run_statement(sql: &str, conn: &mut Connection) -> Result<(), DbError> {
+  let mut stmt = conn.prepare_literal(sql)?;
+  match stmt.explain() {
+    None => {
+      while let Some(values) = stmt.step(conn)? {
+        let fields: Vec<_> = values.iter().map(|v| v.to_string()).collect();
+        println!("({})", fields.join(", "));
+      }
+    }
+    Some(Explain::Program) => {
+      println!("addr  opcode         p1    p2    p3    p4             comment");
+      println!("----  -------------  ----  ----  ----  -------------  -------------");
+      while let Some(values) = stmt.step(conn)? {
+        let fields: Vec<_> = values.iter()
+                                   .map(|v| match v {
+                                     Value::Null => String::new(),
+                                     v => v.to_text(),
+                                   })
+                                   .collect();
+        let line = format!("{:<4}  {:<13}  {:<4}  {:<4}  {:<4}  {:<13}  {}",
+                           fields[0], fields[1], fields[2], fields[3], fields[4], fields[5], fields[6]);
+        println!("{}", line.trim_end());
+      }
+    }
+    Some(Explain::QueryPlan) => {
+      let mut lines = vec![];
+      while let Some(values) = stmt.step(conn)? {
+        if let [Value::Integer(id), Value::Integer(parent), detail] = &values[..] {
+          lines.push((*id, *parent, detail.to_text()));
+        }
+      }
+      if !lines.is_empty() {
+        println!("QUERY PLAN");
+        print_plan(&lines, 0, "");
+      }
+    }
+  }
+  Ok(())
+}
+
+/// Print the lines of a query plan under `parent` as a tree, the way the sqlite3 shell does.
+fn print_plan(lines: &[(i64, i64, String)], parent: i64, indent: &str) {
+  let children: Vec<_> = lines.iter().filter(|(_, p, _)| *p == parent).collect();
+  for (i, (id, _, detail)) in children.iter().enumerate() {
+    let last = i + 1 == children.len();
+    println!("{indent}{}{detail}", if last { "`--" } else { "|--" });
+    print_plan(lines,
+               *id,
+               &format!("{indent}{}", if last { "   " } else { "|  " }));
+  }
+}
+
+/// The database filename, and the options to create it with: `--checksums` turns on page
+/// checksums for a new file, and `--page-size N` sets the size of its pages.
+fn parse_args(args: &[String]) -> Result<(&str, Options), String> {
+  let mut options = Options::default();
+  let mut filename = None;
+  let mut args = args.iter();
+  while let Some(arg) = args.next() {
+    match arg.as_str() {
+      "--checksums" => options.checksums = true,
+      "--page-size" => {
+        let size = args.next().ok_or("Missing value for \"--page-size\".")?;
+        let size = size.parse().map_err(|_| format!("Invalid page size {size:?}."))?;
+        options.page_size = Some(size);
+      }
+      flag if flag.starts_with("--") => return Err(format!("Unknown option {flag:?}.")),
+      name => filename = filename.or(Some(name)),
+    }
   }
+  filename.map(|name| (name, options))
+          .ok_or_else(|| "Must supply a database filename.".to_string())
 }
 
 fn print_prompt() {
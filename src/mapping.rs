@@ -0,0 +1,607 @@
+use crate::connection::Row;
+use crate::error::{DbError, ExecErr};
+use crate::value::Value;
+use serde::de::{self, DeserializeSeed, IntoDeserializer, MapAccess, SeqAccess, Visitor};
+use serde::ser::{self, Impossible, Serialize, SerializeMap, SerializeStruct};
+use std::fmt;
+
+/// Why a row could not be mapped to or from a Rust value.
+#[derive(Debug)]
+pub struct Error(String);
+
+impl fmt::Display for Error {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}", self.0)
+  }
+}
+
+impl std::error::Error for Error {}
+
+impl de::Error for Error {
+  fn custom<T: fmt::Display>(msg: T) -> Self {
+    Self(msg.to_string())
+  }
+}
+
+impl ser::Error for Error {
+  fn custom<T: fmt::Display>(msg: T) -> Self {
+    Self(msg.to_string())
+  }
+}
+
+impl From<Error> for DbError {
+  fn from(e: Error) -> Self {
+    DbError::ExecErr(ExecErr::MappingError(e.0))
+  }
+}
+
+/// Reads a row as a map from column name to value, for structs and maps, or as a sequence of
+/// values, for tuples. A row of a single column also reads as that column's value.
+pub struct RowDeserializer<'a> {
+  pub row: &'a Row,
+}
+
+impl<'a> RowDeserializer<'a> {
+  fn single(&self) -> Result<ValueDeserializer<'a>, Error> {
+    match self.row.values() {
+      [value] => Ok(ValueDeserializer(value)),
+      values => Err(Error(format!(
+        "Cannot read a row of {} columns as a single value.",
+        values.len()
+      ))),
+    }
+  }
+}
+
+macro_rules! single_column {
+  ($($method:ident)*) => {$(
+    fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+      self.single()?.$method(visitor)
+    }
+  )*};
+}
+
+impl<'de> de::Deserializer<'de> for RowDeserializer<'_> {
+  type Error = Error;
+
+  fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+    self.deserialize_map(visitor)
+  }
+
+  fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+    visitor.visit_map(Columns {
+      row: self.row,
+      idx: 0,
+    })
+  }
+
+  fn deserialize_struct<V: Visitor<'de>>(
+    self,
+    _name: &'static str,
+    _fields: &'static [&'static str],
+    visitor: V,
+  ) -> Result<V::Value, Error> {
+    self.deserialize_map(visitor)
+  }
+
+  fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+    visitor.visit_seq(Values(self.row.values().iter()))
+  }
+
+  fn deserialize_tuple<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value, Error> {
+    self.deserialize_seq(visitor)
+  }
+
+  fn deserialize_tuple_struct<V: Visitor<'de>>(
+    self,
+    _name: &'static str,
+    _len: usize,
+    visitor: V,
+  ) -> Result<V::Value, Error> {
+    self.deserialize_seq(visitor)
+  }
+
+  fn deserialize_newtype_struct<V: Visitor<'de>>(
+    self,
+    _name: &'static str,
+    visitor: V,
+  ) -> Result<V::Value, Error> {
+    visitor.visit_newtype_struct(self)
+  }
+
+  fn deserialize_unit_struct<V: Visitor<'de>>(
+    self,
+    name: &'static str,
+    visitor: V,
+  ) -> Result<V::Value, Error> {
+    self.single()?.deserialize_unit_struct(name, visitor)
+  }
+
+  fn deserialize_enum<V: Visitor<'de>>(
+    self,
+    name: &'static str,
+    variants: &'static [&'static str],
+    visitor: V,
+  ) -> Result<V::Value, Error> {
+    self.single()?.deserialize_enum(name, variants, visitor)
+  }
+
+  single_column! {
+    deserialize_bool deserialize_i8 deserialize_i16 deserialize_i32 deserialize_i64
+    deserialize_u8 deserialize_u16 deserialize_u32 deserialize_u64 deserialize_f32
+    deserialize_f64 deserialize_char deserialize_str deserialize_string deserialize_bytes
+    deserialize_byte_buf deserialize_option deserialize_unit deserialize_identifier
+    deserialize_ignored_any
+  }
+}
+
+/// The columns of a row, as map entries keyed by column name.
+struct Columns<'a> {
+  row: &'a Row,
+  idx: usize,
+}
+
+impl<'de> MapAccess<'de> for Columns<'_> {
+  type Error = Error;
+
+  fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>, Error> {
+    match self.row.column_names().get(self.idx) {
+      Some(name) => seed
+        .deserialize(name.as_str().into_deserializer())
+        .map(Some),
+      None => Ok(None),
+    }
+  }
+
+  fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, Error> {
+    let value = &self.row.values()[self.idx];
+    self.idx += 1;
+    seed.deserialize(ValueDeserializer(value))
+  }
+}
+
+struct Values<'a>(std::slice::Iter<'a, Value>);
+
+impl<'de> SeqAccess<'de> for Values<'_> {
+  type Error = Error;
+
+  fn next_element_seed<T: DeserializeSeed<'de>>(
+    &mut self,
+    seed: T,
+  ) -> Result<Option<T::Value>, Error> {
+    self
+      .0
+      .next()
+      .map(|value| seed.deserialize(ValueDeserializer(value)))
+      .transpose()
+  }
+}
+
+/// Reads a single value. Integers read as any integer type that holds them and as floats,
+/// NULL reads as `None` or `()`, and any value reads as a string of its text.
+struct ValueDeserializer<'a>(&'a Value);
+
+impl<'de> de::Deserializer<'de> for ValueDeserializer<'_> {
+  type Error = Error;
+
+  fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+    match self.0 {
+      Value::Null => visitor.visit_unit(),
+      Value::Integer(v) => visitor.visit_i64(*v),
+      Value::Real(v) => visitor.visit_f64(*v),
+      Value::Text(s) => visitor.visit_str(s),
+    }
+  }
+
+  fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+    match self.0.truthy() {
+      Some(b) => visitor.visit_bool(b),
+      None => self.deserialize_any(visitor),
+    }
+  }
+
+  fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+    match self.0 {
+      Value::Null => visitor.visit_none(),
+      _ => visitor.visit_some(self),
+    }
+  }
+
+  fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+    match self.0 {
+      Value::Text(s) => visitor.visit_str(s),
+      Value::Null => visitor.visit_unit(),
+      value => visitor.visit_string(value.to_text()),
+    }
+  }
+
+  fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+    self.deserialize_str(visitor)
+  }
+
+  fn deserialize_bytes<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+    match self.0 {
+      Value::Text(s) => visitor.visit_bytes(s.as_bytes()),
+      _ => self.deserialize_any(visitor),
+    }
+  }
+
+  fn deserialize_byte_buf<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+    self.deserialize_bytes(visitor)
+  }
+
+  fn deserialize_newtype_struct<V: Visitor<'de>>(
+    self,
+    _name: &'static str,
+    visitor: V,
+  ) -> Result<V::Value, Error> {
+    visitor.visit_newtype_struct(self)
+  }
+
+  /// A unit variant, named by the text.
+  fn deserialize_enum<V: Visitor<'de>>(
+    self,
+    _name: &'static str,
+    _variants: &'static [&'static str],
+    visitor: V,
+  ) -> Result<V::Value, Error> {
+    match self.0 {
+      Value::Text(s) => visitor.visit_enum(s.as_str().into_deserializer()),
+      _ => self.deserialize_any(visitor),
+    }
+  }
+
+  serde::forward_to_deserialize_any! {
+    i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char unit unit_struct seq tuple
+    tuple_struct map struct identifier ignored_any
+  }
+}
+
+/// The fields of `value`, a struct or map, as column names and values.
+pub fn to_columns<T: Serialize + ?Sized>(value: &T) -> Result<Vec<(String, Value)>, Error> {
+  value.serialize(RowSerializer)
+}
+
+struct RowSerializer;
+
+/// Collects the entries of a struct or map.
+struct Fields {
+  fields: Vec<(String, Value)>,
+  key: Option<String>, // of a map entry whose value is next
+}
+
+fn unsupported<T>(what: &str) -> Result<T, Error> {
+  Err(Error(format!(
+    "Cannot insert {what}: only structs and maps map to rows."
+  )))
+}
+
+macro_rules! not_a_row {
+  ($($method:ident($($arg:ty),*) $what:literal;)*) => {$(
+    fn $method(self, $(_: $arg),*) -> Result<Self::Ok, Error> {
+      unsupported($what)
+    }
+  )*};
+}
+
+impl ser::Serializer for RowSerializer {
+  type Ok = Vec<(String, Value)>;
+  type Error = Error;
+  type SerializeSeq = Impossible<Self::Ok, Error>;
+  type SerializeTuple = Impossible<Self::Ok, Error>;
+  type SerializeTupleStruct = Impossible<Self::Ok, Error>;
+  type SerializeTupleVariant = Impossible<Self::Ok, Error>;
+  type SerializeMap = Fields;
+  type SerializeStruct = Fields;
+  type SerializeStructVariant = Impossible<Self::Ok, Error>;
+
+  not_a_row! {
+    serialize_bool(bool) "a bool";
+    serialize_i8(i8) "an integer";
+    serialize_i16(i16) "an integer";
+    serialize_i32(i32) "an integer";
+    serialize_i64(i64) "an integer";
+    serialize_u8(u8) "an integer";
+    serialize_u16(u16) "an integer";
+    serialize_u32(u32) "an integer";
+    serialize_u64(u64) "an integer";
+    serialize_f32(f32) "a float";
+    serialize_f64(f64) "a float";
+    serialize_char(char) "a char";
+    serialize_str(&str) "a string";
+    serialize_bytes(&[u8]) "bytes";
+    serialize_none() "None";
+    serialize_unit() "()";
+    serialize_unit_struct(&'static str) "a unit struct";
+    serialize_unit_variant(&'static str, u32, &'static str) "an enum";
+  }
+
+  fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<Self::Ok, Error> {
+    value.serialize(self)
+  }
+
+  fn serialize_newtype_struct<T: Serialize + ?Sized>(
+    self,
+    _name: &'static str,
+    value: &T,
+  ) -> Result<Self::Ok, Error> {
+    value.serialize(self)
+  }
+
+  fn serialize_newtype_variant<T: Serialize + ?Sized>(
+    self,
+    _name: &'static str,
+    _index: u32,
+    _variant: &'static str,
+    _value: &T,
+  ) -> Result<Self::Ok, Error> {
+    unsupported("an enum")
+  }
+
+  fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq, Error> {
+    unsupported("a sequence")
+  }
+
+  fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple, Error> {
+    unsupported("a tuple")
+  }
+
+  fn serialize_tuple_struct(
+    self,
+    _name: &'static str,
+    _len: usize,
+  ) -> Result<Self::SerializeTupleStruct, Error> {
+    unsupported("a tuple struct")
+  }
+
+  fn serialize_tuple_variant(
+    self,
+    _name: &'static str,
+    _index: u32,
+    _variant: &'static str,
+    _len: usize,
+  ) -> Result<Self::SerializeTupleVariant, Error> {
+    unsupported("an enum")
+  }
+
+  fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, Error> {
+    Ok(Fields {
+      fields: vec![],
+      key: None,
+    })
+  }
+
+  fn serialize_struct(
+    self,
+    _name: &'static str,
+    len: usize,
+  ) -> Result<Self::SerializeStruct, Error> {
+    self.serialize_map(Some(len))
+  }
+
+  fn serialize_struct_variant(
+    self,
+    _name: &'static str,
+    _index: u32,
+    _variant: &'static str,
+    _len: usize,
+  ) -> Result<Self::SerializeStructVariant, Error> {
+    unsupported("an enum")
+  }
+}
+
+impl SerializeStruct for Fields {
+  type Ok = Vec<(String, Value)>;
+  type Error = Error;
+
+  fn serialize_field<T: Serialize + ?Sized>(
+    &mut self,
+    key: &'static str,
+    value: &T,
+  ) -> Result<(), Error> {
+    self
+      .fields
+      .push((key.to_string(), value.serialize(ValueSerializer)?));
+    Ok(())
+  }
+
+  fn end(self) -> Result<Self::Ok, Error> {
+    Ok(self.fields)
+  }
+}
+
+impl SerializeMap for Fields {
+  type Ok = Vec<(String, Value)>;
+  type Error = Error;
+
+  fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), Error> {
+    match key.serialize(ValueSerializer)? {
+      Value::Text(key) => self.key = Some(key),
+      _ => return Err(Error("Column names must be strings.".to_string())),
+    }
+    Ok(())
+  }
+
+  fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+    let key = self.key.take().expect("serialize_key comes first");
+    self.fields.push((key, value.serialize(ValueSerializer)?));
+    Ok(())
+  }
+
+  fn end(self) -> Result<Self::Ok, Error> {
+    Ok(self.fields)
+  }
+}
+
+/// Turns a field into a value: integers and bools into INTEGER, floats into REAL, strings,
+/// chars, bytes of UTF-8 and unit variants into TEXT, and `None` and `()` into NULL.
+struct ValueSerializer;
+
+impl ser::Serializer for ValueSerializer {
+  type Ok = Value;
+  type Error = Error;
+  type SerializeSeq = Impossible<Value, Error>;
+  type SerializeTuple = Impossible<Value, Error>;
+  type SerializeTupleStruct = Impossible<Value, Error>;
+  type SerializeTupleVariant = Impossible<Value, Error>;
+  type SerializeMap = Impossible<Value, Error>;
+  type SerializeStruct = Impossible<Value, Error>;
+  type SerializeStructVariant = Impossible<Value, Error>;
+
+  fn serialize_bool(self, v: bool) -> Result<Value, Error> {
+    Ok(Value::Integer(v.into()))
+  }
+
+  fn serialize_i8(self, v: i8) -> Result<Value, Error> {
+    Ok(Value::Integer(v.into()))
+  }
+
+  fn serialize_i16(self, v: i16) -> Result<Value, Error> {
+    Ok(Value::Integer(v.into()))
+  }
+
+  fn serialize_i32(self, v: i32) -> Result<Value, Error> {
+    Ok(Value::Integer(v.into()))
+  }
+
+  fn serialize_i64(self, v: i64) -> Result<Value, Error> {
+    Ok(Value::Integer(v))
+  }
+
+  fn serialize_u8(self, v: u8) -> Result<Value, Error> {
+    Ok(Value::Integer(v.into()))
+  }
+
+  fn serialize_u16(self, v: u16) -> Result<Value, Error> {
+    Ok(Value::Integer(v.into()))
+  }
+
+  fn serialize_u32(self, v: u32) -> Result<Value, Error> {
+    Ok(Value::Integer(v.into()))
+  }
+
+  fn serialize_u64(self, v: u64) -> Result<Value, Error> {
+    i64::try_from(v)
+      .map(Value::Integer)
+      .map_err(|_| Error(format!("Integer {v} is too large.")))
+  }
+
+  fn serialize_f32(self, v: f32) -> Result<Value, Error> {
+    Ok(Value::Real(v.into()))
+  }
+
+  fn serialize_f64(self, v: f64) -> Result<Value, Error> {
+    Ok(Value::Real(v))
+  }
+
+  fn serialize_char(self, v: char) -> Result<Value, Error> {
+    Ok(Value::Text(v.to_string()))
+  }
+
+  fn serialize_str(self, v: &str) -> Result<Value, Error> {
+    Ok(Value::Text(v.to_string()))
+  }
+
+  fn serialize_bytes(self, v: &[u8]) -> Result<Value, Error> {
+    String::from_utf8(v.to_vec())
+      .map(Value::Text)
+      .map_err(|_| Error("Bytes must be UTF-8 to be stored as text.".to_string()))
+  }
+
+  fn serialize_none(self) -> Result<Value, Error> {
+    Ok(Value::Null)
+  }
+
+  fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<Value, Error> {
+    value.serialize(self)
+  }
+
+  fn serialize_unit(self) -> Result<Value, Error> {
+    Ok(Value::Null)
+  }
+
+  fn serialize_unit_struct(self, _name: &'static str) -> Result<Value, Error> {
+    Ok(Value::Null)
+  }
+
+  fn serialize_unit_variant(
+    self,
+    _name: &'static str,
+    _index: u32,
+    variant: &'static str,
+  ) -> Result<Value, Error> {
+    Ok(Value::Text(variant.to_string()))
+  }
+
+  fn serialize_newtype_struct<T: Serialize + ?Sized>(
+    self,
+    _name: &'static str,
+    value: &T,
+  ) -> Result<Value, Error> {
+    value.serialize(self)
+  }
+
+  fn serialize_newtype_variant<T: Serialize + ?Sized>(
+    self,
+    _name: &'static str,
+    _index: u32,
+    _variant: &'static str,
+    _value: &T,
+  ) -> Result<Value, Error> {
+    unsupported_value()
+  }
+
+  fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq, Error> {
+    unsupported_value()
+  }
+
+  fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple, Error> {
+    unsupported_value()
+  }
+
+  fn serialize_tuple_struct(
+    self,
+    _name: &'static str,
+    _len: usize,
+  ) -> Result<Self::SerializeTupleStruct, Error> {
+    unsupported_value()
+  }
+
+  fn serialize_tuple_variant(
+    self,
+    _name: &'static str,
+    _index: u32,
+    _variant: &'static str,
+    _len: usize,
+  ) -> Result<Self::SerializeTupleVariant, Error> {
+    unsupported_value()
+  }
+
+  fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, Error> {
+    unsupported_value()
+  }
+
+  fn serialize_struct(
+    self,
+    _name: &'static str,
+    _len: usize,
+  ) -> Result<Self::SerializeStruct, Error> {
+    unsupported_value()
+  }
+
+  fn serialize_struct_variant(
+    self,
+    _name: &'static str,
+    _index: u32,
+    _variant: &'static str,
+    _len: usize,
+  ) -> Result<Self::SerializeStructVariant, Error> {
+    unsupported_value()
+  }
+}
+
+fn unsupported_value<T>() -> Result<T, Error> {
+  Err(Error(
+    "A column holds a single value, not a sequence, map or struct.".to_string(),
+  ))
+}
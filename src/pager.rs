@@ -1,45 +1,171 @@
 use crate::btree::node::Node;
 use crate::error::ExecErr;
+use crate::stats::{self, Stats};
 use crate::table::MAX_PAGES;
 use std::cell::RefCell;
 use std::fs::{File, OpenOptions};
 use std::io::{Read, Seek, SeekFrom, Write};
 use std::path::Path;
 
-pub const PAGE_SIZE: usize = 4096;
-pub type Page = [u8; PAGE_SIZE];
+mod crc32c;
+
+/// Page size of a new database unless another is chosen, and of files written before the page
+/// size was recorded in their header.
+pub const DEFAULT_PAGE_SIZE: usize = 4096;
+pub const MIN_PAGE_SIZE: usize = 512;
+pub const MAX_PAGE_SIZE: usize = 65536;
+/// The bytes of a page, as many as the page size of its file.
+pub type Page = Vec<u8>;
+
+/// Bytes at the end of every page that nodes leave alone: a flag byte saying whether the page
+/// carries a checksum, then the CRC-32C of everything before it. Files written before
+/// checksums existed have zeros there, as no node ever filled its page to the end.
+pub const RESERVED_SIZE: usize = 1 + CHECKSUM_SIZE;
+const CHECKSUM_SIZE: usize = std::mem::size_of::<u32>();
+
+/// The file header starts with these bytes, followed by the page size as a big-endian `u32`,
+/// the format version, and whether pages carry checksums, a byte each, then whether ANALYZE
+/// ran, a byte, and the stats it gathered. It takes a page of its own, zero past those
+/// fields, so that every page of the file stays aligned to its size.
+const MAGIC: &[u8; 16] = b"sqlite_rs format";
+const PAGE_SIZE_AT: usize = MAGIC.len();
+const VERSION_AT: usize = PAGE_SIZE_AT + std::mem::size_of::<u32>();
+const CHECKSUMS_AT: usize = VERSION_AT + 1;
+const STATS_AT: usize = CHECKSUMS_AT + 1;
+const HEADER_SIZE: usize = STATS_AT + 1 + stats::SIZE;
+/// Format 2 stores separator keys and a right child in intern nodes. Format 1 files have no
+/// header, pages of `DEFAULT_PAGE_SIZE` from their first byte, and the largest key under every
+/// child in intern nodes; they are converted as they are opened.
+const FORMAT_VERSION: u8 = 2;
+
+fn valid_page_size(page_size: usize) -> bool {
+  page_size.is_power_of_two() && (MIN_PAGE_SIZE..=MAX_PAGE_SIZE).contains(&page_size)
+}
+
+/// Bytes of a page a node may use.
+pub const fn usable_size(page_size: usize) -> usize {
+  page_size - RESERVED_SIZE
+}
 
 pub struct Pager {
   cache: RefCell<Cache>,
   pg_num: usize,
+  checksums: bool, // whether pages are written with, and must be read with, a checksum
+  page_size: usize,
+  offset: usize,        // of page 0 in the file: the size of the header
+  stats: Option<Stats>, // as of the last ANALYZE
 }
 
 struct Cache {
   file: File,
-  pages: [Option<Node>; MAX_PAGES],
+  pages: Vec<Option<Node>>, // a slot per page of the file, filled as pages are read
 }
 
 impl Pager {
-  pub fn new(fname: impl AsRef<Path>) -> Result<Self, ExecErr> {
-    let file = OpenOptions::new()
+  /// Open the file `fname`. A new file gets checksums if `checksums` is set, and pages of
+  /// `page_size` bytes; an existing file keeps the settings it was created with, as recorded
+  /// in its header.
+  pub fn new(fname: impl AsRef<Path>, checksums: bool, page_size: usize) -> Result<Self, ExecErr> {
+    if !valid_page_size(page_size) {
+      return Err(ExecErr::Misuse(format!(
+        "Page size must be a power of two from {MIN_PAGE_SIZE} to {MAX_PAGE_SIZE}, not {page_size}."
+      )));
+    }
+    let mut file = OpenOptions::new()
       .write(true)
       .read(true)
       .create(true)
+      .truncate(false) // an existing file is opened, not emptied
       .open(fname)
-      .map_err(|_| ExecErr::IoError("Unable to open file.".to_string()))?;
+      .map_err(ExecErr::CantOpen)?;
 
-    let file_len = file.metadata().unwrap().len() as usize;
-    let num_pages = file_len / PAGE_SIZE;
-    if file_len % PAGE_SIZE != 0 {
-      return Err(ExecErr::IoError("Corrupted file.".to_string()));
+    let file_len = file
+      .metadata()
+      .map_err(|e| ExecErr::IoError("Fail reading file size.".to_string(), e))?
+      .len() as usize;
+    let (header, offset) = match file_len {
+      0 => (
+        Header {
+          page_size,
+          checksums,
+          stats: None,
+        },
+        page_size,
+      ),
+      _ => match Header::read(&mut file, file_len)? {
+        Some(header) => {
+          let offset = header.page_size;
+          (header, offset)
+        }
+        // format 1, converted below
+        None => (
+          Header {
+            page_size: DEFAULT_PAGE_SIZE,
+            checksums: false,
+            stats: None,
+          },
+          0,
+        ),
+      },
+    };
+    let page_size = header.page_size;
+    let file_len = file_len.saturating_sub(offset); // a new file has no header until flushed
+    let num_pages = file_len / page_size;
+    if !file_len.is_multiple_of(page_size) {
+      let err = ExecErr::Corrupt {
+        page: num_pages,
+        reason: "the file ends partway through the page".to_string(),
+      };
+      return Err(if offset == 0 { not_format_1(err) } else { err });
+    }
+    if num_pages > MAX_PAGES {
+      return Err(ExecErr::Corrupt {
+        page: MAX_PAGES,
+        reason: format!("the file has {num_pages} pages, more than {MAX_PAGES}"),
+      });
     }
-    const INIT: Option<Node> = None;
-    let pages = [INIT; MAX_PAGES];
+    let pages = (0..num_pages).map(|_| None).collect();
 
-    Ok(Self {
+    let mut pager = Self {
       cache: RefCell::new(Cache { file, pages }),
       pg_num: num_pages,
-    })
+      checksums: header.checksums,
+      page_size,
+      offset,
+      stats: header.stats,
+    };
+    if num_pages > 0 && offset == 0 {
+      pager.convert_format_1().map_err(not_format_1)?;
+    }
+    Ok(pager)
+  }
+
+  /// Read every page of a format 1 file from where it is, for the next flush to write them all
+  /// after a header. Without a header, whether the file has checksums is taken from page 0.
+  fn convert_format_1(&mut self) -> Result<(), ExecErr> {
+    self.checksums = has_checksum(0, &self.load_page(0)?)?;
+    for pid in 0..self.pg_num {
+      self.load_node_with(pid, Node::new_from_legacy_page)?;
+    }
+    self.offset = self.page_size;
+    Ok(())
+  }
+
+  pub fn checksums(&self) -> bool {
+    self.checksums
+  }
+
+  pub fn page_size(&self) -> usize {
+    self.page_size
+  }
+
+  pub fn stats(&self) -> Option<&Stats> {
+    self.stats.as_ref()
+  }
+
+  /// Replace the stats the header keeps, written with the pages on the next flush.
+  pub fn set_stats(&mut self, stats: Stats) {
+    self.stats = Some(stats);
   }
 
   pub fn size(&self) -> usize {
@@ -50,13 +176,17 @@ impl Pager {
     if self.size() == MAX_PAGES {
       return Err(ExecErr::PagerFull("pager full".to_string()));
     }
-    let slot = &mut self.cache.borrow_mut().pages[self.size()];
-    assert!(slot.is_none());
-    let _ = slot.insert(node);
+    self.cache.borrow_mut().pages.push(Some(node));
     self.pg_num += 1;
     Ok(())
   }
 
+  /// Drop the pages from `pg_num` on, pushed since the file was last flushed.
+  pub fn truncate(&mut self, pg_num: usize) {
+    self.cache.borrow_mut().pages.truncate(pg_num);
+    self.pg_num = pg_num;
+  }
+
   pub fn replace_node(&mut self, pg_idx: usize, node: Node) -> Option<Node> {
     self.cache.borrow_mut().pages[pg_idx].replace(node)
   }
@@ -82,50 +212,201 @@ impl Pager {
   }
 
   pub fn flush(&self) -> Result<(), ExecErr> {
-    for pid in 0..MAX_PAGES {
+    if self.offset > 0 {
+      let header = Header {
+        page_size: self.page_size,
+        checksums: self.checksums,
+        stats: self.stats.clone(),
+      };
+      self.write_at(0, &header.to_page())?;
+    }
+    for pid in 0..self.pg_num {
       self.write_node(pid)?;
     }
     Ok(())
   }
 
   fn load_node(&self, pg_id: usize) -> Result<(), ExecErr> {
+    self.load_node_with(pg_id, Node::new_from_page)
+  }
+
+  fn load_node_with(
+    &self,
+    pg_id: usize,
+    decode: fn(usize, &Page) -> Result<Node, ExecErr>,
+  ) -> Result<(), ExecErr> {
     let page = self.load_page(pg_id)?;
-    let node = Node::new_from_page(&page);
+    let node = decode(pg_id, &page)?;
+    if let Some(pid) = node.page_refs().into_iter().find(|&pid| pid >= self.pg_num) {
+      return Err(ExecErr::Corrupt {
+        page: pg_id,
+        reason: format!("it points to page {pid}, past the end of the file"),
+      });
+    }
     let _ = self.cache.borrow_mut().pages[pg_id].insert(node);
     Ok(())
   }
 
   fn load_page(&self, pid: usize) -> Result<Page, ExecErr> {
-    let mut buf = [0; PAGE_SIZE];
+    let mut buf = vec![0; self.page_size];
 
     let file = &mut self.cache.borrow_mut().file;
     file
-      .seek(SeekFrom::Start((pid * PAGE_SIZE) as u64))
-      .map_err(|_| ExecErr::IoError("Fail seeking.".to_string()))?;
+      .seek(SeekFrom::Start(self.position(pid)))
+      .map_err(|e| ExecErr::IoError("Fail seeking.".to_string(), e))?;
     file
       .read_exact(&mut buf)
-      .map_err(|_| ExecErr::IoError("Fail reading.".to_string()))?;
+      .map_err(|e| ExecErr::IoError("Fail reading.".to_string(), e))?;
 
+    match (has_checksum(pid, &buf)?, self.checksums) {
+      (true, true) => {
+        let (data, stored) = buf.split_at(self.page_size - CHECKSUM_SIZE);
+        if crc32c::crc32c(data) != u32::from_be_bytes(stored.try_into().unwrap()) {
+          return Err(ExecErr::Corrupt {
+            page: pid,
+            reason: "checksum mismatch".to_string(),
+          });
+        }
+      }
+      (false, true) => {
+        return Err(ExecErr::Corrupt {
+          page: pid,
+          reason: "no checksum".to_string(),
+        })
+      }
+      (true, false) => {
+        return Err(ExecErr::Corrupt {
+          page: pid,
+          reason: "a checksum in a file without them".to_string(),
+        })
+      }
+      (false, false) => {}
+    }
     Ok(buf)
   }
 
   pub fn write_node(&self, pid: usize) -> Result<(), ExecErr> {
     let pg_opt = self.cache.borrow().pages[pid]
       .as_ref()
-      .map(|nd| nd.serialize());
-    if let Some(pg) = pg_opt {
-      self.write_page(pid, &pg)?;
+      .map(|nd| nd.serialize(self.page_size));
+    if let Some(mut pg) = pg_opt {
+      if self.checksums {
+        let end = self.page_size - CHECKSUM_SIZE;
+        pg[usable_size(self.page_size)] = 1;
+        let crc = crc32c::crc32c(&pg[..end]);
+        pg[end..].copy_from_slice(&crc.to_be_bytes());
+      }
+      self.write_at(self.position(pid), &pg)?;
     }
     Ok(())
   }
 
-  fn write_page(&self, pid: usize, page: &Page) -> Result<(), ExecErr> {
+  /// Where page `pid` starts in the file.
+  fn position(&self, pid: usize) -> u64 {
+    (self.offset + pid * self.page_size) as u64
+  }
+
+  fn write_at(&self, pos: u64, bytes: &[u8]) -> Result<(), ExecErr> {
     let file = &mut self.cache.borrow_mut().file;
     file
-      .seek(SeekFrom::Start((pid * PAGE_SIZE) as u64))
-      .map_err(|_| ExecErr::IoError("Fail seeking".to_string()))?;
+      .seek(SeekFrom::Start(pos))
+      .map_err(|e| ExecErr::IoError("Fail seeking.".to_string(), e))?;
     file
-      .write_all(page)
-      .map_err(|_| ExecErr::IoError("Fail writing".to_string()))
+      .write_all(bytes)
+      .map_err(|e| ExecErr::IoError("Fail writing.".to_string(), e))
+  }
+}
+
+/// What a file header records.
+struct Header {
+  page_size: usize,
+  checksums: bool,
+  stats: Option<Stats>,
+}
+
+impl Header {
+  /// Read the header of a file of `file_len` bytes. A file without one is taken for a format 1
+  /// file, with pages of `DEFAULT_PAGE_SIZE` from offset 0, for `Pager::new` to convert.
+  fn read(file: &mut File, file_len: usize) -> Result<Option<Self>, ExecErr> {
+    let mut header = [0; HEADER_SIZE];
+    if file_len >= HEADER_SIZE {
+      file
+        .read_exact(&mut header)
+        .map_err(|e| ExecErr::IoError("Fail reading.".to_string(), e))?;
+    }
+    if !header.starts_with(MAGIC) {
+      return Ok(None);
+    }
+    let not_a_database = |what| {
+      Err(ExecErr::NotADatabase(format!(
+        "File is not a database: {what}."
+      )))
+    };
+    let page_size = u32::from_be_bytes(header[PAGE_SIZE_AT..VERSION_AT].try_into().unwrap());
+    let page_size = page_size as usize;
+    let version = header[VERSION_AT];
+    if version != FORMAT_VERSION {
+      return not_a_database(format!(
+        "its header gives format version {version}, not {FORMAT_VERSION}"
+      ));
+    }
+    if !valid_page_size(page_size) {
+      return not_a_database(format!("its header gives page size {page_size}"));
+    }
+    let checksums = match header[CHECKSUMS_AT] {
+      0 => false,
+      1 => true,
+      b => return not_a_database(format!("its header gives checksum flag {b}")),
+    };
+    let stats = match header[STATS_AT] {
+      0 => None,
+      1 => Some(Stats::from_bytes(&header[STATS_AT + 1..])),
+      b => return not_a_database(format!("its header gives stats flag {b}")),
+    };
+    if file_len < page_size {
+      return not_a_database("it ends partway through the header".to_string());
+    }
+    Ok(Some(Self {
+      page_size,
+      checksums,
+      stats,
+    }))
+  }
+
+  /// The page at the front of the file that holds the header.
+  fn to_page(&self) -> Page {
+    let mut page = vec![0; self.page_size];
+    page[..PAGE_SIZE_AT].copy_from_slice(MAGIC);
+    page[PAGE_SIZE_AT..VERSION_AT].copy_from_slice(&(self.page_size as u32).to_be_bytes());
+    page[VERSION_AT] = FORMAT_VERSION;
+    page[CHECKSUMS_AT] = u8::from(self.checksums);
+    if let Some(stats) = &self.stats {
+      page[STATS_AT] = 1;
+      page[STATS_AT + 1..HEADER_SIZE].copy_from_slice(&stats.to_bytes());
+    }
+    page
+  }
+}
+
+/// A headerless file that is not a format 1 database either, going by the error `err` that
+/// reading it as one gave, is not a database at all.
+fn not_format_1(err: ExecErr) -> ExecErr {
+  match err {
+    ExecErr::Corrupt { page, reason } => ExecErr::NotADatabase(format!(
+      "File is not a database: it has no header, and as a format 1 file, page {page}: {reason}."
+    )),
+    err => err,
+  }
+}
+
+/// Whether page `pid` says it carries a checksum.
+fn has_checksum(pid: usize, page: &Page) -> Result<bool, ExecErr> {
+  match page[usable_size(page.len())] {
+    0 => Ok(false),
+    1 => Ok(true),
+    b => Err(ExecErr::Corrupt {
+      page: pid,
+      reason: format!("unknown checksum flag {b}"),
+    }),
   }
 }
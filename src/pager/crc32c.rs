@@ -0,0 +1,42 @@
+/// CRC-32C (Castagnoli), as used by iSCSI and ext4: reflected polynomial 0x82F63B78, initial
+/// value and final xor of all ones.
+pub fn crc32c(bytes: &[u8]) -> u32 {
+  !bytes.iter().fold(!0, |crc, &b| {
+    TABLE[((crc ^ b as u32) & 0xff) as usize] ^ (crc >> 8)
+  })
+}
+
+const POLY: u32 = 0x82f6_3b78;
+
+/// CRC of every byte value, to process a byte per step instead of a bit.
+static TABLE: [u32; 256] = {
+  let mut table = [0; 256];
+  let mut i = 0;
+  while i < 256 {
+    let mut crc = i as u32;
+    let mut bit = 0;
+    while bit < 8 {
+      crc = if crc & 1 == 1 {
+        (crc >> 1) ^ POLY
+      } else {
+        crc >> 1
+      };
+      bit += 1;
+    }
+    table[i] = crc;
+    i += 1;
+  }
+  table
+};
+
+#[cfg(test)]
+mod tests {
+  use super::crc32c;
+
+  #[test]
+  fn known_values() {
+    assert_eq!(crc32c(b""), 0);
+    assert_eq!(crc32c(b"123456789"), 0xe306_9283);
+    assert_eq!(crc32c(&[0; 32]), 0x8a91_36aa);
+  }
+}
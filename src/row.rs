@@ -1,5 +1,6 @@
 use crate::error::PrepareErr;
-use std::io::{Read, Write};
+use crate::value::Value;
+use std::io::Write;
 use std::{fmt, io, str};
 
 const ID_SIZE: usize = std::mem::size_of::<u32>();
@@ -9,6 +10,9 @@ pub const ROW_SIZE: usize = ID_SIZE + USERNAME_SIZE + EMAIL_SIZE;
 
 pub type RowBytes = [u8; ROW_SIZE];
 
+/// Column names of the `users` table, in storage order.
+pub const COLUMNS: [&str; 3] = ["id", "username", "email"];
+
 #[derive(Debug)]
 pub struct Row {
   pub key: u32,
@@ -33,21 +37,25 @@ impl Row {
   }
 
   pub fn deserialize_from(buf: RowBytes) -> Self {
-    let mut reader = io::Cursor::new(&buf[..]);
-    let key = {
-      let mut buf = [0; ID_SIZE];
-      reader.read_exact(&mut buf).unwrap();
-      u32::from_be_bytes(buf)
-    };
-    let mut username = [0; USERNAME_SIZE];
-    reader.read_exact(&mut username).unwrap();
-    let mut email = [0; EMAIL_SIZE];
-    reader.read_exact(&mut email).unwrap();
+    let (key, rest) = buf.split_at(ID_SIZE);
+    let (username, email) = rest.split_at(USERNAME_SIZE);
     Self {
-      key,
-      username,
-      email,
+      key: u32::from_be_bytes(key.try_into().unwrap()),
+      username: username.try_into().unwrap(),
+      email: email.try_into().unwrap(),
+    }
+  }
+
+  /// Check a row read from a leaf cell of key `key`: it must hold that key, and UTF-8 text.
+  pub fn check(buf: &RowBytes, key: u32) -> Result<(), String> {
+    let row = Self::deserialize_from(*buf);
+    if row.key != key {
+      return Err(format!("row of id {} is stored under key {key}", row.key));
+    }
+    for (name, text) in [("username", &row.username[..]), ("email", &row.email[..])] {
+      str::from_utf8(text).map_err(|_| format!("{name} is not UTF-8"))?;
     }
+    Ok(())
   }
 
   pub fn serialize(&self) -> RowBytes {
@@ -58,14 +66,37 @@ impl Row {
     writer.write_all(&self.email).unwrap();
     buf
   }
+
+  pub fn username(&self) -> &str {
+    text(&self.username)
+  }
+
+  pub fn email(&self) -> &str {
+    text(&self.email)
+  }
+
+  pub fn to_values(&self) -> Vec<Value> {
+    vec![
+      Value::Integer(self.key as i64),
+      Value::Text(self.username().to_string()),
+      Value::Text(self.email().to_string()),
+    ]
+  }
 }
 
 impl fmt::Display for Row {
   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-    let username = str::from_utf8(&self.username)
-      .unwrap()
-      .trim_end_matches('\0');
-    let email = str::from_utf8(&self.email).unwrap().trim_end_matches('\0');
+    let (username, email) = (self.username(), self.email());
     write!(f, "({}, {username:?}, {email:?})", self.key)
   }
 }
+
+/// The text in a column, without its NUL padding. Rows read from disk were checked to be
+/// UTF-8 by `Row::check`; should one slip through, its text ends before the first bad byte.
+fn text(bytes: &[u8]) -> &str {
+  let text = match str::from_utf8(bytes) {
+    Ok(text) => text,
+    Err(e) => str::from_utf8(&bytes[..e.valid_up_to()]).unwrap_or_default(),
+  };
+  text.trim_end_matches('\0')
+}
@@ -0,0 +1,14 @@
+pub mod ast;
+mod lexer;
+mod parser;
+
+use crate::error::PrepareErr;
+use ast::{Select, Variables};
+
+/// Parse a SELECT statement, along with the parameters it takes.
+pub fn parse_select(sql: &str) -> Result<(Select, Variables), PrepareErr> {
+  let tokens = lexer::tokenize(sql)?;
+  let mut parser = parser::Parser::new(tokens);
+  let select = parser.parse_select_stmt()?;
+  Ok((select, parser.variables))
+}
@@ -0,0 +1,474 @@
+use crate::value::Value;
+use std::rc::Rc;
+
+/// A parsed statement.
+#[derive(Debug)]
+pub enum Statement {
+  /// `insert id username email`, each value a literal or a parameter.
+  Insert(Box<[Expr; 3]>),
+  Select(Rc<Select>),
+  Analyze,
+  /// `pragma integrity_check`
+  IntegrityCheck,
+  Explain {
+    query_plan: bool,
+    stmt: Box<Statement>,
+  },
+}
+
+/// A SELECT statement. The fields from `columns` to `having` make up its first (or only) core;
+/// `compound` holds the cores joined to it by set operators, and ORDER BY and LIMIT apply to
+/// the rows of the whole compound.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Select {
+  pub with: Option<With>,
+  pub columns: Vec<ResultColumn>, // empty for the bare `select` shorthand
+  pub from: Option<FromClause>,
+  pub where_clause: Option<Expr>,
+  pub group_by: Vec<Expr>,
+  pub having: Option<Expr>,
+  pub compound: Vec<CompoundArm>,
+  pub order_by: Vec<OrderingTerm>,
+  pub limit: Option<usize>, // None means no limit
+  pub offset: usize,
+}
+
+/// `op core`, where `select` only has the fields of a core set.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompoundArm {
+  pub op: CompoundOp,
+  pub select: Select,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CompoundOp {
+  Union,
+  UnionAll,
+  Intersect,
+  Except,
+}
+
+impl CompoundOp {
+  pub fn name(self) -> &'static str {
+    match self {
+      Self::Union => "UNION",
+      Self::UnionAll => "UNION ALL",
+      Self::Intersect => "INTERSECT",
+      Self::Except => "EXCEPT",
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OrderingTerm {
+  pub expr: Expr,
+  pub desc: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ResultColumn {
+  Star,
+  TableStar(String),
+  Expr { expr: Expr, alias: Option<String> },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct With {
+  pub recursive: bool,
+  pub ctes: Vec<Cte>,
+}
+
+/// `name [(columns)] AS (select)`. With RECURSIVE, the last arm of a compound `select` may
+/// read `name`, which then holds one row produced before.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cte {
+  pub name: String,
+  pub columns: Vec<String>,
+  pub select: Select,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FromClause {
+  pub base: TableRef,
+  pub joins: Vec<Join>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TableRef {
+  pub source: TableSource,
+  pub alias: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TableSource {
+  Named(String), // a table or CTE
+  Subquery(Rc<Select>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Join {
+  pub kind: JoinKind,
+  pub table: TableRef,
+  pub on: Option<Expr>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum JoinKind {
+  Inner,
+  Left,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+  Literal(Value),
+  /// A parameter, by its index from 1, whose value is bound before the statement runs.
+  Variable(usize),
+  Column {
+    table: Option<String>,
+    name: String,
+  },
+  Unary(UnaryOp, Box<Expr>),
+  Binary(BinaryOp, Box<Expr>, Box<Expr>),
+  IsNull {
+    expr: Box<Expr>,
+    negated: bool,
+  },
+  InList {
+    expr: Box<Expr>,
+    list: Vec<Expr>,
+    negated: bool,
+  },
+  InSelect {
+    expr: Box<Expr>,
+    select: Rc<Select>,
+    negated: bool,
+  },
+  Exists(Rc<Select>),
+  /// A scalar subquery: the first column of its first row, or NULL.
+  Subquery(Rc<Select>),
+  /// `arg` is None for COUNT(*)
+  Aggregate {
+    func: AggFunc,
+    arg: Option<Box<Expr>>,
+  },
+  /// `func(args) OVER (...)`; `args` is empty for COUNT(*)
+  Window {
+    func: WindowFunc,
+    args: Vec<Expr>,
+    over: Box<Over>,
+  },
+}
+
+/// The parameters of a statement, numbered from 1 as SQLite numbers them: `?` takes the
+/// number after the largest so far, `?NNN` takes NNN, and `:name` takes the number of an
+/// earlier parameter of the same name, else the next one.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Variables {
+  names: Vec<Option<String>>, // by number, from 1; None for a bare `?`
+}
+
+/// The largest parameter number, as SQLite's default SQLITE_MAX_VARIABLE_NUMBER.
+pub const MAX_VARIABLE: usize = 32766;
+
+impl Variables {
+  /// The number of the parameter written as `var`.
+  pub fn number(&mut self, var: &str) -> Result<usize, String> {
+    if var == "?" {
+      self.names.push(None);
+      return Ok(self.names.len());
+    }
+    if let Some(digits) = var.strip_prefix('?') {
+      let n = digits
+        .parse::<usize>()
+        .ok()
+        .filter(|n| (1..=MAX_VARIABLE).contains(n))
+        .ok_or_else(|| format!("Variable number must be between ?1 and ?{MAX_VARIABLE}."))?;
+      if n > self.names.len() {
+        self.names.resize(n, None);
+      }
+      self.names[n - 1].get_or_insert_with(|| var.to_string());
+      return Ok(n);
+    }
+    Ok(match self.index(var) {
+      Some(n) => n,
+      None => {
+        self.names.push(Some(var.to_string()));
+        self.names.len()
+      }
+    })
+  }
+
+  /// The largest parameter number.
+  pub fn count(&self) -> usize {
+    self.names.len()
+  }
+
+  /// The name of parameter `n`, such as `?2` or `:name`; None for a bare `?`.
+  pub fn name(&self, n: usize) -> Option<&str> {
+    self.names.get(n.checked_sub(1)?)?.as_deref()
+  }
+
+  /// The number of the parameter named `name`.
+  pub fn index(&self, name: &str) -> Option<usize> {
+    let pos = self.names.iter().position(|n| n.as_deref() == Some(name))?;
+    Some(pos + 1)
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WindowFunc {
+  RowNumber,
+  Rank,
+  DenseRank,
+  Lag,
+  Lead,
+  FirstValue,
+  LastValue,
+  Aggregate(AggFunc),
+}
+
+/// `OVER ([PARTITION BY expr, ...] [ORDER BY term, ...] [frame])`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Over {
+  pub partition_by: Vec<Expr>,
+  pub order_by: Vec<OrderingTerm>,
+  pub frame: Option<WindowFrame>,
+}
+
+/// `{ROWS | RANGE} BETWEEN start AND end`, or `{ROWS | RANGE} start` which ends at the current
+/// row. RANGE treats rows with equal ORDER BY values (peers) alike, and only supports
+/// UNBOUNDED and CURRENT ROW bounds.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WindowFrame {
+  pub range: bool,
+  pub start: FrameBound,
+  pub end: FrameBound,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FrameBound {
+  UnboundedPreceding,
+  Preceding(usize),
+  CurrentRow,
+  Following(usize),
+  UnboundedFollowing,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UnaryOp {
+  Neg,
+  Not,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinaryOp {
+  Or,
+  And,
+  Eq,
+  Ne,
+  Lt,
+  Le,
+  Gt,
+  Ge,
+  Add,
+  Sub,
+  Mul,
+  Div,
+  Rem,
+  Concat,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AggFunc {
+  Count,
+  Sum,
+  Avg,
+  Min,
+  Max,
+}
+
+impl AggFunc {
+  pub fn from_name(name: &str) -> Option<Self> {
+    match name.to_ascii_lowercase().as_str() {
+      "count" => Some(Self::Count),
+      "sum" => Some(Self::Sum),
+      "avg" => Some(Self::Avg),
+      "min" => Some(Self::Min),
+      "max" => Some(Self::Max),
+      _ => None,
+    }
+  }
+
+  pub fn name(&self) -> &'static str {
+    match self {
+      Self::Count => "count",
+      Self::Sum => "sum",
+      Self::Avg => "avg",
+      Self::Min => "min",
+      Self::Max => "max",
+    }
+  }
+}
+
+impl WindowFunc {
+  /// The window function of this name; aggregate functions are included.
+  pub fn from_name(name: &str) -> Option<Self> {
+    match name.to_ascii_lowercase().as_str() {
+      "row_number" => Some(Self::RowNumber),
+      "rank" => Some(Self::Rank),
+      "dense_rank" => Some(Self::DenseRank),
+      "lag" => Some(Self::Lag),
+      "lead" => Some(Self::Lead),
+      "first_value" => Some(Self::FirstValue),
+      "last_value" => Some(Self::LastValue),
+      _ => AggFunc::from_name(name).map(Self::Aggregate),
+    }
+  }
+
+  pub fn name(&self) -> &'static str {
+    match self {
+      Self::RowNumber => "row_number",
+      Self::Rank => "rank",
+      Self::DenseRank => "dense_rank",
+      Self::Lag => "lag",
+      Self::Lead => "lead",
+      Self::FirstValue => "first_value",
+      Self::LastValue => "last_value",
+      Self::Aggregate(func) => func.name(),
+    }
+  }
+
+  /// The range of arguments the function takes.
+  pub fn arity(&self) -> (usize, usize) {
+    match self {
+      Self::RowNumber | Self::Rank | Self::DenseRank => (0, 0),
+      Self::Lag | Self::Lead => (1, 3),
+      Self::FirstValue | Self::LastValue => (1, 1),
+      Self::Aggregate(AggFunc::Count) => (0, 1),
+      Self::Aggregate(_) => (1, 1),
+    }
+  }
+}
+
+impl TableRef {
+  /// The name columns of this table are qualified with.
+  pub fn qualifier(&self) -> Option<&str> {
+    match (&self.alias, &self.source) {
+      (Some(alias), _) => Some(alias),
+      (None, TableSource::Named(name)) => Some(name),
+      (None, TableSource::Subquery(_)) => None,
+    }
+  }
+}
+
+impl Expr {
+  /// Split a conjunction into its AND-ed terms.
+  pub fn conjuncts(&self) -> Vec<&Expr> {
+    match self {
+      Self::Binary(BinaryOp::And, l, r) => {
+        let mut terms = l.conjuncts();
+        terms.extend(r.conjuncts());
+        terms
+      }
+      other => vec![other],
+    }
+  }
+
+  /// Direct sub-expressions, not descending into the bodies of subqueries.
+  pub fn children(&self) -> Vec<&Expr> {
+    match self {
+      Self::Literal(_)
+      | Self::Variable(_)
+      | Self::Column { .. }
+      | Self::Exists(_)
+      | Self::Subquery(_) => vec![],
+      Self::Aggregate { arg, .. } => arg.iter().map(|e| &**e).collect(),
+      Self::Unary(_, e) | Self::IsNull { expr: e, .. } | Self::InSelect { expr: e, .. } => {
+        vec![e]
+      }
+      Self::Binary(_, l, r) => vec![l, r],
+      Self::InList { expr, list, .. } => {
+        let mut children = vec![&**expr];
+        children.extend(list);
+        children
+      }
+      Self::Window { args, over, .. } => {
+        let mut children: Vec<_> = args.iter().collect();
+        children.extend(&over.partition_by);
+        children.extend(over.order_by.iter().map(|term| &term.expr));
+        children
+      }
+    }
+  }
+
+  /// Visit every column reference in the expression, outside of subqueries.
+  pub fn for_each_column<'e>(&'e self, f: &mut impl FnMut(Option<&'e str>, &'e str)) {
+    match self {
+      Self::Column { table, name } => f(table.as_deref(), name),
+      other => other
+        .children()
+        .into_iter()
+        .for_each(|e| e.for_each_column(f)),
+    }
+  }
+
+  pub fn contains_aggregate(&self) -> bool {
+    match self {
+      Self::Aggregate { .. } => true,
+      other => other.children().into_iter().any(Self::contains_aggregate),
+    }
+  }
+
+  pub fn contains_window(&self) -> bool {
+    match self {
+      Self::Window { .. } => true,
+      other => other.children().into_iter().any(Self::contains_window),
+    }
+  }
+}
+
+impl Select {
+  /// Every expression of the query outside of nested subqueries: result columns, join
+  /// predicates, WHERE, GROUP BY, HAVING and ORDER BY.
+  pub fn exprs(&self) -> Vec<&Expr> {
+    let mut exprs = vec![];
+    for col in &self.columns {
+      if let ResultColumn::Expr { expr, .. } = col {
+        exprs.push(expr);
+      }
+    }
+    if let Some(from) = &self.from {
+      exprs.extend(from.joins.iter().filter_map(|j| j.on.as_ref()));
+    }
+    exprs.extend(&self.where_clause);
+    exprs.extend(&self.group_by);
+    exprs.extend(&self.having);
+    exprs.extend(self.order_by.iter().map(|term| &term.expr));
+    exprs
+  }
+
+  /// The queries this one reads as tables: the bodies of its CTEs and its FROM subqueries.
+  pub fn table_selects(&self) -> Vec<&Select> {
+    let mut selects = vec![];
+    for cte in self.with.iter().flat_map(|with| &with.ctes) {
+      selects.push(&cte.select);
+    }
+    if let Some(from) = &self.from {
+      let tables = std::iter::once(&from.base).chain(from.joins.iter().map(|j| &j.table));
+      for table in tables {
+        if let TableSource::Subquery(select) = &table.source {
+          selects.push(select);
+        }
+      }
+    }
+    selects
+  }
+
+  pub fn is_aggregate(&self) -> bool {
+    let in_columns = self.columns.iter().any(|col| match col {
+      ResultColumn::Star | ResultColumn::TableStar(_) => false,
+      ResultColumn::Expr { expr, .. } => expr.contains_aggregate(),
+    });
+    in_columns || !self.group_by.is_empty() || self.having.is_some()
+  }
+}
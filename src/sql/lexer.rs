@@ -0,0 +1,175 @@
+use crate::error::PrepareErr;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+  Ident(String), // keywords are identifiers too, matched case-insensitively by the parser
+  Integer(i64),
+  Real(f64),
+  Str(String),
+  Variable(String), // `?`, `?NNN` or `:name`, as written
+  LParen,
+  RParen,
+  Comma,
+  Dot,
+  Semicolon,
+  Star,
+  Plus,
+  Minus,
+  Slash,
+  Percent,
+  Concat,
+  Eq,
+  Ne,
+  Lt,
+  Le,
+  Gt,
+  Ge,
+}
+
+impl fmt::Display for Token {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::Ident(s) => write!(f, "{s}"),
+      Self::Integer(v) => write!(f, "{v}"),
+      Self::Real(v) => write!(f, "{v}"),
+      Self::Str(s) => write!(f, "'{s}'"),
+      Self::Variable(s) => write!(f, "{s}"),
+      Self::LParen => write!(f, "("),
+      Self::RParen => write!(f, ")"),
+      Self::Comma => write!(f, ","),
+      Self::Dot => write!(f, "."),
+      Self::Semicolon => write!(f, ";"),
+      Self::Star => write!(f, "*"),
+      Self::Plus => write!(f, "+"),
+      Self::Minus => write!(f, "-"),
+      Self::Slash => write!(f, "/"),
+      Self::Percent => write!(f, "%"),
+      Self::Concat => write!(f, "||"),
+      Self::Eq => write!(f, "="),
+      Self::Ne => write!(f, "<>"),
+      Self::Lt => write!(f, "<"),
+      Self::Le => write!(f, "<="),
+      Self::Gt => write!(f, ">"),
+      Self::Ge => write!(f, ">="),
+    }
+  }
+}
+
+pub fn tokenize(sql: &str) -> Result<Vec<Token>, PrepareErr> {
+  let chars: Vec<char> = sql.chars().collect();
+  let mut tokens = vec![];
+  let mut i = 0;
+
+  while i < chars.len() {
+    let c = chars[i];
+    let next = chars.get(i + 1).copied();
+    let tok = match c {
+      c if c.is_whitespace() => {
+        i += 1;
+        continue;
+      }
+      c if c.is_ascii_digit() => {
+        let start = i;
+        while i < chars.len() && chars[i].is_ascii_digit() {
+          i += 1;
+        }
+        let is_real = chars.get(i) == Some(&'.');
+        if is_real {
+          i += 1;
+          while i < chars.len() && chars[i].is_ascii_digit() {
+            i += 1;
+          }
+        }
+        let digits: String = chars[start..i].iter().collect();
+        let tok = if is_real {
+          digits.parse::<f64>().map(Token::Real).ok()
+        } else {
+          digits.parse::<i64>().map(Token::Integer).ok()
+        };
+        let tok = tok
+          .ok_or_else(|| PrepareErr::SyntaxErr(format!("Malformed number literal {digits}.")))?;
+        tokens.push(tok);
+        continue;
+      }
+      c if c.is_alphabetic() || c == '_' => {
+        let start = i;
+        while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+          i += 1;
+        }
+        tokens.push(Token::Ident(chars[start..i].iter().collect()));
+        continue;
+      }
+      '\'' => {
+        // '' inside a string literal stands for a single quote
+        let mut s = String::new();
+        i += 1;
+        loop {
+          match (chars.get(i), chars.get(i + 1)) {
+            (Some('\''), Some('\'')) => {
+              s.push('\'');
+              i += 2;
+            }
+            (Some('\''), _) => break,
+            (Some(&ch), _) => {
+              s.push(ch);
+              i += 1;
+            }
+            (None, _) => {
+              return Err(PrepareErr::SyntaxErr(
+                "Unterminated string literal.".to_string(),
+              ))
+            }
+          }
+        }
+        i += 1;
+        tokens.push(Token::Str(s));
+        continue;
+      }
+      '?' | ':' => {
+        let start = i;
+        i += 1;
+        while i < chars.len()
+          && match c {
+            '?' => chars[i].is_ascii_digit(),
+            _ => chars[i].is_alphanumeric() || chars[i] == '_',
+          }
+        {
+          i += 1;
+        }
+        if c == ':' && i == start + 1 {
+          return Err(PrepareErr::SyntaxErr("Unrecognized token ':'.".to_string()));
+        }
+        tokens.push(Token::Variable(chars[start..i].iter().collect()));
+        continue;
+      }
+      '(' => Token::LParen,
+      ')' => Token::RParen,
+      ',' => Token::Comma,
+      '.' => Token::Dot,
+      ';' => Token::Semicolon,
+      '*' => Token::Star,
+      '+' => Token::Plus,
+      '-' => Token::Minus,
+      '/' => Token::Slash,
+      '%' => Token::Percent,
+      '|' if next == Some('|') => Token::Concat,
+      '=' if next == Some('=') => Token::Eq,
+      '=' => Token::Eq,
+      '!' if next == Some('=') => Token::Ne,
+      '<' if next == Some('>') => Token::Ne,
+      '<' if next == Some('=') => Token::Le,
+      '<' => Token::Lt,
+      '>' if next == Some('=') => Token::Ge,
+      '>' => Token::Gt,
+      _ => return Err(PrepareErr::SyntaxErr(format!("Unrecognized token {c:?}."))),
+    };
+    i += match tok {
+      Token::Concat | Token::Ne | Token::Le | Token::Ge => 2,
+      Token::Eq if next == Some('=') => 2,
+      _ => 1,
+    };
+    tokens.push(tok);
+  }
+  Ok(tokens)
+}
@@ -0,0 +1,675 @@
+use super::ast::{
+  AggFunc, BinaryOp, CompoundArm, CompoundOp, Cte, Expr, FrameBound, FromClause, Join, JoinKind,
+  OrderingTerm, Over, ResultColumn, Select, TableRef, TableSource, UnaryOp, Variables, WindowFrame,
+  WindowFunc, With,
+};
+use super::lexer::Token;
+use crate::error::PrepareErr;
+use crate::value::Value;
+use std::rc::Rc;
+
+const RESERVED: &[&str] = &[
+  "select",
+  "from",
+  "where",
+  "group",
+  "by",
+  "having",
+  "limit",
+  "offset",
+  "as",
+  "and",
+  "or",
+  "not",
+  "is",
+  "null",
+  "join",
+  "inner",
+  "left",
+  "outer",
+  "cross",
+  "on",
+  "in",
+  "exists",
+  "union",
+  "all",
+  "intersect",
+  "except",
+  "order",
+  "asc",
+  "desc",
+  "with",
+  "recursive",
+];
+
+pub struct Parser {
+  tokens: Vec<Token>,
+  pos: usize,
+  pub variables: Variables,
+}
+
+impl Parser {
+  pub fn new(tokens: Vec<Token>) -> Self {
+    Self {
+      tokens,
+      pos: 0,
+      variables: Variables::default(),
+    }
+  }
+
+  /// select-stmt := [WITH [RECURSIVE] cte, ...] select-core [compound-op select-core, ...]
+  ///                [ORDER BY expr [ASC | DESC], ...] [LIMIT n [OFFSET m | , n]] [;]
+  /// select-core := SELECT [result-column, ...] [FROM table [join, ...]] [WHERE expr]
+  ///                [GROUP BY expr, ... [HAVING expr]]
+  /// compound-op := UNION [ALL] | INTERSECT | EXCEPT
+  pub fn parse_select_stmt(&mut self) -> Result<Select, PrepareErr> {
+    let select = self.parse_select()?;
+    self.expect_end()?;
+    Ok(select)
+  }
+
+  fn parse_select(&mut self) -> Result<Select, PrepareErr> {
+    let mut select = Select::default();
+    if self.eat_keyword("with") {
+      let recursive = self.eat_keyword("recursive");
+      let ctes = self.parse_comma_list(Self::parse_cte)?;
+      select.with = Some(With { recursive, ctes });
+    }
+    self.parse_core(&mut select)?;
+    while let Some(op) = self.parse_compound_op() {
+      let mut core = Select::default();
+      self.parse_core(&mut core)?;
+      select.compound.push(CompoundArm { op, select: core });
+    }
+    if self.eat_keyword("order") {
+      self.expect_keyword("by")?;
+      select.order_by = self.parse_comma_list(Self::parse_ordering_term)?;
+    }
+
+    if self.eat_keyword("limit") {
+      let mut limit = self.parse_int()?;
+      if self.eat_keyword("offset") {
+        select.offset = self.parse_int()?.max(0) as usize;
+      } else if self.eat(&Token::Comma) {
+        // `LIMIT m, n` is the same as `LIMIT n OFFSET m`
+        select.offset = limit.max(0) as usize;
+        limit = self.parse_int()?;
+      }
+      // a negative limit means no limit, as in SQLite
+      select.limit = usize::try_from(limit).ok();
+    }
+    Ok(select)
+  }
+
+  fn parse_core(&mut self, select: &mut Select) -> Result<(), PrepareErr> {
+    self.expect_keyword("select")?;
+    if !self.at_clause_end() {
+      select.columns = self.parse_comma_list(Self::parse_result_column)?;
+    }
+    if self.eat_keyword("from") {
+      select.from = Some(self.parse_from()?);
+    }
+    if self.eat_keyword("where") {
+      select.where_clause = Some(self.parse_expr()?);
+    }
+    if self.eat_keyword("group") {
+      self.expect_keyword("by")?;
+      select.group_by = self.parse_comma_list(Self::parse_expr)?;
+    }
+    if self.eat_keyword("having") {
+      select.having = Some(self.parse_expr()?);
+    }
+    Ok(())
+  }
+
+  fn parse_compound_op(&mut self) -> Option<CompoundOp> {
+    if self.eat_keyword("union") {
+      Some(if self.eat_keyword("all") {
+        CompoundOp::UnionAll
+      } else {
+        CompoundOp::Union
+      })
+    } else if self.eat_keyword("intersect") {
+      Some(CompoundOp::Intersect)
+    } else if self.eat_keyword("except") {
+      Some(CompoundOp::Except)
+    } else {
+      None
+    }
+  }
+
+  fn parse_ordering_term(&mut self) -> Result<OrderingTerm, PrepareErr> {
+    let expr = self.parse_expr()?;
+    let desc = if self.eat_keyword("desc") {
+      true
+    } else {
+      self.eat_keyword("asc");
+      false
+    };
+    Ok(OrderingTerm { expr, desc })
+  }
+
+  /// cte := name [(column, ...)] AS ( select-stmt )
+  fn parse_cte(&mut self) -> Result<Cte, PrepareErr> {
+    let name = self.parse_ident()?;
+    let mut columns = vec![];
+    if self.eat(&Token::LParen) {
+      columns = self.parse_comma_list(Self::parse_ident)?;
+      self.expect(&Token::RParen)?;
+    }
+    self.expect_keyword("as")?;
+    self.expect(&Token::LParen)?;
+    let select = self.parse_select()?;
+    self.expect(&Token::RParen)?;
+    Ok(Cte {
+      name,
+      columns,
+      select,
+    })
+  }
+
+  fn parse_result_column(&mut self) -> Result<ResultColumn, PrepareErr> {
+    if self.eat(&Token::Star) {
+      return Ok(ResultColumn::Star);
+    }
+    if let [Some(Token::Ident(table)), Some(Token::Dot), Some(Token::Star)] =
+      [0, 1, 2].map(|i| self.tokens.get(self.pos + i))
+    {
+      let table = table.clone();
+      self.pos += 3;
+      return Ok(ResultColumn::TableStar(table));
+    }
+    let expr = self.parse_expr()?;
+    let alias = if self.eat_keyword("as") {
+      Some(self.parse_ident()?)
+    } else {
+      match self.peek() {
+        Some(Token::Ident(s)) if !is_reserved(s) => Some(self.parse_ident()?),
+        _ => None,
+      }
+    };
+    Ok(ResultColumn::Expr { expr, alias })
+  }
+
+  /// from := table-ref { [INNER | LEFT [OUTER] | CROSS] JOIN table-ref [ON expr] | , table-ref }
+  fn parse_from(&mut self) -> Result<FromClause, PrepareErr> {
+    let base = self.parse_table_ref()?;
+    let mut joins = vec![];
+    loop {
+      if self.eat(&Token::Comma) {
+        joins.push(Join {
+          kind: JoinKind::Inner,
+          table: self.parse_table_ref()?,
+          on: None,
+        });
+        continue;
+      }
+      let kind = if self.eat_keyword("left") {
+        self.eat_keyword("outer");
+        JoinKind::Left
+      } else if self.eat_keyword("inner") || self.eat_keyword("cross") || self.peek_keyword("join")
+      {
+        JoinKind::Inner
+      } else {
+        break;
+      };
+      self.expect_keyword("join")?;
+      let table = self.parse_table_ref()?;
+      let on = if self.eat_keyword("on") {
+        Some(self.parse_expr()?)
+      } else {
+        None
+      };
+      joins.push(Join { kind, table, on });
+    }
+    Ok(FromClause { base, joins })
+  }
+
+  /// table-ref := name [[AS] alias] | ( select-stmt ) [[AS] alias]
+  fn parse_table_ref(&mut self) -> Result<TableRef, PrepareErr> {
+    let source = if self.eat(&Token::LParen) {
+      let select = self.parse_select()?;
+      self.expect(&Token::RParen)?;
+      TableSource::Subquery(Rc::new(select))
+    } else {
+      TableSource::Named(self.parse_ident()?)
+    };
+    let alias = if self.eat_keyword("as") {
+      Some(self.parse_ident()?)
+    } else {
+      match self.peek() {
+        Some(Token::Ident(s)) if !is_reserved(s) => Some(self.parse_ident()?),
+        _ => None,
+      }
+    };
+    Ok(TableRef { source, alias })
+  }
+
+  fn parse_comma_list<T>(
+    &mut self,
+    mut f: impl FnMut(&mut Self) -> Result<T, PrepareErr>,
+  ) -> Result<Vec<T>, PrepareErr> {
+    let mut items = vec![f(self)?];
+    while self.eat(&Token::Comma) {
+      items.push(f(self)?);
+    }
+    Ok(items)
+  }
+
+  pub fn parse_expr(&mut self) -> Result<Expr, PrepareErr> {
+    self.parse_or()
+  }
+
+  fn parse_or(&mut self) -> Result<Expr, PrepareErr> {
+    let mut lhs = self.parse_and()?;
+    while self.eat_keyword("or") {
+      let rhs = self.parse_and()?;
+      lhs = Expr::Binary(BinaryOp::Or, Box::new(lhs), Box::new(rhs));
+    }
+    Ok(lhs)
+  }
+
+  fn parse_and(&mut self) -> Result<Expr, PrepareErr> {
+    let mut lhs = self.parse_not()?;
+    while self.eat_keyword("and") {
+      let rhs = self.parse_not()?;
+      lhs = Expr::Binary(BinaryOp::And, Box::new(lhs), Box::new(rhs));
+    }
+    Ok(lhs)
+  }
+
+  fn parse_not(&mut self) -> Result<Expr, PrepareErr> {
+    if self.eat_keyword("not") {
+      let expr = self.parse_not()?;
+      return Ok(Expr::Unary(UnaryOp::Not, Box::new(expr)));
+    }
+    self.parse_equality()
+  }
+
+  fn parse_equality(&mut self) -> Result<Expr, PrepareErr> {
+    let mut lhs = self.parse_comparison()?;
+    loop {
+      let op = match self.peek() {
+        Some(Token::Eq) => BinaryOp::Eq,
+        Some(Token::Ne) => BinaryOp::Ne,
+        Some(Token::Ident(s)) if s.eq_ignore_ascii_case("in") => {
+          self.pos += 1;
+          lhs = self.parse_in_rhs(lhs, false)?;
+          continue;
+        }
+        Some(Token::Ident(s))
+          if s.eq_ignore_ascii_case("not")
+            && matches!(self.tokens.get(self.pos + 1), Some(Token::Ident(s)) if s.eq_ignore_ascii_case("in")) =>
+        {
+          self.pos += 2;
+          lhs = self.parse_in_rhs(lhs, true)?;
+          continue;
+        }
+        Some(Token::Ident(s)) if s.eq_ignore_ascii_case("is") => {
+          self.pos += 1;
+          let negated = self.eat_keyword("not");
+          self.expect_keyword("null")?;
+          lhs = Expr::IsNull {
+            expr: Box::new(lhs),
+            negated,
+          };
+          continue;
+        }
+        _ => return Ok(lhs),
+      };
+      self.pos += 1;
+      let rhs = self.parse_comparison()?;
+      lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+    }
+  }
+
+  /// in-rhs := ( select-stmt ) | ( expr, ... )
+  fn parse_in_rhs(&mut self, lhs: Expr, negated: bool) -> Result<Expr, PrepareErr> {
+    self.expect(&Token::LParen)?;
+    let expr = Box::new(lhs);
+    let res = if self.at_select() {
+      let select = Rc::new(self.parse_select()?);
+      Expr::InSelect {
+        expr,
+        select,
+        negated,
+      }
+    } else {
+      let list = self.parse_comma_list(Self::parse_expr)?;
+      Expr::InList {
+        expr,
+        list,
+        negated,
+      }
+    };
+    self.expect(&Token::RParen)?;
+    Ok(res)
+  }
+
+  fn parse_comparison(&mut self) -> Result<Expr, PrepareErr> {
+    let mut lhs = self.parse_additive()?;
+    loop {
+      let op = match self.peek() {
+        Some(Token::Lt) => BinaryOp::Lt,
+        Some(Token::Le) => BinaryOp::Le,
+        Some(Token::Gt) => BinaryOp::Gt,
+        Some(Token::Ge) => BinaryOp::Ge,
+        _ => return Ok(lhs),
+      };
+      self.pos += 1;
+      let rhs = self.parse_additive()?;
+      lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+    }
+  }
+
+  fn parse_additive(&mut self) -> Result<Expr, PrepareErr> {
+    let mut lhs = self.parse_multiplicative()?;
+    loop {
+      let op = match self.peek() {
+        Some(Token::Plus) => BinaryOp::Add,
+        Some(Token::Minus) => BinaryOp::Sub,
+        _ => return Ok(lhs),
+      };
+      self.pos += 1;
+      let rhs = self.parse_multiplicative()?;
+      lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+    }
+  }
+
+  fn parse_multiplicative(&mut self) -> Result<Expr, PrepareErr> {
+    let mut lhs = self.parse_concat()?;
+    loop {
+      let op = match self.peek() {
+        Some(Token::Star) => BinaryOp::Mul,
+        Some(Token::Slash) => BinaryOp::Div,
+        Some(Token::Percent) => BinaryOp::Rem,
+        _ => return Ok(lhs),
+      };
+      self.pos += 1;
+      let rhs = self.parse_concat()?;
+      lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+    }
+  }
+
+  fn parse_concat(&mut self) -> Result<Expr, PrepareErr> {
+    let mut lhs = self.parse_unary()?;
+    while self.eat(&Token::Concat) {
+      let rhs = self.parse_unary()?;
+      lhs = Expr::Binary(BinaryOp::Concat, Box::new(lhs), Box::new(rhs));
+    }
+    Ok(lhs)
+  }
+
+  fn parse_unary(&mut self) -> Result<Expr, PrepareErr> {
+    if self.eat(&Token::Minus) {
+      let expr = self.parse_unary()?;
+      return Ok(match expr {
+        Expr::Literal(Value::Integer(v)) => Expr::Literal(Value::Integer(-v)),
+        expr => Expr::Unary(UnaryOp::Neg, Box::new(expr)),
+      });
+    }
+    if self.eat(&Token::Plus) {
+      return self.parse_unary();
+    }
+    self.parse_primary()
+  }
+
+  fn parse_primary(&mut self) -> Result<Expr, PrepareErr> {
+    match self.next() {
+      Some(Token::Integer(v)) => Ok(Expr::Literal(Value::Integer(v))),
+      Some(Token::Real(v)) => Ok(Expr::Literal(Value::Real(v))),
+      Some(Token::Str(s)) => Ok(Expr::Literal(Value::Text(s))),
+      Some(Token::Variable(var)) => {
+        let n = self.variables.number(&var).map_err(PrepareErr::SyntaxErr)?;
+        Ok(Expr::Variable(n))
+      }
+      Some(Token::LParen) if self.at_select() => {
+        let select = self.parse_select()?;
+        self.expect(&Token::RParen)?;
+        Ok(Expr::Subquery(Rc::new(select)))
+      }
+      Some(Token::LParen) => {
+        let expr = self.parse_expr()?;
+        self.expect(&Token::RParen)?;
+        Ok(expr)
+      }
+      Some(Token::Ident(s)) if s.eq_ignore_ascii_case("exists") => {
+        self.expect(&Token::LParen)?;
+        let select = self.parse_select()?;
+        self.expect(&Token::RParen)?;
+        Ok(Expr::Exists(Rc::new(select)))
+      }
+      Some(Token::Ident(s)) if s.eq_ignore_ascii_case("null") => Ok(Expr::Literal(Value::Null)),
+      Some(Token::Ident(name)) if !is_reserved(&name) => {
+        if self.eat(&Token::Dot) {
+          let column = self.parse_ident()?;
+          return Ok(Expr::Column {
+            table: Some(name),
+            name: column,
+          });
+        }
+        if !self.eat(&Token::LParen) {
+          return Ok(Expr::Column { table: None, name });
+        }
+        self.parse_call(name)
+      }
+      tok => {
+        self.pos -= 1;
+        Err(syntax_err(tok.as_ref()))
+      }
+    }
+  }
+
+  /// call := name ( [* | expr, ...] ) [OVER window], after the opening parenthesis
+  fn parse_call(&mut self, name: String) -> Result<Expr, PrepareErr> {
+    let func = WindowFunc::from_name(&name)
+      .ok_or_else(|| PrepareErr::SyntaxErr(format!("No such function: {name}.")))?;
+    let count = WindowFunc::Aggregate(AggFunc::Count);
+    let mut args = vec![];
+    if !(func == count && self.eat(&Token::Star)) {
+      if !matches!(self.peek(), Some(Token::RParen)) {
+        args = self.parse_comma_list(Self::parse_expr)?;
+      }
+      let (min, max) = func.arity();
+      if args.len() < min.max((func == count) as usize) || args.len() > max {
+        return Err(PrepareErr::SyntaxErr(format!(
+          "Wrong number of arguments to function {name}()."
+        )));
+      }
+    }
+    self.expect(&Token::RParen)?;
+
+    if self.eat_keyword("over") {
+      let over = Box::new(self.parse_over()?);
+      return Ok(Expr::Window { func, args, over });
+    }
+    match func {
+      WindowFunc::Aggregate(func) => Ok(Expr::Aggregate {
+        func,
+        arg: args.pop().map(Box::new),
+      }),
+      _ => Err(PrepareErr::SyntaxErr(format!(
+        "Misuse of window function {name}()."
+      ))),
+    }
+  }
+
+  /// window := ( [PARTITION BY expr, ...] [ORDER BY expr [ASC | DESC], ...]
+  ///            [{ROWS | RANGE} {BETWEEN bound AND bound | bound}] )
+  fn parse_over(&mut self) -> Result<Over, PrepareErr> {
+    self.expect(&Token::LParen)?;
+    let mut over = Over {
+      partition_by: vec![],
+      order_by: vec![],
+      frame: None,
+    };
+    if self.eat_keyword("partition") {
+      self.expect_keyword("by")?;
+      over.partition_by = self.parse_comma_list(Self::parse_expr)?;
+    }
+    if self.eat_keyword("order") {
+      self.expect_keyword("by")?;
+      over.order_by = self.parse_comma_list(Self::parse_ordering_term)?;
+    }
+    let range = self.eat_keyword("range");
+    if range || self.eat_keyword("rows") {
+      let (start, end) = if self.eat_keyword("between") {
+        let start = self.parse_frame_bound()?;
+        self.expect_keyword("and")?;
+        (start, self.parse_frame_bound()?)
+      } else {
+        (self.parse_frame_bound()?, FrameBound::CurrentRow)
+      };
+      let with_offset = |b| matches!(b, FrameBound::Preceding(_) | FrameBound::Following(_));
+      // as in SQLite, a frame may not start at a later kind of bound than it ends
+      let kind = |b| match b {
+        FrameBound::UnboundedPreceding => 0,
+        FrameBound::Preceding(_) => 1,
+        FrameBound::CurrentRow => 2,
+        FrameBound::Following(_) => 3,
+        FrameBound::UnboundedFollowing => 4,
+      };
+      if start == FrameBound::UnboundedFollowing
+        || end == FrameBound::UnboundedPreceding
+        || kind(start) > kind(end)
+        || (range && (with_offset(start) || with_offset(end)))
+      {
+        return Err(PrepareErr::SyntaxErr(
+          "Unsupported frame specification.".to_string(),
+        ));
+      }
+      over.frame = Some(WindowFrame { range, start, end });
+    }
+    self.expect(&Token::RParen)?;
+    Ok(over)
+  }
+
+  /// bound := UNBOUNDED {PRECEDING | FOLLOWING} | CURRENT ROW | n {PRECEDING | FOLLOWING}
+  fn parse_frame_bound(&mut self) -> Result<FrameBound, PrepareErr> {
+    if self.eat_keyword("unbounded") {
+      if self.eat_keyword("preceding") {
+        return Ok(FrameBound::UnboundedPreceding);
+      }
+      self.expect_keyword("following")?;
+      return Ok(FrameBound::UnboundedFollowing);
+    }
+    if self.eat_keyword("current") {
+      self.expect_keyword("row")?;
+      return Ok(FrameBound::CurrentRow);
+    }
+    let n = match self.parse_int()? {
+      n if n < 0 => return Err(syntax_err(self.tokens.get(self.pos - 1))),
+      n => n as usize,
+    };
+    if self.eat_keyword("preceding") {
+      return Ok(FrameBound::Preceding(n));
+    }
+    self.expect_keyword("following")?;
+    Ok(FrameBound::Following(n))
+  }
+
+  fn parse_ident(&mut self) -> Result<String, PrepareErr> {
+    match self.next() {
+      Some(Token::Ident(s)) if !is_reserved(&s) => Ok(s),
+      tok => Err(syntax_err(tok.as_ref())),
+    }
+  }
+
+  fn parse_int(&mut self) -> Result<i64, PrepareErr> {
+    let neg = self.eat(&Token::Minus);
+    match self.next() {
+      Some(Token::Integer(v)) => Ok(if neg { -v } else { v }),
+      tok => Err(syntax_err(tok.as_ref())),
+    }
+  }
+
+  fn at_clause_end(&self) -> bool {
+    match self.peek() {
+      None | Some(Token::Semicolon) | Some(Token::RParen) => true,
+      Some(Token::Ident(s)) => [
+        "from",
+        "where",
+        "limit",
+        "order",
+        "union",
+        "intersect",
+        "except",
+      ]
+      .iter()
+      .any(|kw| s.eq_ignore_ascii_case(kw)),
+      _ => false,
+    }
+  }
+
+  fn at_select(&self) -> bool {
+    self.peek_keyword("select") || self.peek_keyword("with")
+  }
+
+  fn peek(&self) -> Option<&Token> {
+    self.tokens.get(self.pos)
+  }
+
+  fn next(&mut self) -> Option<Token> {
+    let tok = self.tokens.get(self.pos).cloned();
+    self.pos += 1;
+    tok
+  }
+
+  fn eat(&mut self, tok: &Token) -> bool {
+    if self.peek() == Some(tok) {
+      self.pos += 1;
+      true
+    } else {
+      false
+    }
+  }
+
+  fn expect(&mut self, tok: &Token) -> Result<(), PrepareErr> {
+    if self.eat(tok) {
+      Ok(())
+    } else {
+      Err(syntax_err(self.peek()))
+    }
+  }
+
+  fn peek_keyword(&self, kw: &str) -> bool {
+    matches!(self.peek(), Some(Token::Ident(s)) if s.eq_ignore_ascii_case(kw))
+  }
+
+  fn eat_keyword(&mut self, kw: &str) -> bool {
+    match self.peek() {
+      Some(Token::Ident(s)) if s.eq_ignore_ascii_case(kw) => {
+        self.pos += 1;
+        true
+      }
+      _ => false,
+    }
+  }
+
+  fn expect_keyword(&mut self, kw: &str) -> Result<(), PrepareErr> {
+    if self.eat_keyword(kw) {
+      Ok(())
+    } else {
+      Err(syntax_err(self.peek()))
+    }
+  }
+
+  fn expect_end(&mut self) -> Result<(), PrepareErr> {
+    self.eat(&Token::Semicolon);
+    match self.peek() {
+      None => Ok(()),
+      tok => Err(syntax_err(tok)),
+    }
+  }
+}
+
+fn is_reserved(word: &str) -> bool {
+  RESERVED.iter().any(|kw| word.eq_ignore_ascii_case(kw))
+}
+
+fn syntax_err(tok: Option<&Token>) -> PrepareErr {
+  match tok {
+    Some(tok) => PrepareErr::SyntaxErr(format!("Syntax error near \"{tok}\".")),
+    None => PrepareErr::SyntaxErr("Syntax error: incomplete input.".to_string()),
+  }
+}
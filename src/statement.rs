@@ -0,0 +1,126 @@
+use crate::connection::Connection;
+use crate::error::{DbError, ExecErr};
+use crate::value::Value;
+use crate::vdbe::{Explain, Program, Vm};
+use std::rc::Rc;
+
+/// A prepared statement: compiled once by `Connection::prepare`, then run any number of times, with
+/// values bound to its `?`, `?NNN` and `:name` parameters instead of written into the SQL.
+///
+/// Parameters are numbered from 1, and all of them must be bound before the first `step`.
+/// Bindings can only change before the first `step` or after a `reset`, and they survive the
+/// reset. A statement only steps against the connection that prepared it.
+pub struct Statement {
+  vm: Vm,
+  columns: Rc<[String]>,
+  conn: u64, // `Connection::id` of the connection that prepared it
+}
+
+impl Statement {
+  pub(crate) fn new(program: Program, conn: u64) -> Self {
+    Self {
+      columns: program.columns.clone().into(),
+      vm: Vm::new(program),
+      conn,
+    }
+  }
+
+  /// Whether this is an EXPLAIN statement, and which kind: its rows then describe the
+  /// statement it explains.
+  pub fn explain(&self) -> Option<Explain> {
+    self.vm.program().explain
+  }
+
+  /// Names of the columns of the rows the statement yields.
+  pub fn column_names(&self) -> &[String] {
+    &self.columns
+  }
+
+  pub(crate) fn columns(&self) -> Rc<[String]> {
+    Rc::clone(&self.columns)
+  }
+
+  /// Run until the next result row, or `None` once the statement is done.
+  pub fn step(&mut self, conn: &mut Connection) -> Result<Option<Vec<Value>>, DbError> {
+    if conn.id != self.conn {
+      return Err(DbError::ExecErr(ExecErr::Misuse(
+        "Statement was prepared on another connection.".to_string(),
+      )));
+    }
+    self.vm.step(&mut conn.table).map_err(DbError::ExecErr)
+  }
+
+  /// Number of rows inserted since the statement started or was reset.
+  pub fn changes(&self) -> usize {
+    self.vm.changes()
+  }
+
+  /// Make the statement ready to run again from the start, keeping its bindings.
+  pub fn reset(&mut self) {
+    self.vm.reset();
+  }
+
+  /// The largest parameter number.
+  pub fn bind_parameter_count(&self) -> usize {
+    self.vm.program().variables.count()
+  }
+
+  /// The name of parameter `number` as written, such as `?2` or `:name`; `None` for a bare `?`
+  /// or a number beyond the count.
+  pub fn bind_parameter_name(&self, number: usize) -> Option<&str> {
+    self.vm.program().variables.name(number)
+  }
+
+  /// The number of the parameter written as `name`, including its `:` or `?`.
+  pub fn bind_parameter_index(&self, name: &str) -> Option<usize> {
+    self.vm.program().variables.index(name)
+  }
+
+  pub fn bind_null(&mut self, number: usize) -> Result<(), DbError> {
+    self.bind_value(number, Value::Null)
+  }
+
+  pub fn bind_int(&mut self, number: usize, value: i64) -> Result<(), DbError> {
+    self.bind_value(number, Value::Integer(value))
+  }
+
+  pub fn bind_double(&mut self, number: usize, value: f64) -> Result<(), DbError> {
+    self.bind_value(number, Value::Real(value))
+  }
+
+  pub fn bind_text(&mut self, number: usize, value: &str) -> Result<(), DbError> {
+    self.bind_value(number, Value::Text(value.to_string()))
+  }
+
+  pub fn bind_value(&mut self, number: usize, value: Value) -> Result<(), DbError> {
+    if self.vm.is_running() {
+      return Err(misuse());
+    }
+    match self.vm.bind(number, value) {
+      true => Ok(()),
+      false => Err(bind_err(&format!(
+        "Parameter number {number} out of range 1..={}.",
+        self.bind_parameter_count()
+      ))),
+    }
+  }
+
+  /// Unbind every parameter.
+  pub fn clear_bindings(&mut self) -> Result<(), DbError> {
+    if self.vm.is_running() {
+      return Err(misuse());
+    }
+    self.vm.clear_bindings();
+    Ok(())
+  }
+}
+
+fn misuse() -> DbError {
+  DbError::ExecErr(ExecErr::Misuse(
+    "Statement must be reset before binding.".to_string(),
+  ))
+}
+
+fn bind_err(msg: &str) -> DbError {
+  DbError::ExecErr(ExecErr::BindError(msg.to_string()))
+}
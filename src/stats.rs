@@ -0,0 +1,131 @@
+use crate::cursor::Cursor;
+use crate::error::ExecErr;
+use crate::row;
+use crate::table::Table;
+use crate::value::Value;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::BTreeSet;
+use std::hash::{Hash, Hasher};
+
+/// What ANALYZE learned about the users table, for the planner to estimate how many rows a
+/// loop visits. They go stale as rows are inserted, until the next ANALYZE.
+///
+/// Unlike SQLite, which stores them as rows of a `sqlite_stat1` table, this keeps them in the
+/// file header, the only place besides the users table that the file has. That bounds them to
+/// the fixed size of the header: a row count and a count of distinct values per column, which
+/// is all the planner reads. `sqlite_stat1` can be queried all the same, its rows computed
+/// from the header, but not written.
+#[derive(Debug, Clone)]
+pub struct Stats {
+  pub rows: usize,
+  distinct: [usize; row::COLUMNS.len()], // number of distinct values, per column
+}
+
+impl Stats {
+  /// Read every row of `table`, counting rows and estimating the distinct values of each
+  /// column in memory that does not grow with the table. The key is unique, so it has as
+  /// many values as there are rows.
+  pub fn gather(table: &Table) -> Result<Self, ExecErr> {
+    let mut seen: [Distinct; row::COLUMNS.len() - 1] = Default::default();
+    let mut rows = 0;
+    let mut cursor = Cursor::new();
+    cursor.first(table)?;
+    while cursor.is_valid() {
+      let values = cursor.value(table)?.to_values();
+      for (distinct, value) in seen.iter_mut().zip(&values[1..]) {
+        distinct.insert(&value.to_text());
+      }
+      rows += 1;
+      cursor.next(table)?;
+    }
+    let mut distinct = [rows; row::COLUMNS.len()];
+    for (n, seen) in distinct[1..].iter_mut().zip(&seen) {
+      *n = seen.count().min(rows);
+    }
+    Ok(Self { rows, distinct })
+  }
+
+  /// The stats as the file header stores them, in `SIZE` bytes: the row count, then the
+  /// number of distinct values of each column, as big-endian `u64`s.
+  pub fn to_bytes(&self) -> Vec<u8> {
+    std::iter::once(self.rows)
+      .chain(self.distinct)
+      .flat_map(|n| (n as u64).to_be_bytes())
+      .collect()
+  }
+
+  /// Read the stats `to_bytes` stored in `bytes`.
+  pub fn from_bytes(bytes: &[u8]) -> Self {
+    let mut numbers = bytes
+      .chunks_exact(8)
+      .map(|n| u64::from_be_bytes(n.try_into().unwrap()) as usize);
+    Self {
+      rows: numbers.next().unwrap(),
+      distinct: std::array::from_fn(|_| numbers.next().unwrap()),
+    }
+  }
+
+  /// Average number of rows sharing a value of the column, rounded up as SQLite does.
+  pub fn rows_per_value(&self, column: usize) -> usize {
+    self.rows.div_ceil(self.distinct[column].max(1))
+  }
+
+  /// The rows of `sqlite_stat1`: `(tbl, idx, stat)`. The table's own row has a NULL `idx`
+  /// and the row count as `stat`; each column has a row named after it, whose `stat` is the
+  /// row count and the average number of rows per value, as for a one-column index.
+  pub fn to_rows(&self) -> Vec<Vec<Value>> {
+    let mut rows = vec![vec![
+      Value::Text("users".to_string()),
+      Value::Null,
+      Value::Text(self.rows.to_string()),
+    ]];
+    for (column, name) in row::COLUMNS.iter().enumerate() {
+      rows.push(vec![
+        Value::Text("users".to_string()),
+        Value::Text(name.to_string()),
+        Value::Text(format!("{} {}", self.rows, self.rows_per_value(column))),
+      ]);
+    }
+    rows
+  }
+}
+
+/// Distinct hashes `Distinct` keeps.
+const SAMPLE: usize = 1024;
+
+/// An estimate of the number of distinct values in a stream, from the `SAMPLE` smallest
+/// distinct hashes of them. Up to `SAMPLE` distinct values, the count is exact. Past that,
+/// as hashes spread evenly over their range, the largest one kept lies about `SAMPLE / n` of
+/// the way through it, for `n` distinct values.
+#[derive(Default)]
+struct Distinct {
+  smallest: BTreeSet<u64>,
+}
+
+impl Distinct {
+  fn insert(&mut self, value: &str) {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    let hash = hasher.finish();
+    if self.smallest.len() < SAMPLE {
+      self.smallest.insert(hash);
+    } else if hash < *self.smallest.last().unwrap() && self.smallest.insert(hash) {
+      self.smallest.pop_last();
+    }
+  }
+
+  fn count(&self) -> usize {
+    match self.smallest.last() {
+      Some(&largest) if self.smallest.len() == SAMPLE => {
+        ((SAMPLE - 1) as f64 * (u64::MAX as f64 / largest as f64)) as usize
+      }
+      _ => self.smallest.len(),
+    }
+  }
+}
+
+/// Bytes the stats take in the file header.
+pub const SIZE: usize = std::mem::size_of::<u64>() * (1 + row::COLUMNS.len());
+
+/// Columns of `sqlite_stat1`.
+pub const COLUMNS: [&str; 3] = ["tbl", "idx", "stat"];
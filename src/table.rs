@@ -1,40 +1,73 @@
 use crate::btree::intern::{Child, Intern};
 use crate::btree::leaf::Leaf;
 use crate::btree::node::Node;
-use crate::cursor::Cursor;
 use crate::error::ExecErr;
 use crate::pager::Pager;
 use crate::row::Row;
+use crate::stats::Stats;
 use std::path::Path;
 
-const ROOT: usize = 0;
-pub const MAX_PAGES: usize = 100;
+mod bulk;
+mod check;
+
+pub const ROOT: usize = 0;
+/// Page numbers are stored as `u32`, with `u32::MAX` standing for no page.
+pub const MAX_PAGES: usize = u32::MAX as usize;
 
 pub struct Table {
   pager: Pager,
+  version: u64, // bumped by every change to the tree, for cursors to notice
 }
 
 impl Table {
-  pub fn open_db(fname: impl AsRef<Path>) -> Result<Self, ExecErr> {
-    let mut pager = Pager::new(fname)?;
+  pub fn open_db(
+    fname: impl AsRef<Path>,
+    checksums: bool,
+    page_size: usize,
+  ) -> Result<Self, ExecErr> {
+    let mut pager = Pager::new(fname, checksums, page_size)?;
 
     if pager.size() == 0 {
       let root = Node::Leaf(Leaf::new(true, None, None));
       pager.push_node(root)?;
     }
-    Ok(Self { pager })
+    Ok(Self { pager, version: 0 })
+  }
+
+  /// Whether the pages of the file carry checksums.
+  pub fn checksums(&self) -> bool {
+    self.pager.checksums()
+  }
+
+  /// Bytes in each page of the file.
+  pub fn page_size(&self) -> usize {
+    self.pager.page_size()
   }
 
   pub fn close_db(&self) -> Result<(), ExecErr> {
     self.pager.flush()
   }
 
+  /// Gather fresh statistics for the planner, replacing those of an earlier ANALYZE.
+  pub fn analyze(&mut self) -> Result<(), ExecErr> {
+    let stats = Stats::gather(self)?;
+    self.pager.set_stats(stats);
+    Ok(())
+  }
+
+  /// Stats of the last ANALYZE, kept in the file.
+  pub fn stats(&self) -> Option<&Stats> {
+    self.pager.stats()
+  }
+
   pub fn insert_row(&mut self, key: u32, row: &Row) -> Result<(), ExecErr> {
     let row = row.serialize();
-    let leaf_idx = self.find_leaf_recur(ROOT, key)?;
-    let res = self
-      .pager
-      .set_node_by(leaf_idx, |nd| nd.as_leaf_mut()?.insert_row(key, &row))?;
+    self.version += 1;
+    let page_size = self.page_size();
+    let leaf_idx = self.find_leaf(key)?;
+    let res = self.pager.set_node_by(leaf_idx, |nd| {
+      nd.as_leaf_mut()?.insert_row(key, &row, page_size)
+    })?;
 
     match res {
       Err(ExecErr::LeafNodeFull(_)) => {
@@ -44,116 +77,152 @@ impl Table {
             .insert_row_and_split(key, &row, pg_idx_new)
         })??;
 
-        // update the key_max of the splitted node in the parent's child item
+        // the old leaf kept the lower keys, so its largest separates it from the new one
         let key_max = self
           .pager
-          .get_node_do(leaf_idx, |nd| nd.as_leaf().unwrap().key_max())?;
-        if let Some(&parent) = leaf.parent.as_ref() {
-          self.pager.set_node_by(parent, |nd| {
-            nd.as_intern_mut()
-              .unwrap()
-              .find_mut_child_and(key_max, |ch| ch.key_max = key_max)
-          })??;
-        }
-
-        let key_max = leaf.key_max();
+          .get_node_do(leaf_idx, |nd| Ok::<_, ExecErr>(nd.as_leaf()?.key_max()))??;
         let parent = leaf.parent;
         self.pager.push_node(Node::Leaf(leaf))?;
-        let child = Child::new(pg_idx_new, key_max);
-        self.insert_child(child, parent)
+        self.insert_child(key_max, pg_idx_new, parent)?;
       }
-      others => others,
+      others => others?,
     }
+    Ok(())
   }
 
-  pub fn new_cursor_by_key(&self, key: u32) -> Cursor {
-    let leaf_idx = self.find_leaf_recur(ROOT, key).unwrap();
-    let (cell_idx, at_end) = self
-      .pager
-      .get_node_do(leaf_idx, |nd| {
-        let leaf = nd.as_leaf().unwrap();
-        let cell_idx = leaf.search_cell_idx_by_key(key);
-        let at_end = leaf.next.is_none() && cell_idx == leaf.size();
-        (cell_idx, at_end)
-      })
-      .unwrap();
-    Cursor::new(leaf_idx, cell_idx, at_end)
-  }
-
-  pub fn advance_cursor(&self, cursor: &mut Cursor) {
-    cursor.cell_idx += 1;
+  /// Run `f` on the node in page `pid`, reading it in if needed.
+  pub fn node_do<F, T>(&self, pid: usize, f: F) -> Result<T, ExecErr>
+  where
+    F: FnMut(&Node) -> T,
+  {
+    self.pager.get_node_do(pid, f)
+  }
+
+  /// Changes to the tree so far, for a cursor to tell whether its path still holds.
+  pub fn version(&self) -> u64 {
+    self.version
+  }
+
+  /// Number of rows, summed over the cell counts of the leaves without reading any row. The
+  /// leaves are reached down the intern nodes, as a cursor walks them, and a walk that visits
+  /// more nodes than the file has pages must have gone round a loop.
+  pub fn count_rows(&self) -> Result<usize, ExecErr> {
+    let mut count = 0;
+    let mut pending = vec![ROOT];
+    let mut visits = 0;
+    while let Some(pg_idx) = pending.pop() {
+      visits += 1;
+      if visits > self.pager.size() {
+        return Err(ExecErr::Corrupt {
+          page: pg_idx,
+          reason: format!(
+            "the tree has more nodes than the {} pages of the file",
+            self.pager.size()
+          ),
+        });
+      }
+      self.pager.get_node_do(pg_idx, |nd| match nd {
+        Node::Intern(nd) => pending.extend(nd.child_pages()),
+        Node::Leaf(nd) => count += nd.size(),
+      })?;
+    }
+    Ok(count)
+  }
+
+  /// Smallest key, read from the first cell of the leftmost leaf.
+  pub fn first_key(&self) -> Result<Option<u32>, ExecErr> {
+    let leaf_idx = self.find_leaf(0)?;
+    self.pager.get_node_do(leaf_idx, |nd| {
+      Ok(nd.as_leaf()?.cells.first().map(|c| c.key))
+    })?
+  }
+
+  /// Largest key, read from the last cell of the rightmost leaf.
+  pub fn last_key(&self) -> Result<Option<u32>, ExecErr> {
+    let leaf_idx = self.find_leaf(u32::MAX)?;
     self
       .pager
-      .get_node_do(cursor.leaf_idx, |nd| {
-        let leaf = nd.as_leaf().unwrap();
-        if leaf.size() == cursor.cell_idx {
-          match leaf.next {
-            None => cursor.at_end = true,
-            Some(pid) => {
-              cursor.leaf_idx = pid;
-              cursor.cell_idx = 0;
-            }
-          }
-        }
-      })
-      .unwrap();
+      .get_node_do(leaf_idx, |nd| Ok(nd.as_leaf()?.cells.last().map(|c| c.key)))?
   }
 
-  pub fn select_row(&self, cursor: &Cursor) -> Row {
-    let rowbytes = self
-      .pager
-      .get_node_do(cursor.leaf_idx, |nd| {
-        nd.as_leaf().unwrap().cells[cursor.cell_idx].row
-      })
-      .unwrap();
-    Row::deserialize_from(rowbytes)
+  /// Look a row up by its key.
+  pub fn find_row(&self, key: u32) -> Result<Option<Row>, ExecErr> {
+    let leaf_idx = self.find_leaf(key)?;
+    self.pager.get_node_do(leaf_idx, |nd| {
+      let leaf = nd.as_leaf()?;
+      let cell = leaf.cells.get(leaf.search_cell_idx_by_key(key));
+      Ok(
+        cell
+          .filter(|c| c.key == key)
+          .map(|c| Row::deserialize_from(c.row)),
+      )
+    })?
   }
 
-  pub fn btree_to_str(&self) -> String {
-    self.btree_to_str_recur(ROOT)
+  pub fn btree_to_str(&self) -> Result<String, ExecErr> {
+    self.btree_to_str_recur(ROOT, 0)
   }
 
-  fn find_leaf_recur(&self, pg_idx: usize, key: u32) -> Result<usize, ExecErr> {
-    let (is_leaf, pid) = self.pager.get_node_do(pg_idx, |node| match node {
-      Node::Intern(nd) => {
-        let pg_idx = nd.find_child_and(key, |ch| ch.pg_idx)?;
-        Ok((false, pg_idx))
+  /// Page of the leaf that holds `key` or would hold it. A path down longer than the file has
+  /// pages must go round a loop of child pointers, so it is corrupt.
+  fn find_leaf(&self, key: u32) -> Result<usize, ExecErr> {
+    let mut pg_idx = ROOT;
+    for _ in 0..=self.pager.size() {
+      let child = self.pager.get_node_do(pg_idx, |node| match node {
+        Node::Intern(nd) => Some(nd.find_child(key)),
+        Node::Leaf(_) => None,
+      })?;
+      match child {
+        Some(pid) => pg_idx = pid,
+        None => return Ok(pg_idx),
       }
-      Node::Leaf(_) => Ok((true, pg_idx)),
-    })??;
+    }
+    Err(self.too_deep(pg_idx))
+  }
 
-    if is_leaf {
-      Ok(pid)
-    } else {
-      self.find_leaf_recur(pid, key)
+  /// The error for a descent that reached page `pid` without finding a leaf.
+  pub fn too_deep(&self, pid: usize) -> ExecErr {
+    ExecErr::Corrupt {
+      page: pid,
+      reason: format!(
+        "the tree is deeper than the {} pages of the file",
+        self.pager.size()
+      ),
     }
   }
 
-  fn insert_child(&mut self, child: Child, parent: Option<usize>) -> Result<(), ExecErr> {
+  /// Pages in the file, which bounds the depth of the tree.
+  pub fn page_count(&self) -> usize {
+    self.pager.size()
+  }
+
+  /// Put page `pg_idx` into `parent` after the child it split off, with the keys above `key`.
+  fn insert_child(
+    &mut self,
+    key: u32,
+    pg_idx: usize,
+    parent: Option<usize>,
+  ) -> Result<(), ExecErr> {
     match parent {
       // base case
-      None => self.new_root_and_insert_child(child),
+      None => self.new_root_and_insert_child(key, pg_idx),
       // recursive case
       Some(pg) => {
+        let page_size = self.page_size();
         let res = self.pager.set_node_by(pg, |nd| {
-          nd.as_intern_mut()
-            .unwrap()
-            .insert_child(child.pg_idx, child.key_max)
+          nd.as_intern_mut()?.insert_child(key, pg_idx, page_size)
         })?;
         match res {
           Err(ExecErr::InternNodeFull(_)) => {
-            let intern_new = self.pager.set_node_by(pg, |nd| {
-              nd.as_intern_mut()
-                .unwrap()
-                .insert_child_and_split(child.pg_idx, child.key_max)
+            let (key, intern_new) = self.pager.set_node_by(pg, |nd| {
+              Ok::<_, ExecErr>(nd.as_intern_mut()?.insert_child_and_split(key, pg_idx))
             })??;
             let pid_new = self.pager.size();
             let parent = intern_new.parent;
+            let moved: Vec<_> = intern_new.child_pages().collect();
             self.pager.push_node(Node::Intern(intern_new))?;
-
-            let key_max = self.key_max(pid_new);
-            let child = Child::new(pid_new, key_max);
-            self.insert_child(child, parent)
+            self.set_parent(&moved, pid_new)?;
+            self.insert_child(key, pid_new, parent)
           }
           other => other,
         }
@@ -161,72 +230,56 @@ impl Table {
     }
   }
 
-  fn new_root_and_insert_child(&mut self, child_rht: Child) -> Result<(), ExecErr> {
-    self.pager.set_node_by(child_rht.pg_idx, |nd| {
-      nd.set_parent(Some(ROOT));
-    })?;
+  /// Grow the tree by a level: the root moves to a new page, and the root page becomes an
+  /// intern node over it and `pg_idx`, the node split off it above `key`.
+  fn new_root_and_insert_child(&mut self, key: u32, pg_idx: usize) -> Result<(), ExecErr> {
     let pg_idx_new = self.pager.size();
-    let key_max = self.key_max(ROOT);
-    let root_new = self.pager.set_node_by(ROOT, |nd| {
-      nd.set_is_root(false);
-      nd.set_parent(Some(ROOT));
-      let child_lft = Child::new(pg_idx_new, key_max);
-      let children = vec![child_lft, child_rht.clone()];
-      Node::Intern(Intern::new(true, None, children))
-    })?;
+    let children = vec![Child::new(pg_idx_new, key)];
+    let root_new = Node::Intern(Intern::new(true, None, children, pg_idx));
 
     let mut root_old = self.pager.replace_node(ROOT, root_new).unwrap();
+    root_old.set_is_root(false);
     root_old.set_parent(Some(ROOT));
+    let grandchildren: Vec<_> = match &root_old {
+      Node::Intern(nd) => nd.child_pages().collect(),
+      Node::Leaf(_) => vec![],
+    };
     self.pager.push_node(root_old)?;
-    Ok(())
+    self.set_parent(&grandchildren, pg_idx_new)?;
+    self.set_parent(&[pg_idx], ROOT)
   }
 
-  fn key_max(&self, pg_idx: usize) -> u32 {
-    self
-      .pager
-      .get_node_do(pg_idx, |node| match node {
-        Node::Leaf(nd) => nd.key_max(),
-        Node::Intern(nd) => nd
-          .find_child_and(u32::MAX, |ch| self.key_max(ch.pg_idx))
-          .unwrap(),
-      })
-      .unwrap()
-  }
-
-  fn btree_to_str_recur(&self, pg_idx: usize) -> String {
-    let mut res = String::new();
-    let node_str = self
-      .pager
-      .get_node_do(pg_idx, |nd| format!("{}\n", nd))
-      .unwrap();
-    res.push_str(&node_str);
+  /// Point the nodes in `pages` at `parent`, after they moved under it.
+  fn set_parent(&mut self, pages: &[usize], parent: usize) -> Result<(), ExecErr> {
+    for &pid in pages {
+      self
+        .pager
+        .set_node_by(pid, |nd| nd.set_parent(Some(parent)))?;
+    }
+    Ok(())
+  }
 
-    if self.pager.get_node_do(pg_idx, |nd| nd.is_leaf()).unwrap() {
-      return res;
+  fn btree_to_str_recur(&self, pg_idx: usize, depth: usize) -> Result<String, ExecErr> {
+    if depth > self.pager.size() {
+      return Err(self.too_deep(pg_idx));
+    }
+    let mut res = self.pager.get_node_do(pg_idx, |nd| format!("{}\n", nd))?;
+    if self.pager.get_node_do(pg_idx, |nd| nd.is_leaf())? {
+      return Ok(res);
     }
 
-    let s: String = self
-      .pager
-      .get_node_do(pg_idx, |nd| {
-        nd.as_intern()
-          .unwrap()
-          .children
-          .iter()
-          .map(|x| x.pg_idx)
-          .collect::<Vec<_>>()
-      })
-      .unwrap()
-      .into_iter()
-      .map(|pgid| {
-        self
-          .btree_to_str_recur(pgid)
-          .lines()
-          .map(|s| format!("  {}\n", s))
-          .collect::<String>()
-      })
-      .collect();
-
-    res.push_str(&s);
-    res
+    let children = self.pager.get_node_do(pg_idx, |nd| {
+      Ok::<_, ExecErr>(nd.as_intern()?.child_pages().collect::<Vec<_>>())
+    })??;
+
+    for pgid in children {
+      let s: String = self
+        .btree_to_str_recur(pgid, depth + 1)?
+        .lines()
+        .map(|s| format!("  {}\n", s))
+        .collect();
+      res.push_str(&s);
+    }
+    Ok(res)
   }
 }
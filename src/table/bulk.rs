@@ -0,0 +1,150 @@
+use super::{Table, ROOT};
+use crate::btree::intern::{self, Child, Intern};
+use crate::btree::leaf::{self, Cell, Leaf};
+use crate::btree::node::Node;
+use crate::error::ExecErr;
+use crate::row::Row;
+
+impl Table {
+  /// Fill the empty table with `rows`, which must come in ascending key order, building the
+  /// B-tree bottom up in one pass: leaves packed to `fill` of their capacity, then each level
+  /// of intern nodes over the one below until a level fits in the root. Returns the number of
+  /// rows loaded. On error the table is left empty.
+  pub fn bulk_load<E: From<ExecErr>>(
+    &mut self,
+    rows: impl IntoIterator<Item = Result<Row, E>>,
+    fill: f64,
+  ) -> Result<usize, E> {
+    if !(fill > 0.0 && fill <= 1.0) {
+      return Err(
+        ExecErr::Misuse(format!(
+          "Fill factor must be above 0 and at most 1, not {fill}."
+        ))
+        .into(),
+      );
+    }
+    let empty = self.pager.get_node_do(
+      ROOT,
+      |nd| matches!(nd, Node::Leaf(nd) if nd.cells.is_empty()),
+    )?;
+    if !empty {
+      return Err(ExecErr::Misuse("Bulk load needs an empty table.".to_string()).into());
+    }
+    let pages = self.pager.size();
+    self.version += 1;
+    let res = self.build(rows, fill);
+    if res.is_err() {
+      self.pager.truncate(pages);
+    }
+    res
+  }
+
+  fn build<E: From<ExecErr>>(
+    &mut self,
+    rows: impl IntoIterator<Item = Result<Row, E>>,
+    fill: f64,
+  ) -> Result<usize, E> {
+    let per_leaf = capacity(leaf::max_cells(self.page_size()), fill, 1);
+    let mut level = vec![]; // page and key_max of each node of the level last built
+    let mut current = Leaf::new(false, None, None);
+    let mut full = None; // the leaf before `current`, written once it is known not to be last
+    let mut count = 0;
+    for row in rows {
+      let row = row?;
+      if let Some(last) = current.cells.last().map(|c| c.key) {
+        if row.key == last {
+          return Err(ExecErr::DuplicateKey("Duplicated key".to_string()).into());
+        }
+        if row.key < last {
+          return Err(
+            ExecErr::Misuse(format!(
+              "Rows must come in ascending id order: {} after {last}.",
+              row.key
+            ))
+            .into(),
+          );
+        }
+      }
+      if current.size() == per_leaf {
+        if let Some(leaf) = full.take() {
+          self.push_leaf(leaf, true, &mut level)?;
+        }
+        full = Some(std::mem::replace(
+          &mut current,
+          Leaf::new(false, None, None),
+        ));
+      }
+      current.cells.push(Cell {
+        key: row.key,
+        row: row.serialize(),
+      });
+      count += 1;
+    }
+
+    // a single leaf is the root itself
+    let Some(leaf) = full else {
+      current.is_root = true;
+      self.pager.replace_node(ROOT, Node::Leaf(current));
+      return Ok(count);
+    };
+    self.push_leaf(leaf, true, &mut level)?;
+    self.push_leaf(current, false, &mut level)?;
+
+    // nodes of a level share its entries out evenly, so none is left with a single child
+    let per_intern = capacity(intern::child_max(self.page_size()), fill, 4);
+    while level.len() > per_intern {
+      let nodes = level.len().div_ceil(per_intern);
+      let mut upper = vec![];
+      let mut rest = &level[..];
+      for i in 0..nodes {
+        let (children, tail) = rest.split_at(rest.len() / (nodes - i));
+        upper.push(self.push_intern(children)?);
+        rest = tail;
+      }
+      level = upper;
+    }
+    self
+      .pager
+      .replace_node(ROOT, Node::Intern(intern(&level, true)));
+    let pages: Vec<_> = level.iter().map(|&(pid, _)| pid).collect();
+    self.set_parent(&pages, ROOT)?;
+    Ok(count)
+  }
+
+  /// Write `leaf` to a new page, linked to the page after it when `more` leaves follow.
+  fn push_leaf(
+    &mut self,
+    mut leaf: Leaf,
+    more: bool,
+    level: &mut Vec<(usize, u32)>,
+  ) -> Result<(), ExecErr> {
+    let pid = self.pager.size();
+    leaf.next = more.then_some(pid + 1);
+    level.push((pid, leaf.key_max()));
+    self.pager.push_node(Node::Leaf(leaf))
+  }
+
+  /// Write an intern node over `children` to a new page, returning its page and key_max.
+  fn push_intern(&mut self, children: &[(usize, u32)]) -> Result<(usize, u32), ExecErr> {
+    let pid = self.pager.size();
+    let key_max = children[children.len() - 1].1;
+    self
+      .pager
+      .push_node(Node::Intern(intern(children, false)))?;
+    let pages: Vec<_> = children.iter().map(|&(pid, _)| pid).collect();
+    self.set_parent(&pages, pid)?;
+    Ok((pid, key_max))
+  }
+}
+
+/// An intern node over `children`, the largest key of each but the last its separator.
+fn intern(children: &[(usize, u32)], is_root: bool) -> Intern {
+  let (&(right, _), lower) = children.split_last().unwrap();
+  let lower = lower.iter().map(|&(pid, key_max)| Child::new(pid, key_max));
+  Intern::new(is_root, None, lower.collect(), right)
+}
+
+/// Entries to put in a node that holds up to `max`, filled to `fill`.
+fn capacity(max: usize, fill: f64, least: usize) -> usize {
+  ((max as f64 * fill).round() as usize).clamp(least, max)
+}
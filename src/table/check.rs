@@ -0,0 +1,167 @@
+use super::{Table, ROOT};
+use crate::btree::node::Node;
+use crate::error::ExecErr;
+
+/// What the check needs of a node, copied out of the pager's cache.
+enum Shape {
+  Leaf {
+    next: Option<usize>,
+    keys: Vec<u32>,
+  },
+  Intern {
+    children: Vec<(usize, u32)>, // page and separator of each child but the right one
+    right: usize,
+  },
+}
+
+impl Table {
+  /// Walk the whole B-tree from the root, returning a line for every problem found: keys out
+  /// of order within or across nodes, keys outside the range the separators give their node,
+  /// a wrong parent pointer, a leaf chain that skips, repeats or reorders leaves, and pages
+  /// referenced twice or not at all. A sound tree yields no lines.
+  pub fn integrity_check(&self) -> Vec<String> {
+    let mut check = Check {
+      table: self,
+      seen: vec![false; self.pager.size()],
+      leaves: vec![],
+      leaf_depth: None,
+      problems: vec![],
+    };
+    check.node(ROOT, None, None, None, 0);
+    check.leaf_chain();
+    for pid in 0..check.seen.len() {
+      if !check.seen[pid] {
+        check.problem(pid, "never used".to_string());
+      }
+    }
+    check.problems
+  }
+}
+
+struct Check<'t> {
+  table: &'t Table,
+  seen: Vec<bool>,                     // pages reached from the root
+  leaves: Vec<(usize, Option<usize>)>, // page and next leaf of each leaf, in key order
+  leaf_depth: Option<usize>,
+  problems: Vec<String>,
+}
+
+impl Check<'_> {
+  fn problem(&mut self, pid: usize, msg: String) {
+    self.problems.push(format!("Page {pid}: {msg}"));
+  }
+
+  /// Check the subtree at page `pid`, reached from `parent`, whose keys must be greater than
+  /// `lower` and not above `upper`.
+  fn node(
+    &mut self,
+    pid: usize,
+    parent: Option<usize>,
+    lower: Option<u32>,
+    upper: Option<u32>,
+    depth: usize,
+  ) {
+    if self.seen[pid] {
+      self.problem(pid, "referenced more than once".to_string());
+      return;
+    }
+    self.seen[pid] = true;
+    let node = self.table.pager.get_node_do(pid, |nd| {
+      let shape = match nd {
+        Node::Leaf(nd) => Shape::Leaf {
+          next: nd.next,
+          keys: nd.cells.iter().map(|c| c.key).collect(),
+        },
+        Node::Intern(nd) => Shape::Intern {
+          children: nd.children.iter().map(|ch| (ch.pg_idx, ch.key)).collect(),
+          right: nd.right,
+        },
+      };
+      (nd.get_is_root(), nd.get_parent(), shape)
+    });
+    let (is_root, stored_parent, shape) = match node {
+      Ok(node) => node,
+      Err(ExecErr::Corrupt { reason, .. }) => {
+        self.problem(pid, reason);
+        return;
+      }
+      Err(e) => {
+        self.problem(pid, e.to_string());
+        return;
+      }
+    };
+
+    if is_root != (pid == ROOT) {
+      self.problem(
+        pid,
+        format!("marked as {}the root", if is_root { "" } else { "not " }),
+      );
+    }
+    if stored_parent != parent {
+      self.problem(
+        pid,
+        format!(
+          "parent is {}, expected {}",
+          page(stored_parent),
+          page(parent)
+        ),
+      );
+    }
+
+    match shape {
+      Shape::Leaf { next, keys } => {
+        match self.leaf_depth {
+          Some(d) if d != depth => {
+            self.problem(pid, format!("leaf at depth {depth}, other leaves at {d}"))
+          }
+          _ => self.leaf_depth = Some(depth),
+        }
+        let mut prev = lower;
+        for (i, &key) in keys.iter().enumerate() {
+          if prev.is_some_and(|p| key <= p) {
+            self.problem(pid, format!("key {key} of cell {i} is out of order"));
+          }
+          prev = Some(key);
+        }
+        let above = upper.and_then(|u| keys.iter().position(|&key| key > u).map(|i| (i, u)));
+        if let Some((i, upper)) = above {
+          let key = keys[i];
+          self.problem(
+            pid,
+            format!("key {key} of cell {i} is above the separator {upper}"),
+          );
+        }
+        self.leaves.push((pid, next));
+      }
+      Shape::Intern { children, right } => {
+        let mut prev = lower;
+        for (i, &(child, key)) in children.iter().enumerate() {
+          if prev.is_some_and(|p| key <= p) || upper.is_some_and(|u| key > u) {
+            self.problem(pid, format!("separator {key} of child {i} is out of order"));
+          }
+          self.node(child, Some(pid), prev, Some(key), depth + 1);
+          prev = Some(key);
+        }
+        self.node(right, Some(pid), prev, upper, depth + 1);
+      }
+    }
+  }
+
+  /// Each leaf must point to the next one in key order, and the last to none.
+  fn leaf_chain(&mut self) {
+    for i in 0..self.leaves.len() {
+      let (pid, next) = self.leaves[i];
+      let expected = self.leaves.get(i + 1).map(|&(pid, _)| pid);
+      if next != expected {
+        self.problem(
+          pid,
+          format!("next leaf is {}, expected {}", page(next), page(expected)),
+        );
+      }
+    }
+  }
+}
+
+fn page(pid: Option<usize>) -> String {
+  pid.map_or("none".to_string(), |pid| format!("page {pid}"))
+}
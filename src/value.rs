@@ -0,0 +1,207 @@
+use std::cmp::Ordering;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+  Null,
+  Integer(i64),
+  Real(f64),
+  Text(String),
+}
+
+impl Value {
+  pub fn is_null(&self) -> bool {
+    matches!(self, Self::Null)
+  }
+
+  /// Three-valued truthiness: `None` for NULL.
+  pub fn truthy(&self) -> Option<bool> {
+    match self.to_numeric() {
+      Self::Null => None,
+      Self::Integer(v) => Some(v != 0),
+      Self::Real(v) => Some(v != 0.0),
+      Self::Text(_) => unreachable!(),
+    }
+  }
+
+  /// Numeric view of a value; text is converted by its longest numeric prefix, as SQLite does.
+  pub fn to_numeric(&self) -> Value {
+    match self {
+      Self::Text(s) => {
+        let prefix = numeric_prefix(s.trim());
+        if let Ok(v) = prefix.parse::<i64>() {
+          Self::Integer(v)
+        } else if let Ok(v) = prefix.parse::<f64>() {
+          Self::Real(v)
+        } else {
+          Self::Integer(0)
+        }
+      }
+      other => other.clone(),
+    }
+  }
+
+  pub fn as_f64(&self) -> Option<f64> {
+    match self.to_numeric() {
+      Self::Integer(v) => Some(v as f64),
+      Self::Real(v) => Some(v),
+      _ => None,
+    }
+  }
+
+  /// The primary key an equality against `id` can match, if any.
+  pub fn to_key(&self) -> Option<u32> {
+    match self.to_numeric() {
+      Self::Integer(v) => u32::try_from(v).ok(),
+      Self::Real(v) if v.fract() == 0.0 => u32::try_from(v as i64).ok(),
+      _ => None,
+    }
+  }
+
+  /// Total order used by ORDER BY, MIN/MAX and grouping: NULL < numbers < text.
+  pub fn cmp_total(&self, other: &Value) -> Ordering {
+    use Value::*;
+    match (self, other) {
+      (Null, Null) => Ordering::Equal,
+      (Null, _) => Ordering::Less,
+      (_, Null) => Ordering::Greater,
+      (Integer(a), Integer(b)) => a.cmp(b),
+      (Integer(a), Real(b)) => (*a as f64).total_cmp(b),
+      (Real(a), Integer(b)) => a.total_cmp(&(*b as f64)),
+      (Real(a), Real(b)) => a.total_cmp(b),
+      (Text(a), Text(b)) => a.cmp(b),
+      (Text(_), _) => Ordering::Greater,
+      (_, Text(_)) => Ordering::Less,
+    }
+  }
+
+  /// SQL comparison: NULL if either side is NULL.
+  pub fn compare(&self, other: &Value) -> Option<Ordering> {
+    if self.is_null() || other.is_null() {
+      None
+    } else {
+      Some(self.cmp_total(other))
+    }
+  }
+
+  pub fn add(&self, other: &Value) -> Value {
+    arith(self, other, i64::checked_add, |a, b| a + b)
+  }
+
+  pub fn sub(&self, other: &Value) -> Value {
+    arith(self, other, i64::checked_sub, |a, b| a - b)
+  }
+
+  pub fn mul(&self, other: &Value) -> Value {
+    arith(self, other, i64::checked_mul, |a, b| a * b)
+  }
+
+  /// Division by zero yields NULL.
+  pub fn div(&self, other: &Value) -> Value {
+    if other.as_f64() == Some(0.0) {
+      return Value::Null;
+    }
+    arith(self, other, i64::checked_div, |a, b| a / b)
+  }
+
+  pub fn rem(&self, other: &Value) -> Value {
+    if other.as_f64() == Some(0.0) {
+      return Value::Null;
+    }
+    // only i64::MIN % -1 overflows, and its remainder is 0
+    arith(self, other, |a, b| Some(a.wrapping_rem(b)), |a, b| a % b)
+  }
+
+  pub fn neg(&self) -> Value {
+    Value::Integer(0).sub(self)
+  }
+
+  pub fn concat(&self, other: &Value) -> Value {
+    if self.is_null() || other.is_null() {
+      return Value::Null;
+    }
+    Value::Text(format!("{}{}", self.to_text(), other.to_text()))
+  }
+
+  pub fn to_text(&self) -> String {
+    match self {
+      Self::Null => String::new(),
+      Self::Text(s) => s.clone(),
+      other => other.to_string(),
+    }
+  }
+}
+
+/// The longest prefix of `s` that reads as a number: a sign, digits with at most one `.`,
+/// and an exponent if it has digits. Empty when `s` does not start with a digit or a `.`
+/// and a digit.
+fn numeric_prefix(s: &str) -> &str {
+  let b = s.as_bytes();
+  let digits = |from: usize| from + b[from..].iter().take_while(|c| c.is_ascii_digit()).count();
+  let mut end = usize::from(b.first().is_some_and(|c| b"+-".contains(c)));
+  let start = end;
+  end = digits(end);
+  if b.get(end) == Some(&b'.') {
+    end = digits(end + 1);
+  }
+  if end - start <= usize::from(b.get(start) == Some(&b'.')) {
+    return "";
+  }
+  if b.get(end).is_some_and(|c| b"eE".contains(c)) {
+    let sign = end + 1 + usize::from(b.get(end + 1).is_some_and(|c| b"+-".contains(c)));
+    if digits(sign) > sign {
+      end = digits(sign);
+    }
+  }
+  &s[..end]
+}
+
+/// Integers that overflow are redone as reals, as SQLite does.
+fn arith(
+  lhs: &Value,
+  rhs: &Value,
+  int_op: fn(i64, i64) -> Option<i64>,
+  real_op: fn(f64, f64) -> f64,
+) -> Value {
+  match (lhs.to_numeric(), rhs.to_numeric()) {
+    (Value::Null, _) | (_, Value::Null) => Value::Null,
+    (Value::Integer(a), Value::Integer(b)) => int_op(a, b)
+      .map(Value::Integer)
+      .unwrap_or_else(|| Value::Real(real_op(a as f64, b as f64))),
+    (a, b) => Value::Real(real_op(a.as_f64().unwrap(), b.as_f64().unwrap())),
+  }
+}
+
+impl fmt::Display for Value {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::Null => write!(f, "NULL"),
+      Self::Integer(v) => write!(f, "{v}"),
+      Self::Real(v) if v.fract() == 0.0 && v.abs() < 1e15 => write!(f, "{v:.1}"),
+      Self::Real(v) => write!(f, "{v}"),
+      Self::Text(s) => write!(f, "{s:?}"),
+    }
+  }
+}
+
+/// Hashable form of a value, used as the key of hash-based grouping. Integral reals and
+/// integers fall into the same group, and all NULLs are one group, as in SQLite.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum GroupKey {
+  Null,
+  Integer(i64),
+  Real(u64),
+  Text(String),
+}
+
+impl From<&Value> for GroupKey {
+  fn from(val: &Value) -> Self {
+    match val {
+      Value::Null => Self::Null,
+      Value::Integer(v) => Self::Integer(*v),
+      Value::Real(v) if v.fract() == 0.0 && v.abs() < 9e15 => Self::Integer(*v as i64),
+      Value::Real(v) => Self::Real(v.to_bits()),
+      Value::Text(s) => Self::Text(s.clone()),
+    }
+  }
+}
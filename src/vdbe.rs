@@ -0,0 +1,41 @@
+mod compile;
+mod op;
+pub(crate) mod planner;
+mod vm;
+
+pub use compile::compile;
+pub(crate) use compile::scan;
+pub use op::{Op, Rows};
+pub use vm::Vm;
+
+use crate::sql::ast::{Select, Variables};
+use std::rc::Rc;
+
+/// A compiled statement: a flat list of instructions, run from the first one on by a `Vm`,
+/// and the number of registers and cursors they use.
+#[derive(Debug)]
+pub struct Program {
+  pub ops: Vec<Op>,
+  pub registers: usize,
+  pub cursors: usize,
+  /// Names of the columns of each cursor's table.
+  pub tables: Vec<Vec<String>>,
+  /// What EXPLAIN QUERY PLAN shows: a line per loop, sort or subquery, with its nesting depth.
+  pub plan: Vec<(usize, String)>,
+  /// Set for an EXPLAIN statement, whose program lists itself instead of running.
+  pub explain: Option<Explain>,
+  pub variables: Variables,
+  /// Names of the columns of the rows it yields.
+  pub columns: Vec<String>,
+  /// The query it runs, when it is a SELECT: its CTEs, parameters and subqueries are in
+  /// scope of every instruction.
+  pub select: Option<Rc<Select>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Explain {
+  /// Rows of address, opcode, operands p1 to p4 and comment, one per instruction.
+  Program,
+  /// Rows of id, parent id and detail, one per line of the plan.
+  QueryPlan,
+}
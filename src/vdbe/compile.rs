@@ -0,0 +1,735 @@
+use super::planner::{Level, Seek};
+use super::{Explain, Op, Program};
+use crate::error::ExecErr;
+use crate::exec::{self, resolve_column, Column, Source, Subqueries};
+use crate::row;
+use crate::sql::ast::{
+  BinaryOp, CompoundOp, Expr, JoinKind, ResultColumn, Select, Statement, UnaryOp, Variables,
+};
+use crate::table::Table;
+use crate::value::Value;
+use std::rc::Rc;
+
+/// Compile a statement, which takes the parameters `variables`, into a program. Column
+/// references are resolved here, so a query naming a column that does not exist fails before
+/// it reads any row.
+pub fn compile(stmt: &Statement, variables: Variables, table: &Table) -> Result<Program, ExecErr> {
+  let (explain, stmt) = match stmt {
+    Statement::Explain { query_plan, stmt } => {
+      let explain = match query_plan {
+        true => Explain::QueryPlan,
+        false => Explain::Program,
+      };
+      (Some(explain), &**stmt)
+    }
+    stmt => (None, stmt),
+  };
+  let mut builder = Builder::default();
+  let code = match stmt {
+    Statement::Insert(values) => {
+      builder.insert(values)?;
+      builder.finish()
+    }
+    Statement::Analyze => {
+      builder.analyze();
+      builder.finish()
+    }
+    Statement::IntegrityCheck => {
+      builder.integrity_check();
+      builder.finish()
+    }
+    Statement::Select(select) => {
+      let subqueries = Subqueries::default();
+      exec::in_scope(select, table, &[], &subqueries, |scope| {
+        let arms = select.compound.iter().map(|arm| &arm.select);
+        let sources = std::iter::once(&**select)
+          .chain(arms)
+          .map(|core| Source::plan(core, scope))
+          .collect::<Result<Vec<_>, _>>()?;
+        let mut builder = Builder::default();
+        match fallback(select, &sources) {
+          None => builder.select(select, &sources)?,
+          Some(reason) => {
+            builder.emit(Op::Query {
+              select: Rc::clone(select),
+              reason,
+            });
+            builder.emit(Op::Halt);
+          }
+        }
+        Ok::<_, ExecErr>(builder.finish())
+      })?
+    }
+    Statement::Explain { .. } => unreachable!("EXPLAIN does not nest"),
+  };
+  let columns = match (explain, stmt) {
+    (Some(Explain::Program), _) => ["addr", "opcode", "p1", "p2", "p3", "p4", "comment"]
+      .map(str::to_string)
+      .to_vec(),
+    (Some(Explain::QueryPlan), _) => ["id", "parent", "detail"].map(str::to_string).to_vec(),
+    (None, Statement::Select(select)) => exec::column_names(select, table)?,
+    (None, Statement::IntegrityCheck) => vec!["integrity_check".to_string()],
+    (None, _) => vec![],
+  };
+  let (plan, select) = match stmt {
+    Statement::Select(select) => (exec::query_plan(select, table)?, Some(Rc::clone(select))),
+    _ => (vec![], None),
+  };
+  Ok(Program {
+    ops: code.ops,
+    registers: code.registers,
+    cursors: code.tables.len(),
+    tables: code.tables,
+    plan,
+    explain,
+    variables,
+    columns,
+    select,
+  })
+}
+
+/// Compile the loops of `source`, run by the executor as a program of its own: the program
+/// yields each joined row that passes WHERE, after the first `skip`, which it steps over
+/// without reading their columns. Columns that `source` does not have are those of the query
+/// it is a subquery of.
+pub(crate) fn scan(source: &Source, skip: usize) -> Result<Program, ExecErr> {
+  let mut builder = Builder {
+    outer: true,
+    ..Builder::default()
+  };
+  builder.use_source(source);
+  let skip = (skip > 0).then(|| {
+    let reg = builder.alloc(1);
+    builder.emit(Op::Integer {
+      value: skip as i64,
+      dest: reg,
+    });
+    reg
+  });
+  builder.open_cursors(source);
+  let width = builder.columns.len();
+  let body = source.plan.body.clone();
+  let skips = builder.level(0, &mut |b: &mut Builder| {
+    let mut skips = b.terms(&body)?;
+    if let Some(reg) = skip {
+      skips.push(b.emit(Op::IfPos { reg, target: 0 }));
+    }
+    let start = b.alloc(width);
+    for idx in 0..width {
+      b.column(idx, start + idx);
+    }
+    b.emit(Op::ResultRow { start, len: width });
+    Ok(skips)
+  })?;
+  for addr in skips {
+    builder.patch(addr);
+  }
+  builder.emit(Op::Halt);
+  let code = builder.finish();
+  Ok(Program {
+    ops: code.ops,
+    registers: code.registers,
+    cursors: code.tables.len(),
+    tables: code.tables,
+    plan: vec![],
+    explain: None,
+    variables: Variables::default(),
+    columns: vec![],
+    select: None,
+  })
+}
+
+/// Why the compiler does not translate `select` itself, if it does not. It translates queries
+/// that stream their rows: any query of the tables of its FROM clause, joined, filtered and
+/// limited, that neither groups nor computes window functions, and whose ORDER BY, if it has
+/// one, reading its single users table by key already satisfies; and such queries combined
+/// by UNION and UNION ALL, without ORDER BY. Anything else runs as an `Op::Query`, which
+/// EXPLAIN lists with the reason. `sources` holds the FROM clause of each core.
+fn fallback(select: &Select, sources: &[Source]) -> Option<&'static str> {
+  let arms = &select.compound;
+  let cores: Vec<_> = std::iter::once(select)
+    .chain(arms.iter().map(|arm| &arm.select))
+    .collect();
+  let any = |f: fn(&Expr) -> bool| cores.iter().any(|core| core.exprs().into_iter().any(f));
+  if cores.iter().any(|core| core.is_aggregate()) || any(Expr::contains_aggregate) {
+    return Some("aggregate");
+  }
+  if any(Expr::contains_window) {
+    return Some("window function");
+  }
+  if let Some(arm) = arms
+    .iter()
+    .find(|arm| !matches!(arm.op, CompoundOp::Union | CompoundOp::UnionAll))
+  {
+    return Some(arm.op.name());
+  }
+  let sorted = cores
+    .iter()
+    .zip(sources)
+    .all(|(core, source)| exec::streams(core, source));
+  (!sorted).then_some("ORDER BY")
+}
+
+/// What a builder leaves of a program once done.
+struct Code {
+  ops: Vec<Op>,
+  registers: usize,
+  tables: Vec<Vec<String>>, // the column names of each cursor
+}
+
+#[derive(Default)]
+struct Builder<'s> {
+  ops: Vec<Op>,
+  registers: usize,
+  tables: Vec<Vec<String>>,
+  columns: Vec<Column>, // of all joined tables
+  levels: Vec<Level<'s>>,
+  order: Vec<usize>, // of the loops, outermost first
+  open: usize,       // how many loops, in that order, enclose the code being emitted
+  base: usize,       // the cursor of the first table; each core of a compound has its own
+  /// Whether the query is a subquery, whose columns that do not resolve are those of the
+  /// query it is correlated with.
+  outer: bool,
+}
+
+impl<'s> Builder<'s> {
+  fn finish(self) -> Code {
+    Code {
+      ops: self.ops,
+      registers: self.registers,
+      tables: self.tables,
+    }
+  }
+
+  fn emit(&mut self, op: Op) -> usize {
+    self.ops.push(op);
+    self.ops.len() - 1
+  }
+
+  fn here(&self) -> usize {
+    self.ops.len()
+  }
+
+  /// Point the jump at `addr` to the next instruction emitted.
+  fn patch(&mut self, addr: usize) {
+    let here = self.here();
+    self.ops[addr].set_target(here);
+  }
+
+  fn alloc(&mut self, n: usize) -> usize {
+    self.registers += n;
+    self.registers - n
+  }
+
+  fn insert(&mut self, values: &[Expr; 3]) -> Result<(), ExecErr> {
+    self.tables = vec![users_columns()];
+    let key = self.alloc(3);
+    self.emit(Op::OpenWrite { cursor: 0 });
+    for (i, value) in values.iter().enumerate() {
+      self.expr(value, &[], key + i)?;
+    }
+    self.emit(Op::Insert {
+      cursor: 0,
+      key,
+      data: key + 1,
+    });
+    self.emit(Op::Halt);
+    Ok(())
+  }
+
+  fn analyze(&mut self) {
+    self.tables = vec![users_columns()];
+    self.emit(Op::OpenRead { cursor: 0 });
+    self.emit(Op::Analyze { cursor: 0 });
+    self.emit(Op::Halt);
+  }
+
+  fn integrity_check(&mut self) {
+    self.tables = vec![users_columns()];
+    self.emit(Op::OpenRead { cursor: 0 });
+    self.emit(Op::IntegrityCk { cursor: 0 });
+    self.emit(Op::Halt);
+  }
+
+  /// A SELECT runs as one loop per table, nested in the order the planner chose, around the
+  /// code for a single joined row: what is left of WHERE, OFFSET, the result columns and
+  /// LIMIT. The cores of a compound select, one per source, run one after the other and
+  /// share OFFSET and LIMIT; the rows of those up to the last UNION go through a set, which
+  /// drops those seen before.
+  fn select(&mut self, select: &Select, sources: &[Source<'s>]) -> Result<(), ExecErr> {
+    let mut halts = vec![];
+    let limit = match select.limit {
+      Some(0) => {
+        self.emit(Op::Halt);
+        return Ok(());
+      }
+      Some(n) => {
+        let reg = self.alloc(1);
+        self.emit(Op::Integer {
+          value: n as i64,
+          dest: reg,
+        });
+        Some(reg)
+      }
+      None => None,
+    };
+    let offset = (select.offset > 0).then(|| {
+      let reg = self.alloc(1);
+      self.emit(Op::Integer {
+        value: select.offset as i64,
+        dest: reg,
+      });
+      reg
+    });
+    let arms = &select.compound;
+    let distinct = arms
+      .iter()
+      .rposition(|arm| arm.op == CompoundOp::Union)
+      .map(|last| {
+        let cursor = self.tables.len();
+        self.tables.push(vec![]);
+        self.emit(Op::OpenEphemeral { cursor });
+        (cursor, last + 1) // the set, and the last core whose rows go through it
+      });
+
+    let cores = std::iter::once(select).chain(arms.iter().map(|arm| &arm.select));
+    let mut width = None;
+    for (i, (core, source)) in cores.zip(sources).enumerate() {
+      self.use_source(source);
+      self.open_cursors(source);
+      let body = source.plan.body.clone();
+      let set = distinct.filter(|&(_, last)| i <= last).map(|(set, _)| set);
+      let skips = self.level(0, &mut |b: &mut Self| {
+        let mut skips = b.terms(&body)?;
+        // a row the set drops does not count towards OFFSET
+        if let (Some(reg), None) = (offset, set) {
+          skips.push(b.emit(Op::IfPos { reg, target: 0 }));
+        }
+        let (start, len) = b.result_columns(core)?;
+        if *width.get_or_insert(len) != len {
+          return Err(ExecErr::ExprError(format!(
+            "SELECTs to the left and right of {} do not have the same number of result columns.",
+            arms[i - 1].op.name()
+          )));
+        }
+        if let Some(cursor) = set {
+          skips.push(b.emit(Op::Distinct {
+            cursor,
+            start,
+            len,
+            target: 0,
+          }));
+          if let Some(reg) = offset {
+            skips.push(b.emit(Op::IfPos { reg, target: 0 }));
+          }
+        }
+        b.emit(Op::ResultRow { start, len });
+        if let Some(reg) = limit {
+          halts.push(b.emit(Op::DecrJumpZero { reg, target: 0 }));
+        }
+        Ok(skips)
+      })?;
+      for addr in skips {
+        self.patch(addr);
+      }
+    }
+    for addr in halts {
+      self.patch(addr);
+    }
+    self.emit(Op::Halt);
+    Ok(())
+  }
+
+  /// Take the tables of `source`, read by cursors numbered from the next free one in FROM
+  /// order, and the loops the planner chose for them.
+  fn use_source(&mut self, source: &Source<'s>) {
+    let plan = &source.plan;
+    self.base = self.tables.len();
+    self.columns = plan.columns.clone();
+    self.levels = plan.levels.clone();
+    self.order = plan.order.clone();
+    for level in &self.levels {
+      let columns = &self.columns[level.offset..level.offset + level.width];
+      self
+        .tables
+        .push(columns.iter().map(|col| col.name.clone()).collect());
+    }
+  }
+
+  fn open_cursors(&mut self, source: &Source) {
+    for t in 0..self.levels.len() {
+      let cursor = self.base + t;
+      // only the outermost loop can read a recursive CTE as it is computed
+      let outermost = self.order.first() == Some(&t);
+      match source.rows(t, outermost) {
+        Some(rows) => self.emit(Op::OpenRows { cursor, rows }),
+        None => self.emit(Op::OpenRead { cursor }),
+      };
+    }
+  }
+
+  /// Check each term on the current row, returning the jumps taken when one fails.
+  fn terms(&mut self, terms: &[&Expr]) -> Result<Vec<usize>, ExecErr> {
+    let columns = self.columns.clone();
+    let mut skips = vec![];
+    for term in terms {
+      let reg = self.alloc(1);
+      self.expr(term, &columns, reg)?;
+      skips.push(self.emit(Op::IfNot {
+        reg,
+        target: 0,
+        jump_if_null: true,
+      }));
+    }
+    Ok(skips)
+  }
+
+  /// Emit the loop at `depth` and, inside it, those after it and
+  /// `body`. The body returns the jumps that skip the current row, and so does this: they go
+  /// to the step of the innermost loop, which the caller emits.
+  fn level(
+    &mut self,
+    depth: usize,
+    body: &mut dyn FnMut(&mut Self) -> Result<Vec<usize>, ExecErr>,
+  ) -> Result<Vec<usize>, ExecErr> {
+    let Some(&t) = self.order.get(depth) else {
+      return body(self);
+    };
+    let cursor = self.base + t;
+    let level = &self.levels[t];
+    let (kind, seek, desc, terms) = (level.kind, level.seek, level.desc, level.terms.clone());
+    let columns = self.columns.clone();
+
+    // set once a row of this table matched, so that a LEFT JOIN knows to pad with NULLs
+    let matched = (kind == JoinKind::Left).then(|| {
+      let reg = self.alloc(1);
+      self.emit(Op::Integer {
+        value: 0,
+        dest: reg,
+      });
+      reg
+    });
+    let mut exits = vec![];
+    let mut end_bound = None;
+    let open = match seek {
+      Seek::Key(key) => {
+        let reg = self.alloc(1);
+        self.expr(key, &columns, reg)?;
+        self.emit(Op::SeekRowid {
+          cursor,
+          key: reg,
+          target: 0,
+        })
+      }
+      Seek::Range { lower, upper } => {
+        // the loop starts at one bound and ends at the other, which way round depends on
+        // the direction it reads in
+        let (start, end) = if desc { (upper, lower) } else { (lower, upper) };
+        if let Some((op, bound)) = end {
+          let reg = self.alloc(1);
+          self.expr(bound, &columns, reg)?;
+          end_bound = Some((op, reg));
+        }
+        match start {
+          Some((op, bound)) => {
+            let reg = self.alloc(1);
+            self.expr(bound, &columns, reg)?;
+            self.emit(match op {
+              BinaryOp::Gt => Op::SeekGT {
+                cursor,
+                key: reg,
+                target: 0,
+              },
+              BinaryOp::Ge => Op::SeekGE {
+                cursor,
+                key: reg,
+                target: 0,
+              },
+              BinaryOp::Lt => Op::SeekLT {
+                cursor,
+                key: reg,
+                target: 0,
+              },
+              _ => Op::SeekLE {
+                cursor,
+                key: reg,
+                target: 0,
+              },
+            })
+          }
+          None if desc => self.emit(Op::Last { cursor, target: 0 }),
+          None => self.emit(Op::Rewind { cursor, target: 0 }),
+        }
+      }
+      Seek::Scan if desc => self.emit(Op::Last { cursor, target: 0 }),
+      Seek::Scan => self.emit(Op::Rewind { cursor, target: 0 }),
+    };
+    let top = self.here();
+    // rows come in key order, so the first one past the bound it reads towards ends the loop
+    if let Some((op, bound)) = end_bound {
+      let reg = self.alloc(1);
+      self.emit(Op::Rowid { cursor, dest: reg });
+      let past = match op {
+        BinaryOp::Lt => BinaryOp::Ge,
+        BinaryOp::Le => BinaryOp::Gt,
+        BinaryOp::Gt => BinaryOp::Le,
+        _ => BinaryOp::Lt,
+      };
+      self.emit(Op::Binary {
+        op: past,
+        lhs: reg,
+        rhs: bound,
+        dest: reg,
+      });
+      exits.push(self.emit(Op::If {
+        reg,
+        target: 0,
+        jump_if_null: false,
+      }));
+    }
+    self.open = depth + 1;
+    let mut skips = self.terms(&terms)?;
+    if let Some(reg) = matched {
+      self.emit(Op::Integer {
+        value: 1,
+        dest: reg,
+      });
+    }
+    let inner = self.here();
+    skips.extend(self.level(depth + 1, body)?);
+    self.open = depth;
+    for addr in skips {
+      self.patch(addr);
+    }
+    // a table seeked by key has a single row to visit
+    if desc && !matches!(seek, Seek::Key(_)) {
+      self.emit(Op::Prev {
+        cursor,
+        target: top,
+      });
+    } else if !matches!(seek, Seek::Key(_)) {
+      self.emit(Op::Next {
+        cursor,
+        target: top,
+      });
+    }
+    for addr in exits.into_iter().chain([open]) {
+      self.patch(addr);
+    }
+    if let Some(reg) = matched {
+      let done = self.emit(Op::If {
+        reg,
+        target: 0,
+        jump_if_null: false,
+      });
+      self.emit(Op::NullRow { cursor });
+      self.emit(Op::Integer {
+        value: 1,
+        dest: reg,
+      });
+      self.emit(Op::Goto { target: inner });
+      self.patch(done);
+    }
+    Ok(vec![])
+  }
+
+  /// Evaluate the result columns into consecutive registers, returning the first one and
+  /// their number.
+  fn result_columns(&mut self, select: &Select) -> Result<(usize, usize), ExecErr> {
+    let mut items: Vec<Result<&Expr, usize>> = vec![]; // an expression or a column position
+    if select.columns.is_empty() {
+      items.extend((0..self.columns.len()).map(Err));
+    }
+    for col in &select.columns {
+      match col {
+        ResultColumn::Star => items.extend((0..self.columns.len()).map(Err)),
+        ResultColumn::TableStar(table) => {
+          let before = items.len();
+          items.extend(
+            (0..self.columns.len())
+              .filter(|&idx| {
+                self.columns[idx]
+                  .table
+                  .as_deref()
+                  .is_some_and(|t| t.eq_ignore_ascii_case(table))
+              })
+              .map(Err),
+          );
+          if items.len() == before {
+            return Err(ExecErr::NoSuchTable(format!("No such table: {table}.")));
+          }
+        }
+        ResultColumn::Expr { expr, .. } => items.push(Ok(expr)),
+      }
+    }
+    let start = self.alloc(items.len());
+    let columns = self.columns.clone();
+    for (i, item) in items.iter().enumerate() {
+      match item {
+        Ok(expr) => self.expr(expr, &columns, start + i)?,
+        Err(idx) => self.column(*idx, start + i),
+      }
+    }
+    Ok((start, items.len()))
+  }
+
+  /// Read the joined column at `idx` into `dest`.
+  fn column(&mut self, idx: usize, dest: usize) {
+    let t = self
+      .levels
+      .iter()
+      .rposition(|level| level.offset <= idx)
+      .unwrap();
+    let (cursor, level) = (self.base + t, &self.levels[t]);
+    match idx - level.offset {
+      0 if level.users => self.emit(Op::Rowid { cursor, dest }),
+      column => self.emit(Op::Column {
+        cursor,
+        column,
+        dest,
+      }),
+    };
+  }
+
+  /// Emit code leaving the value of `expr` over `columns` in `dest`.
+  fn expr(&mut self, expr: &Expr, columns: &[Column], dest: usize) -> Result<(), ExecErr> {
+    match expr {
+      Expr::Literal(value) => {
+        self.emit(match value {
+          Value::Null => Op::Null { dest },
+          Value::Integer(value) => Op::Integer {
+            value: *value,
+            dest,
+          },
+          Value::Real(value) => Op::Real {
+            value: *value,
+            dest,
+          },
+          Value::Text(value) => Op::String8 {
+            value: value.clone(),
+            dest,
+          },
+        });
+      }
+      Expr::Variable(number) => {
+        self.emit(Op::Variable {
+          number: *number,
+          dest,
+        });
+      }
+      Expr::Column { table, name } => match resolve_column(columns, table.as_deref(), name) {
+        Ok(idx) => self.column(idx, dest),
+        Err(ExecErr::NoSuchColumn(_)) if self.outer => self.eval(expr, dest),
+        Err(e) => return Err(e),
+      },
+      Expr::Unary(op, e) => {
+        let src = self.alloc(1);
+        self.expr(e, columns, src)?;
+        self.emit(match op {
+          UnaryOp::Neg => Op::Negative { src, dest },
+          UnaryOp::Not => Op::Not { src, dest },
+        });
+      }
+      // AND and OR skip their right side once the left one decides the result
+      Expr::Binary(op @ (BinaryOp::And | BinaryOp::Or), l, r) => {
+        let (lhs, rhs) = (self.alloc(1), self.alloc(1));
+        self.expr(l, columns, lhs)?;
+        let and = *op == BinaryOp::And;
+        self.emit(Op::Integer {
+          value: i64::from(!and),
+          dest,
+        });
+        let decided = self.emit(match and {
+          true => Op::IfNot {
+            reg: lhs,
+            target: 0,
+            jump_if_null: false,
+          },
+          false => Op::If {
+            reg: lhs,
+            target: 0,
+            jump_if_null: false,
+          },
+        });
+        self.expr(r, columns, rhs)?;
+        self.emit(Op::Binary {
+          op: *op,
+          lhs,
+          rhs,
+          dest,
+        });
+        self.patch(decided);
+      }
+      Expr::Binary(op, l, r) => {
+        let (lhs, rhs) = (self.alloc(1), self.alloc(1));
+        self.expr(l, columns, lhs)?;
+        self.expr(r, columns, rhs)?;
+        self.emit(Op::Binary {
+          op: *op,
+          lhs,
+          rhs,
+          dest,
+        });
+      }
+      Expr::IsNull { expr, negated } => {
+        let src = self.alloc(1);
+        self.expr(expr, columns, src)?;
+        self.emit(match negated {
+          false => Op::IsNull { src, dest },
+          true => Op::NotNull { src, dest },
+        });
+      }
+      Expr::InList {
+        expr,
+        list,
+        negated,
+      } => {
+        let lhs = self.alloc(1);
+        let start = self.alloc(list.len());
+        self.expr(expr, columns, lhs)?;
+        for (i, item) in list.iter().enumerate() {
+          self.expr(item, columns, start + i)?;
+        }
+        self.emit(Op::InList {
+          lhs,
+          start,
+          len: list.len(),
+          dest,
+        });
+        if *negated {
+          self.emit(Op::Not { src: dest, dest });
+        }
+      }
+      // the executor raises the error for an aggregate or window function out of place
+      Expr::InSelect { .. }
+      | Expr::Exists(_)
+      | Expr::Subquery(_)
+      | Expr::Aggregate { .. }
+      | Expr::Window { .. } => self.eval(expr, dest),
+    }
+    Ok(())
+  }
+
+  /// Emit code having the executor leave the value of `expr` in `dest`, given the columns of
+  /// the tables whose loops enclose it.
+  fn eval(&mut self, expr: &Expr, dest: usize) {
+    let start = self.alloc(self.columns.len());
+    let open: Vec<_> = self.order[..self.open].to_vec();
+    for t in open {
+      let level = &self.levels[t];
+      for idx in level.offset..level.offset + level.width {
+        self.column(idx, start + idx);
+      }
+    }
+    self.emit(Op::Eval {
+      expr: Box::new(expr.clone()),
+      columns: self.columns.clone().into(),
+      start,
+      dest,
+    });
+  }
+}
+
+fn users_columns() -> Vec<String> {
+  row::COLUMNS.map(str::to_string).to_vec()
+}
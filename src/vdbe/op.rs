@@ -0,0 +1,556 @@
+use crate::exec::Column;
+use crate::sql::ast::{BinaryOp, Expr, Select};
+use crate::value::Value;
+use std::rc::Rc;
+
+/// One instruction of a program, modelled on SQLite's opcodes. Registers and cursors are
+/// numbered from 0; a `target` is the address execution continues at when the instruction
+/// jumps. A cursor reads or writes the users table, or reads the rows of a CTE, a subquery in
+/// FROM or `sqlite_stat1`.
+#[derive(Debug, Clone)]
+pub enum Op {
+  /// `r[dest] = value`
+  Integer {
+    value: i64,
+    dest: usize,
+  },
+  Real {
+    value: f64,
+    dest: usize,
+  },
+  String8 {
+    value: String,
+    dest: usize,
+  },
+  Null {
+    dest: usize,
+  },
+  /// `r[dest]` = the value bound to parameter `number`, or NULL
+  Variable {
+    number: usize,
+    dest: usize,
+  },
+  Goto {
+    target: usize,
+  },
+  /// Jump if `r[reg]` is true, or NULL when `jump_if_null` is set.
+  If {
+    reg: usize,
+    target: usize,
+    jump_if_null: bool,
+  },
+  /// Jump if `r[reg]` is false, or NULL when `jump_if_null` is set.
+  IfNot {
+    reg: usize,
+    target: usize,
+    jump_if_null: bool,
+  },
+  /// If `r[reg]` is positive, decrement it and jump. Counts down OFFSET.
+  IfPos {
+    reg: usize,
+    target: usize,
+  },
+  /// Decrement `r[reg]` and jump once it reaches zero. Counts down LIMIT.
+  DecrJumpZero {
+    reg: usize,
+    target: usize,
+  },
+  OpenRead {
+    cursor: usize,
+  },
+  OpenWrite {
+    cursor: usize,
+  },
+  /// Open a cursor over `rows`, which are computed here, except those of a recursive CTE
+  /// read by the outermost loop: they are computed one at a time as the cursor advances.
+  OpenRows {
+    cursor: usize,
+    rows: Rows,
+  },
+  /// Open a cursor over an empty set of rows, for `Distinct`.
+  OpenEphemeral {
+    cursor: usize,
+  },
+  /// Jump if the set of `cursor` has the row `r[start..start + len]`, else add it.
+  Distinct {
+    cursor: usize,
+    start: usize,
+    len: usize,
+    target: usize,
+  },
+  /// Move to the first row; jump if the table is empty.
+  Rewind {
+    cursor: usize,
+    target: usize,
+  },
+  /// Move to the next row; jump back to `target` unless there is none.
+  Next {
+    cursor: usize,
+    target: usize,
+  },
+  /// Move to the last row; jump if the table is empty.
+  Last {
+    cursor: usize,
+    target: usize,
+  },
+  /// Move to the previous row; jump back to `target` unless there is none.
+  Prev {
+    cursor: usize,
+    target: usize,
+  },
+  /// Move to the row whose key is `r[key]`; jump if there is none.
+  SeekRowid {
+    cursor: usize,
+    key: usize,
+    target: usize,
+  },
+  /// Move to the first row whose key is at least `r[key]`; jump if there is none. A key that
+  /// is not a number does not narrow the scan, which then starts at the first row.
+  SeekGE {
+    cursor: usize,
+    key: usize,
+    target: usize,
+  },
+  /// As `SeekGE`, for the first row whose key is greater than `r[key]`.
+  SeekGT {
+    cursor: usize,
+    key: usize,
+    target: usize,
+  },
+  /// Move to the last row whose key is at most `r[key]`, to read rows backwards from there;
+  /// jump if there is none. A key that is not a number starts at the last row.
+  SeekLE {
+    cursor: usize,
+    key: usize,
+    target: usize,
+  },
+  /// As `SeekLE`, for the last row whose key is less than `r[key]`.
+  SeekLT {
+    cursor: usize,
+    key: usize,
+    target: usize,
+  },
+  /// Make every column of the cursor read as NULL until it moves again, for the unmatched
+  /// side of a LEFT JOIN.
+  NullRow {
+    cursor: usize,
+  },
+  /// `r[dest]` = column `column` of the cursor's row
+  Column {
+    cursor: usize,
+    column: usize,
+    dest: usize,
+  },
+  /// `r[dest]` = key of the cursor's row
+  Rowid {
+    cursor: usize,
+    dest: usize,
+  },
+  /// `r[dest] = r[lhs] op r[rhs]`, for arithmetic, comparisons, AND, OR and `||`
+  Binary {
+    op: BinaryOp,
+    lhs: usize,
+    rhs: usize,
+    dest: usize,
+  },
+  Not {
+    src: usize,
+    dest: usize,
+  },
+  Negative {
+    src: usize,
+    dest: usize,
+  },
+  IsNull {
+    src: usize,
+    dest: usize,
+  },
+  NotNull {
+    src: usize,
+    dest: usize,
+  },
+  /// `r[dest]` = the value of `expr` for the joined row of `columns` in `r[start..]`, by the
+  /// executor. Subqueries, and columns of the query a subquery is correlated with, compile
+  /// to this.
+  Eval {
+    expr: Box<Expr>,
+    columns: Rc<[Column]>,
+    start: usize,
+    dest: usize,
+  },
+  /// `r[dest] = r[lhs] IN (r[start], .., r[start + len - 1])`
+  InList {
+    lhs: usize,
+    start: usize,
+    len: usize,
+    dest: usize,
+  },
+  /// Yield `r[start..start + len]` as a result row.
+  ResultRow {
+    start: usize,
+    len: usize,
+  },
+  /// Insert the row with key `r[key]`, username `r[data]` and email `r[data + 1]`.
+  Insert {
+    cursor: usize,
+    key: usize,
+    data: usize,
+  },
+  /// Gather statistics on the table for the planner, readable as `sqlite_stat1`.
+  Analyze {
+    cursor: usize,
+  },
+  /// Check the whole B-tree, yielding a row per problem found, or a single `ok`.
+  IntegrityCk {
+    cursor: usize,
+  },
+  /// Run a whole SELECT on the tree-walking executor and yield its rows. Queries that group,
+  /// sort, compute window functions or combine selects by INTERSECT or EXCEPT compile to this
+  /// alone, with the `reason` they do; the executor runs their loops as compiled programs of
+  /// their own.
+  Query {
+    select: Rc<Select>,
+    reason: &'static str,
+  },
+  Halt,
+}
+
+impl Op {
+  /// Point a jump at `target`.
+  pub fn set_target(&mut self, to: usize) {
+    match self {
+      Self::Goto { target }
+      | Self::If { target, .. }
+      | Self::IfNot { target, .. }
+      | Self::IfPos { target, .. }
+      | Self::DecrJumpZero { target, .. }
+      | Self::Distinct { target, .. }
+      | Self::Rewind { target, .. }
+      | Self::Next { target, .. }
+      | Self::Last { target, .. }
+      | Self::Prev { target, .. }
+      | Self::SeekRowid { target, .. }
+      | Self::SeekGE { target, .. }
+      | Self::SeekGT { target, .. }
+      | Self::SeekLE { target, .. }
+      | Self::SeekLT { target, .. } => *target = to,
+      other => unreachable!("{other:?} does not jump"),
+    }
+  }
+
+  /// The row EXPLAIN lists this instruction as: address, opcode, operands p1 to p4 and a
+  /// comment on what it does. `tables` holds the column names of each cursor.
+  pub fn explain(&self, addr: usize, tables: &[Vec<String>]) -> Vec<Value> {
+    let n = |n: &usize| *n as i64;
+    let (opcode, [p1, p2, p3], p4, comment) = match self {
+      Self::Integer { value, dest } => (
+        "Integer",
+        [*value, n(dest), 0],
+        None,
+        format!("r[{dest}]={value}"),
+      ),
+      Self::Real { value, dest } => (
+        "Real",
+        [0, n(dest), 0],
+        Some(value.to_string()),
+        format!("r[{dest}]={value}"),
+      ),
+      Self::String8 { value, dest } => (
+        "String8",
+        [0, n(dest), 0],
+        Some(value.clone()),
+        format!("r[{dest}]='{value}'"),
+      ),
+      Self::Null { dest } => ("Null", [0, n(dest), 0], None, format!("r[{dest}]=NULL")),
+      Self::Variable { number, dest } => (
+        "Variable",
+        [n(number), n(dest), 0],
+        None,
+        format!("r[{dest}]=parameter({number})"),
+      ),
+      Self::Goto { target } => ("Goto", [0, n(target), 0], None, String::new()),
+      Self::If {
+        reg,
+        target,
+        jump_if_null,
+      } => (
+        "If",
+        [n(reg), n(target), i64::from(*jump_if_null)],
+        None,
+        format!("if r[{reg}] goto {target}"),
+      ),
+      Self::IfNot {
+        reg,
+        target,
+        jump_if_null,
+      } => (
+        "IfNot",
+        [n(reg), n(target), i64::from(*jump_if_null)],
+        None,
+        format!("if !r[{reg}] goto {target}"),
+      ),
+      Self::IfPos { reg, target } => (
+        "IfPos",
+        [n(reg), n(target), 1],
+        None,
+        format!("if r[{reg}]>0 then r[{reg}]-=1, goto {target}"),
+      ),
+      Self::DecrJumpZero { reg, target } => (
+        "DecrJumpZero",
+        [n(reg), n(target), 0],
+        None,
+        format!("if (--r[{reg}])==0 goto {target}"),
+      ),
+      Self::OpenRead { cursor } => (
+        "OpenRead",
+        [n(cursor), 0, 0],
+        Some("users".to_string()),
+        "root=0".to_string(),
+      ),
+      Self::OpenWrite { cursor } => (
+        "OpenWrite",
+        [n(cursor), 0, 0],
+        Some("users".to_string()),
+        "root=0".to_string(),
+      ),
+      Self::OpenRows { cursor, rows } => (
+        "OpenRows",
+        [n(cursor), 0, 0],
+        Some(rows.name().to_string()),
+        match rows {
+          Rows::Recursive { .. } => "computed as read",
+          _ => "materialize",
+        }
+        .to_string(),
+      ),
+      Self::OpenEphemeral { cursor } => ("OpenEphemeral", [n(cursor), 0, 0], None, String::new()),
+      Self::Distinct {
+        cursor,
+        start,
+        len,
+        target,
+      } => (
+        "Distinct",
+        [n(cursor), n(target), n(start)],
+        Some(len.to_string()),
+        format!("if r[{start}..{}] in set goto {target}", start + len - 1),
+      ),
+      Self::Rewind { cursor, target } => ("Rewind", [n(cursor), n(target), 0], None, String::new()),
+      Self::Next { cursor, target } => ("Next", [n(cursor), n(target), 0], None, String::new()),
+      Self::Last { cursor, target } => ("Last", [n(cursor), n(target), 0], None, String::new()),
+      Self::Prev { cursor, target } => ("Prev", [n(cursor), n(target), 0], None, String::new()),
+      Self::SeekRowid {
+        cursor,
+        key,
+        target,
+      } => (
+        "SeekRowid",
+        [n(cursor), n(target), n(key)],
+        None,
+        format!("intkey=r[{key}]"),
+      ),
+      Self::SeekGE {
+        cursor,
+        key,
+        target,
+      } => (
+        "SeekGE",
+        [n(cursor), n(target), n(key)],
+        None,
+        format!("key=r[{key}]"),
+      ),
+      Self::SeekGT {
+        cursor,
+        key,
+        target,
+      } => (
+        "SeekGT",
+        [n(cursor), n(target), n(key)],
+        None,
+        format!("key=r[{key}]"),
+      ),
+      Self::SeekLE {
+        cursor,
+        key,
+        target,
+      } => (
+        "SeekLE",
+        [n(cursor), n(target), n(key)],
+        None,
+        format!("key=r[{key}]"),
+      ),
+      Self::SeekLT {
+        cursor,
+        key,
+        target,
+      } => (
+        "SeekLT",
+        [n(cursor), n(target), n(key)],
+        None,
+        format!("key=r[{key}]"),
+      ),
+      Self::NullRow { cursor } => ("NullRow", [n(cursor), 0, 0], None, String::new()),
+      Self::Column {
+        cursor,
+        column,
+        dest,
+      } => (
+        "Column",
+        [n(cursor), n(column), n(dest)],
+        None,
+        format!("r[{dest}]={}", tables[*cursor][*column]),
+      ),
+      Self::Rowid { cursor, dest } => (
+        "Rowid",
+        [n(cursor), n(dest), 0],
+        None,
+        format!("r[{dest}]=id"),
+      ),
+      Self::Binary { op, lhs, rhs, dest } => {
+        let (opcode, symbol) = binary_opcode(*op);
+        (
+          opcode,
+          [n(lhs), n(rhs), n(dest)],
+          None,
+          format!("r[{dest}]=r[{lhs}]{symbol}r[{rhs}]"),
+        )
+      }
+      Self::Not { src, dest } => (
+        "Not",
+        [n(src), n(dest), 0],
+        None,
+        format!("r[{dest}]=!r[{src}]"),
+      ),
+      Self::Negative { src, dest } => (
+        "Negative",
+        [n(src), n(dest), 0],
+        None,
+        format!("r[{dest}]=-r[{src}]"),
+      ),
+      Self::IsNull { src, dest } => (
+        "IsNull",
+        [n(src), n(dest), 0],
+        None,
+        format!("r[{dest}]=r[{src}] IS NULL"),
+      ),
+      Self::NotNull { src, dest } => (
+        "NotNull",
+        [n(src), n(dest), 0],
+        None,
+        format!("r[{dest}]=r[{src}] IS NOT NULL"),
+      ),
+      Self::Eval {
+        columns,
+        start,
+        dest,
+        ..
+      } => (
+        "Eval",
+        [n(start), n(dest), 0],
+        None,
+        match columns.len() {
+          0 => format!("r[{dest}]=expr"),
+          len => format!("r[{dest}]=expr(r[{start}..{}])", start + len - 1),
+        },
+      ),
+      Self::InList {
+        lhs,
+        start,
+        len,
+        dest,
+      } => (
+        "InList",
+        [n(lhs), n(start), n(dest)],
+        Some(len.to_string()),
+        format!("r[{dest}]=r[{lhs}] IN r[{start}..{}]", start + len - 1),
+      ),
+      Self::ResultRow { start, len } => (
+        "ResultRow",
+        [n(start), n(len), 0],
+        None,
+        format!("output=r[{start}..{}]", start + len - 1),
+      ),
+      Self::Insert { cursor, key, data } => (
+        "Insert",
+        [n(cursor), n(data), n(key)],
+        Some("users".to_string()),
+        format!("intkey=r[{key}] data=r[{data}..{}]", data + 1),
+      ),
+      Self::Analyze { cursor } => (
+        "Analyze",
+        [n(cursor), 0, 0],
+        Some("users".to_string()),
+        "sqlite_stat1=stats of users".to_string(),
+      ),
+      Self::IntegrityCk { cursor } => (
+        "IntegrityCk",
+        [n(cursor), 0, 0],
+        Some("users".to_string()),
+        "check the B-tree".to_string(),
+      ),
+      Self::Query { reason, .. } => (
+        "Query",
+        [0, 0, 0],
+        Some(reason.to_string()),
+        "all rows by the executor".to_string(),
+      ),
+      Self::Halt => ("Halt", [0, 0, 0], None, String::new()),
+    };
+    vec![
+      Value::Integer(addr as i64),
+      Value::Text(opcode.to_string()),
+      Value::Integer(p1),
+      Value::Integer(p2),
+      Value::Integer(p3),
+      p4.map_or(Value::Null, Value::Text),
+      Value::Text(comment),
+    ]
+  }
+}
+
+/// What an `Op::OpenRows` cursor reads.
+#[derive(Debug, Clone)]
+pub enum Rows {
+  /// A CTE, or the working table of a recursive one, computed whole.
+  Cte {
+    name: String,
+    columns: Vec<String>,
+  },
+  /// A recursive CTE, computed a row at a time.
+  Recursive {
+    name: String,
+    columns: Vec<String>,
+  },
+  Subquery(Rc<Select>),
+  Stat, // sqlite_stat1
+}
+
+impl Rows {
+  fn name(&self) -> &str {
+    match self {
+      Self::Cte { name, .. } | Self::Recursive { name, .. } => name,
+      Self::Subquery(_) => "(subquery)",
+      Self::Stat => "sqlite_stat1",
+    }
+  }
+}
+
+/// The opcode of a binary operator, and how comments write it.
+fn binary_opcode(op: BinaryOp) -> (&'static str, &'static str) {
+  match op {
+    BinaryOp::Or => ("Or", " OR "),
+    BinaryOp::And => ("And", " AND "),
+    BinaryOp::Eq => ("Eq", "=="),
+    BinaryOp::Ne => ("Ne", "!="),
+    BinaryOp::Lt => ("Lt", "<"),
+    BinaryOp::Le => ("Le", "<="),
+    BinaryOp::Gt => ("Gt", ">"),
+    BinaryOp::Ge => ("Ge", ">="),
+    BinaryOp::Add => ("Add", "+"),
+    BinaryOp::Sub => ("Subtract", "-"),
+    BinaryOp::Mul => ("Multiply", "*"),
+    BinaryOp::Div => ("Divide", "/"),
+    BinaryOp::Rem => ("Remainder", "%"),
+    BinaryOp::Concat => ("Concat", "||"),
+  }
+}
@@ -0,0 +1,486 @@
+use crate::error::ExecErr;
+use crate::exec::{resolve_column, Column};
+use crate::row;
+use crate::sql::ast::{BinaryOp, Expr, JoinKind, ResultColumn, Select};
+use crate::stats::Stats;
+
+/// Rows a table is assumed to have before ANALYZE, as SQLite assumes.
+const DEFAULT_ROWS: f64 = 1_000_000.0;
+/// Joins of more tables than this run in FROM order instead of trying every order.
+const MAX_REORDERED: usize = 6;
+
+/// How a SELECT reads its tables: the access path of each one and the order their loops nest
+/// in, chosen as the cheapest by estimated cost, and where each term of WHERE and of the join
+/// predicates is checked. Compiled queries and those the executor runs share it.
+pub struct Plan<'s> {
+  pub columns: Vec<Column>, // of all joined tables, in FROM order
+  pub levels: Vec<Level<'s>>,
+  /// The loops, outermost first, as positions in `levels`.
+  pub order: Vec<usize>,
+  /// Terms checked once every table has its row.
+  pub body: Vec<&'s Expr>,
+}
+
+/// A table of the FROM clause as the planner is given it.
+pub struct Table<'s> {
+  pub name: String,              // in EXPLAIN QUERY PLAN
+  pub qualifier: Option<String>, // what the query calls its columns
+  pub columns: Vec<String>,
+  /// Whether it is the users table, which can be seeked by its key, its first column, and
+  /// which ANALYZE describes. Other tables are scanned.
+  pub users: bool,
+  pub kind: JoinKind,
+  pub on: Option<&'s Expr>,
+}
+
+/// A table of the FROM clause, read by the cursor of the same number.
+#[derive(Clone)]
+pub struct Level<'s> {
+  pub name: String,
+  pub offset: usize, // position of its first column in `columns`
+  pub width: usize,
+  pub users: bool,
+  pub kind: JoinKind,
+  pub seek: Seek<'s>,
+  /// Read from the largest key down, for ORDER BY id DESC.
+  pub desc: bool,
+  /// Terms checked as soon as this table has its row.
+  pub terms: Vec<&'s Expr>,
+}
+
+/// How a loop finds its rows. The terms a seek is derived from are still checked on every
+/// row, so a key that turns out not to be a number can fall back to reading more rows.
+#[derive(Clone, Copy)]
+pub enum Seek<'s> {
+  Scan,
+  /// The single row whose key is the value of the expression.
+  Key(&'s Expr),
+  /// The rows between two bounds, given as `id op expr` with `op` one of `>`, `>=`, `<` and
+  /// `<=`.
+  Range {
+    lower: Option<(BinaryOp, &'s Expr)>,
+    upper: Option<(BinaryOp, &'s Expr)>,
+  },
+}
+
+/// A conjunct of WHERE or of a join predicate.
+struct Term<'s> {
+  expr: &'s Expr,
+  tables: Vec<usize>, // that it reads
+  home: Home,
+  keys: Vec<KeyTerm<'s>>,
+}
+
+/// Where a term may be checked. With a LEFT JOIN, tables keep their FROM order, a join
+/// predicate decides which rows are matched and WHERE filters the padded rows too; joins
+/// that are all inner can check every term at the first loop that has all it reads.
+#[derive(Clone, Copy, PartialEq)]
+enum Home {
+  Any,
+  Level(usize),
+  Body,
+}
+
+/// A term read as `id op expr` on the key of `table`, with the tables `expr` reads.
+struct KeyTerm<'s> {
+  table: usize,
+  op: BinaryOp,
+  expr: &'s Expr,
+  needs: Vec<usize>,
+}
+
+/// An access path with its estimated cost, in rows read, and the rows it yields.
+struct Access<'s> {
+  seek: Seek<'s>,
+  used: Vec<usize>, // the terms it seeks by
+  cost: f64,
+  rows: f64,
+}
+
+impl<'s> Plan<'s> {
+  /// Plan `select` over `tables`, those of its FROM clause in order.
+  pub fn for_tables(
+    select: &'s Select,
+    tables: Vec<Table<'s>>,
+    stats: Option<&Stats>,
+  ) -> Result<Self, ExecErr> {
+    let mut plan = Self {
+      columns: vec![],
+      levels: vec![],
+      order: vec![],
+      body: vec![],
+    };
+    let mut ons = vec![];
+    for table in tables {
+      ons.push(table.on);
+      plan.push_level(table);
+    }
+
+    // a join predicate reads the tables up to its own only, whatever order the loops take
+    for (level, on) in plan.levels.iter().zip(&ons) {
+      if let Some(on) = on {
+        let columns = &plan.columns[..level.offset + level.width];
+        let mut res = Ok(0);
+        on.for_each_column(&mut |table, name| {
+          if res.is_ok() {
+            res = resolve_column(columns, table, name);
+          }
+        });
+        res?;
+      }
+    }
+
+    let left = plan.levels.iter().any(|level| level.kind == JoinKind::Left);
+    let mut planner = Planner {
+      columns: &plan.columns,
+      levels: &plan.levels,
+      terms: vec![],
+      rows: stats.map_or(DEFAULT_ROWS, |s| s.rows as f64).max(1.0),
+      stats,
+    };
+    for (i, on) in ons.iter().enumerate() {
+      for expr in on.iter().flat_map(|on| on.conjuncts()) {
+        let home = if left { Home::Level(i) } else { Home::Any };
+        planner.terms.push(planner.term(expr, home));
+      }
+    }
+    for expr in select.where_clause.iter().flat_map(Expr::conjuncts) {
+      let home = if left { Home::Body } else { Home::Any };
+      planner.terms.push(planner.term(expr, home));
+    }
+
+    let order = match left || plan.levels.len() > MAX_REORDERED {
+      true => (0..plan.levels.len()).collect(),
+      false => planner.best_order(),
+    };
+    let seeks: Vec<_> = (0..order.len())
+      .map(|pos| planner.access(order[pos], &order[..pos]).seek)
+      .collect();
+    let mut homes = vec![];
+    for term in &planner.terms {
+      homes.push((term.expr, planner.position(term, &order)));
+    }
+    for (&t, seek) in order.iter().zip(seeks) {
+      plan.levels[t].seek = seek;
+    }
+    if let [level] = plan.levels.as_mut_slice() {
+      level.desc = level.users && key_order(select) == Some(true);
+    }
+    for (expr, pos) in homes {
+      match pos {
+        Some(pos) => plan.levels[order[pos]].terms.push(expr),
+        None => plan.body.push(expr),
+      }
+    }
+    plan.order = order;
+    Ok(plan)
+  }
+
+  fn push_level(&mut self, table: Table<'s>) {
+    let offset = self.columns.len();
+    let width = table.columns.len();
+    self
+      .columns
+      .extend(table.columns.into_iter().map(|name| Column {
+        table: table.qualifier.clone(),
+        name,
+      }));
+    self.levels.push(Level {
+      name: table.name,
+      offset,
+      width,
+      users: table.users,
+      kind: table.kind,
+      seek: Seek::Scan,
+      desc: false,
+      terms: vec![],
+    });
+  }
+}
+
+/// Whether a single-table `select` yields its rows in the order its ORDER BY asks for when
+/// the table is read by key: `Some(false)` reading forwards, as with no ORDER BY or with
+/// `ORDER BY id`, `Some(true)` reading backwards for `ORDER BY id DESC`, and none when the
+/// rows need sorting.
+pub fn key_order(select: &Select) -> Option<bool> {
+  let [term] = select.order_by.as_slice() else {
+    return select.order_by.is_empty().then_some(false);
+  };
+  let qualifier = match &select.from {
+    None if select.columns.is_empty() => "users",
+    Some(from) if from.joins.is_empty() => from.base.qualifier()?,
+    _ => return None,
+  };
+  let Expr::Column { table, name } = &term.expr else {
+    return None;
+  };
+  // a term naming a result column sorts on it, whatever it holds
+  let aliased = select.columns.iter().any(|col| {
+    matches!(col, ResultColumn::Expr { alias: Some(alias), .. } if alias.eq_ignore_ascii_case(name))
+  });
+  let own = table
+    .as_deref()
+    .is_none_or(|t| t.eq_ignore_ascii_case(qualifier));
+  (name.eq_ignore_ascii_case(row::COLUMNS[0]) && own && !aliased).then_some(term.desc)
+}
+
+impl Level<'_> {
+  /// The line of EXPLAIN QUERY PLAN for this loop.
+  pub fn describe(&self) -> String {
+    match self.seek {
+      Seek::Scan => format!("SCAN {}", self.name),
+      Seek::Key(_) => format!("SEARCH {} USING PRIMARY KEY (id=?)", self.name),
+      Seek::Range { lower, upper } => {
+        let bounds: Vec<_> = [lower, upper]
+          .into_iter()
+          .flatten()
+          .map(|(op, _)| format!("id{}?", comparison(op)))
+          .collect();
+        format!(
+          "SEARCH {} USING PRIMARY KEY ({})",
+          self.name,
+          bounds.join(" AND ")
+        )
+      }
+    }
+  }
+}
+
+struct Planner<'a, 's> {
+  columns: &'a [Column],
+  levels: &'a [Level<'s>],
+  terms: Vec<Term<'s>>,
+  rows: f64, // in each table
+  stats: Option<&'a Stats>,
+}
+
+impl<'s> Planner<'_, 's> {
+  fn term(&self, expr: &'s Expr, home: Home) -> Term<'s> {
+    let mut keys = vec![];
+    if let Expr::Binary(
+      op @ (BinaryOp::Eq | BinaryOp::Lt | BinaryOp::Le | BinaryOp::Gt | BinaryOp::Ge),
+      l,
+      r,
+    ) = expr
+    {
+      for (key, other, op) in [(l, r, *op), (r, l, flip(*op))] {
+        let Some((table, 0)) = self.column(key).map(|idx| self.locate(idx)) else {
+          continue;
+        };
+        let needs = self.tables(other);
+        if self.levels[table].users && !needs.contains(&table) {
+          keys.push(KeyTerm {
+            table,
+            op,
+            expr: other,
+            needs,
+          });
+        }
+      }
+    }
+    Term {
+      expr,
+      tables: self.tables(expr),
+      home,
+      keys,
+    }
+  }
+
+  /// The joined column `expr` is, if it is one.
+  fn column(&self, expr: &Expr) -> Option<usize> {
+    match expr {
+      Expr::Column { table, name } => resolve_column(self.columns, table.as_deref(), name).ok(),
+      _ => None,
+    }
+  }
+
+  /// The table the joined column at `idx` belongs to, and its position in that table.
+  fn locate(&self, idx: usize) -> (usize, usize) {
+    let t = self
+      .levels
+      .iter()
+      .rposition(|level| level.offset <= idx)
+      .unwrap();
+    (t, idx - self.levels[t].offset)
+  }
+
+  /// The tables `expr` reads. A column that does not resolve reads none of them: it is one of
+  /// an enclosing query, fixed while this one runs, or an error raised wherever the term ends
+  /// up.
+  fn tables(&self, expr: &Expr) -> Vec<usize> {
+    let mut tables = vec![];
+    expr.for_each_column(&mut |table, name| {
+      if let Ok(idx) = resolve_column(self.columns, table, name) {
+        tables.push(self.locate(idx).0);
+      }
+    });
+    tables.sort_unstable();
+    tables.dedup();
+    tables
+  }
+
+  /// The cheapest order of the loops, trying them all. Among equally cheap ones, the first
+  /// in FROM order wins.
+  fn best_order(&self) -> Vec<usize> {
+    let mut orders = vec![];
+    permutations(&mut vec![], self.levels.len(), &mut orders);
+    let mut best: Option<(f64, Vec<usize>)> = None;
+    for order in orders {
+      let cost = self.cost(&order);
+      if best.as_ref().is_none_or(|(least, _)| cost < *least) {
+        best = Some((cost, order));
+      }
+    }
+    best.map(|(_, order)| order).unwrap_or_default()
+  }
+
+  /// Estimated rows read by running the loops in `order`: each loop runs once per row that
+  /// the loops around it let through.
+  fn cost(&self, order: &[usize]) -> f64 {
+    let (mut total, mut loops) = (0.0, 1.0);
+    for (pos, &t) in order.iter().enumerate() {
+      let access = self.access(t, &order[..pos]);
+      total += loops * access.cost;
+      let mut rows = access.rows;
+      for (i, term) in self.terms.iter().enumerate() {
+        if self.position(term, order) == Some(pos) && !access.used.contains(&i) {
+          rows *= self.selectivity(term);
+        }
+      }
+      loops *= rows;
+    }
+    total
+  }
+
+  /// The cheapest way to read table `t` inside the loops over `outer`.
+  fn access(&self, t: usize, outer: &[usize]) -> Access<'s> {
+    let usable: Vec<_> = self
+      .terms
+      .iter()
+      .enumerate()
+      .filter(|(_, term)| match term.home {
+        Home::Any => true,
+        Home::Level(i) => i == t,
+        // WHERE seeks no table that a LEFT JOIN pads with NULLs
+        Home::Body => self.levels[t].kind == JoinKind::Inner,
+      })
+      .flat_map(|(i, term)| term.keys.iter().map(move |key| (i, key)))
+      .filter(|(_, key)| key.table == t && key.needs.iter().all(|n| outer.contains(n)))
+      .collect();
+    let find = |ops: &[BinaryOp]| usable.iter().find(|(_, key)| ops.contains(&key.op));
+
+    let n = self.rows;
+    let lookup = n.log2().max(1.0);
+    let mut candidates = vec![];
+    if let Some((i, key)) = find(&[BinaryOp::Eq]) {
+      candidates.push(Access {
+        seek: Seek::Key(key.expr),
+        used: vec![*i],
+        cost: lookup,
+        rows: 1.0,
+      });
+    }
+    let lower = find(&[BinaryOp::Gt, BinaryOp::Ge]);
+    let upper = find(&[BinaryOp::Lt, BinaryOp::Le]);
+    if lower.is_some() || upper.is_some() {
+      let bounds = [lower, upper].into_iter().flatten();
+      // each bound is taken to keep a quarter of the rows
+      let rows = n * 0.25f64.powi(bounds.clone().count() as i32);
+      candidates.push(Access {
+        seek: Seek::Range {
+          lower: lower.map(|(_, key)| (key.op, key.expr)),
+          upper: upper.map(|(_, key)| (key.op, key.expr)),
+        },
+        used: bounds.map(|(i, _)| *i).collect(),
+        cost: lookup + rows,
+        rows,
+      });
+    }
+    candidates.push(Access {
+      seek: Seek::Scan,
+      used: vec![],
+      cost: n,
+      rows: n,
+    });
+    // on a tie, the earlier candidate, which seeks more narrowly, wins
+    candidates
+      .into_iter()
+      .reduce(|best, next| if next.cost < best.cost { next } else { best })
+      .unwrap()
+  }
+
+  /// The position in `order` of the loop that checks `term`, or `None` for the body.
+  fn position(&self, term: &Term, order: &[usize]) -> Option<usize> {
+    let pos = |t: usize| order.iter().position(|&o| o == t);
+    match term.home {
+      Home::Body => None,
+      Home::Level(t) => pos(t),
+      Home::Any if order.is_empty() => None,
+      Home::Any => Some(
+        term
+          .tables
+          .iter()
+          .filter_map(|&t| pos(t))
+          .max()
+          .unwrap_or(0),
+      ),
+    }
+  }
+
+  /// The share of rows expected to pass `term`: from ANALYZE for equality on a column of
+  /// users, else the guesses SQLite makes without statistics.
+  fn selectivity(&self, term: &Term) -> f64 {
+    match term.expr {
+      Expr::Binary(BinaryOp::Eq, l, r) => [l, r]
+        .into_iter()
+        .filter_map(|e| self.column(e))
+        .map(|idx| {
+          let (t, column) = self.locate(idx);
+          match (self.levels[t].users, column, self.stats) {
+            (true, 0, _) => 1.0 / self.rows,
+            (true, column, Some(stats)) => stats.rows_per_value(column) as f64 / self.rows,
+            _ => 0.1,
+          }
+        })
+        .fold(1.0, f64::min),
+      Expr::Binary(BinaryOp::Lt | BinaryOp::Le | BinaryOp::Gt | BinaryOp::Ge, ..) => 0.25,
+      _ => 1.0,
+    }
+  }
+}
+
+/// Every order of `n` tables that starts with `prefix`, in lexicographic order.
+fn permutations(prefix: &mut Vec<usize>, n: usize, orders: &mut Vec<Vec<usize>>) {
+  if prefix.len() == n {
+    orders.push(prefix.clone());
+    return;
+  }
+  for t in 0..n {
+    if !prefix.contains(&t) {
+      prefix.push(t);
+      permutations(prefix, n, orders);
+      prefix.pop();
+    }
+  }
+}
+
+/// The operator that keeps `a op b` true when the sides are swapped.
+fn flip(op: BinaryOp) -> BinaryOp {
+  match op {
+    BinaryOp::Lt => BinaryOp::Gt,
+    BinaryOp::Le => BinaryOp::Ge,
+    BinaryOp::Gt => BinaryOp::Lt,
+    BinaryOp::Ge => BinaryOp::Le,
+    op => op,
+  }
+}
+
+fn comparison(op: BinaryOp) -> &'static str {
+  match op {
+    BinaryOp::Lt => "<",
+    BinaryOp::Le => "<=",
+    BinaryOp::Gt => ">",
+    BinaryOp::Ge => ">=",
+    _ => "=",
+  }
+}
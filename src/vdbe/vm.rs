@@ -0,0 +1,559 @@
+use super::{Explain, Op, Program, Rows};
+use crate::cursor::Cursor;
+use crate::error::ExecErr;
+use crate::exec::{self, binary, bool_value, Recursion, Scope, Subqueries};
+use crate::row::Row;
+use crate::table::Table;
+use crate::value::{GroupKey, Value};
+use std::cmp::Ordering;
+use std::collections::{HashSet, VecDeque};
+use std::rc::Rc;
+
+/// The execution state of a program: program counter, registers, open cursors and the values
+/// bound to its parameters. Each step runs until the next result row, so a compiled query
+/// computes no more rows than the caller takes, a recursive CTE included. A query that runs
+/// as an `Op::Query` (one that groups, sorts, computes window functions, or combines selects
+/// other than by UNION and UNION ALL) computes all of its rows at the first step instead, as
+/// it cannot yield any before it has seen them all. It can be reset to run the program again.
+pub struct Vm {
+  program: Rc<Program>,
+  pc: usize,
+  registers: Vec<Value>,
+  params: Rc<Vec<Value>>, // by parameter number, from 1
+  bound: Vec<bool>,       // whether each parameter was given a value
+  changes: usize,         // rows inserted since the start
+  cursors: Vec<Option<VmCursor>>,
+  pending: VecDeque<Vec<Value>>, // rows of an `Op::Query` not handed out yet
+  subqueries: Rc<Subqueries>,    // of this run of a query
+}
+
+struct VmCursor {
+  position: Position,
+  row: Option<Vec<Value>>, // the row under the cursor, once read
+  null_row: bool,
+}
+
+enum Position {
+  Table(Cursor),
+  Rows {
+    rows: Vec<Vec<Value>>,
+    at: usize,
+  },
+  Recursive {
+    name: String,
+    columns: Vec<String>,
+    recursion: Recursion,
+  },
+  Set(HashSet<Vec<GroupKey>>),
+}
+
+/// What the instructions run against: the table, which an INSERT or ANALYZE writes, or the
+/// scope a query runs in.
+enum Db<'a> {
+  Write(&'a mut Table),
+  Read(Scope<'a>),
+}
+
+impl Db<'_> {
+  fn table(&self) -> &Table {
+    match self {
+      Self::Write(table) => table,
+      Self::Read(scope) => scope.ctx.table,
+    }
+  }
+
+  fn scope(&self) -> Scope<'_> {
+    match self {
+      Self::Read(scope) => *scope,
+      Self::Write(_) => unreachable!("only a query reads rows other than the table's"),
+    }
+  }
+}
+
+impl Vm {
+  pub fn new(program: Program) -> Self {
+    Self {
+      pc: 0,
+      registers: vec![Value::Null; program.registers],
+      params: Rc::new(vec![Value::Null; program.variables.count()]),
+      bound: vec![false; program.variables.count()],
+      changes: 0,
+      cursors: (0..program.cursors).map(|_| None).collect(),
+      pending: VecDeque::new(),
+      subqueries: Rc::default(),
+      program: Rc::new(program),
+    }
+  }
+
+  pub fn program(&self) -> &Program {
+    &self.program
+  }
+
+  /// Number of rows the program inserted so far.
+  pub fn changes(&self) -> usize {
+    self.changes
+  }
+
+  /// Whether the program started and was not reset since.
+  pub fn is_running(&self) -> bool {
+    self.pc > 0
+  }
+
+  /// Bind `value` to parameter `number`. Returns false if there is no such parameter.
+  pub fn bind(&mut self, number: usize, value: Value) -> bool {
+    let params = Rc::make_mut(&mut self.params);
+    match number.checked_sub(1).and_then(|i| params.get_mut(i)) {
+      Some(param) => {
+        *param = value;
+        self.bound[number - 1] = true;
+        true
+      }
+      None => false,
+    }
+  }
+
+  /// Unbind every parameter.
+  pub fn clear_bindings(&mut self) {
+    Rc::make_mut(&mut self.params).fill(Value::Null);
+    self.bound.fill(false);
+  }
+
+  /// Rewind to the first instruction, as if the program had not run. Bindings are kept.
+  pub fn reset(&mut self) {
+    self.pc = 0;
+    self.changes = 0;
+    self.registers.fill(Value::Null);
+    self.cursors.iter_mut().for_each(|cursor| *cursor = None);
+    self.pending.clear();
+    self.subqueries.borrow_mut().clear();
+  }
+
+  /// Run until the program yields its next result row, or `None` once it halted. The program
+  /// of an EXPLAIN statement yields its listing instead. It does not start while a parameter
+  /// is unbound.
+  pub fn step(&mut self, table: &mut Table) -> Result<Option<Vec<Value>>, ExecErr> {
+    match self.program.explain {
+      Some(Explain::Program) => return Ok(self.list_op()),
+      Some(Explain::QueryPlan) => return Ok(self.list_plan_line()),
+      None => {}
+    }
+    if let (0, Some(n)) = (self.pc, self.bound.iter().position(|bound| !bound)) {
+      let name = match self.program.variables.name(n + 1) {
+        Some(name) => name.to_string(),
+        None => format!("?{}", n + 1),
+      };
+      return Err(ExecErr::Misuse(format!("Parameter {name} is not bound.")));
+    }
+    match self.program.select.clone() {
+      Some(select) => {
+        let (params, subqueries) = (Rc::clone(&self.params), Rc::clone(&self.subqueries));
+        exec::in_scope(&select, table, &params, &subqueries, |scope| {
+          self.run(Db::Read(scope))
+        })
+      }
+      None => self.run(Db::Write(table)),
+    }
+  }
+
+  /// Run a program the executor compiled until it yields its next row, in `scope`.
+  pub(crate) fn step_in(&mut self, scope: Scope) -> Result<Option<Vec<Value>>, ExecErr> {
+    self.run(Db::Read(scope))
+  }
+
+  fn run(&mut self, mut db: Db) -> Result<Option<Vec<Value>>, ExecErr> {
+    if let Some(row) = self.pending.pop_front() {
+      return Ok(Some(row));
+    }
+    let program = Rc::clone(&self.program);
+    loop {
+      let op = &program.ops[self.pc];
+      self.pc += 1;
+      let r = &mut self.registers;
+      match op {
+        Op::Integer { value, dest } => r[*dest] = Value::Integer(*value),
+        Op::Real { value, dest } => r[*dest] = Value::Real(*value),
+        Op::String8 { value, dest } => r[*dest] = Value::Text(value.clone()),
+        Op::Null { dest } => r[*dest] = Value::Null,
+        Op::Variable { number, dest } => {
+          r[*dest] = match &db {
+            Db::Read(scope) => scope.ctx.params[number - 1].clone(),
+            Db::Write(_) => self.params[number - 1].clone(),
+          }
+        }
+        Op::Goto { target } => self.pc = *target,
+        Op::If {
+          reg,
+          target,
+          jump_if_null,
+        } => {
+          if r[*reg].truthy().unwrap_or(*jump_if_null) {
+            self.pc = *target;
+          }
+        }
+        Op::IfNot {
+          reg,
+          target,
+          jump_if_null,
+        } => {
+          if !r[*reg].truthy().unwrap_or(!*jump_if_null) {
+            self.pc = *target;
+          }
+        }
+        Op::IfPos { reg, target } => {
+          if let Value::Integer(n @ 1..) = r[*reg] {
+            r[*reg] = Value::Integer(n - 1);
+            self.pc = *target;
+          }
+        }
+        Op::DecrJumpZero { reg, target } => {
+          if let Value::Integer(n) = r[*reg] {
+            r[*reg] = Value::Integer(n - 1);
+            if n == 1 {
+              self.pc = *target;
+            }
+          }
+        }
+        Op::OpenRead { cursor } | Op::OpenWrite { cursor } => {
+          self.open(*cursor, Position::Table(Cursor::new()));
+        }
+        Op::OpenRows { cursor, rows } => {
+          let position = match rows {
+            Rows::Recursive { name, columns } => Position::Recursive {
+              name: name.clone(),
+              columns: columns.clone(),
+              recursion: Recursion::default(),
+            },
+            rows => Position::Rows {
+              rows: exec::materialize(rows, db.scope())?,
+              at: 0,
+            },
+          };
+          self.open(*cursor, position);
+        }
+        Op::OpenEphemeral { cursor } => self.open(*cursor, Position::Set(HashSet::new())),
+        Op::Distinct {
+          cursor,
+          start,
+          len,
+          target,
+        } => {
+          let row = r[*start..start + len].iter().map(GroupKey::from).collect();
+          let Some(VmCursor {
+            position: Position::Set(set),
+            ..
+          }) = &mut self.cursors[*cursor]
+          else {
+            unreachable!("Distinct through a cursor that is no set");
+          };
+          if !set.insert(row) {
+            self.pc = *target;
+          }
+        }
+        Op::Rewind { cursor, target } => {
+          let VmCursor {
+            position,
+            row,
+            null_row,
+          } = self.cursors[*cursor].as_mut().expect("cursor is open");
+          *row = None;
+          *null_row = false;
+          let valid = match position {
+            Position::Table(position) => {
+              position.first(db.table())?;
+              position.is_valid()
+            }
+            Position::Rows { rows, at } => {
+              *at = 0;
+              !rows.is_empty()
+            }
+            Position::Recursive {
+              name,
+              columns,
+              recursion,
+            } => {
+              *row = recursion.next(db.scope(), name, columns)?;
+              row.is_some()
+            }
+            Position::Set(_) => unreachable!("a set is not read by a loop"),
+          };
+          if !valid {
+            self.pc = *target;
+          }
+        }
+        Op::Next { cursor, target } => {
+          let VmCursor {
+            position,
+            row,
+            null_row,
+          } = self.cursors[*cursor].as_mut().expect("cursor is open");
+          if !*null_row {
+            let valid = match position {
+              Position::Table(position) if position.is_valid() => {
+                position.next(db.table())?;
+                *row = None;
+                position.is_valid()
+              }
+              Position::Table(_) => false,
+              Position::Rows { rows, at } => {
+                *at += 1;
+                *at < rows.len()
+              }
+              Position::Recursive {
+                name,
+                columns,
+                recursion,
+              } => {
+                *row = recursion.next(db.scope(), name, columns)?;
+                row.is_some()
+              }
+              Position::Set(_) => unreachable!("a set is not read by a loop"),
+            };
+            if valid {
+              self.pc = *target;
+            }
+          }
+        }
+        Op::Last { cursor, target } => {
+          let cur = self.cursor(*cursor);
+          let position = cur.table();
+          position.last(db.table())?;
+          let valid = position.is_valid();
+          cur.row = None;
+          cur.null_row = false;
+          if !valid {
+            self.pc = *target;
+          }
+        }
+        Op::Prev { cursor, target } => {
+          let cur = self.cursor(*cursor);
+          if !cur.null_row && cur.table().is_valid() {
+            cur.table().prev(db.table())?;
+            cur.row = None;
+            if cur.table().is_valid() {
+              self.pc = *target;
+            }
+          }
+        }
+        Op::SeekRowid {
+          cursor,
+          key,
+          target,
+        } => {
+          let found = match r[*key].to_key() {
+            Some(key) => db.table().find_row(key)?,
+            None => None,
+          };
+          let cur = self.cursor(*cursor);
+          cur.null_row = false;
+          // the row is kept as read, the cursor itself is not positioned on it
+          cur.position = Position::Table(Cursor::new());
+          cur.row = found.as_ref().map(Row::to_values);
+          if found.is_none() {
+            self.pc = *target;
+          }
+        }
+        Op::SeekGE {
+          cursor,
+          key,
+          target,
+        }
+        | Op::SeekGT {
+          cursor,
+          key,
+          target,
+        } => {
+          let strict = matches!(op, Op::SeekGT { .. });
+          let bound = r[*key].clone();
+          let cur = self.cursor(*cursor);
+          cur.row = None;
+          cur.null_row = false;
+          cur.table().seek_above(db.table(), &bound, strict)?;
+          if !cur.table().is_valid() {
+            self.pc = *target;
+          }
+        }
+        Op::SeekLE {
+          cursor,
+          key,
+          target,
+        }
+        | Op::SeekLT {
+          cursor,
+          key,
+          target,
+        } => {
+          let strict = matches!(op, Op::SeekLT { .. });
+          let bound = r[*key].clone();
+          let cur = self.cursor(*cursor);
+          cur.row = None;
+          cur.null_row = false;
+          cur.table().seek_below(db.table(), &bound, strict)?;
+          if !cur.table().is_valid() {
+            self.pc = *target;
+          }
+        }
+        Op::NullRow { cursor } => {
+          let cur = self.cursor(*cursor);
+          cur.null_row = true;
+          cur.row = None;
+        }
+        Op::Column {
+          cursor,
+          column,
+          dest,
+        } => self.registers[*dest] = self.column(db.table(), *cursor, *column)?,
+        Op::Rowid { cursor, dest } => {
+          self.registers[*dest] = self.column(db.table(), *cursor, 0)?
+        }
+        Op::Binary { op, lhs, rhs, dest } => r[*dest] = binary(*op, &r[*lhs], &r[*rhs])?,
+        Op::Not { src, dest } => r[*dest] = bool_value(r[*src].truthy().map(|b| !b)),
+        Op::Negative { src, dest } => r[*dest] = r[*src].neg(),
+        Op::IsNull { src, dest } => r[*dest] = bool_value(Some(r[*src].is_null())),
+        Op::NotNull { src, dest } => r[*dest] = bool_value(Some(!r[*src].is_null())),
+        Op::Eval {
+          expr,
+          columns,
+          start,
+          dest,
+        } => {
+          let row = &self.registers[*start..start + columns.len()];
+          self.registers[*dest] = exec::eval_row(expr, db.scope(), columns, row)?;
+        }
+        Op::InList {
+          lhs,
+          start,
+          len,
+          dest,
+        } => {
+          let mut res = Some(false);
+          for item in &r[*start..start + len] {
+            match r[*lhs].compare(item) {
+              Some(Ordering::Equal) => {
+                res = Some(true);
+                break;
+              }
+              None => res = None,
+              Some(_) => {}
+            }
+          }
+          r[*dest] = bool_value(res);
+        }
+        Op::ResultRow { start, len } => return Ok(Some(r[*start..start + len].to_vec())),
+        Op::Insert { cursor, key, data } => {
+          debug_assert!(
+            self.cursors[*cursor].is_some(),
+            "INSERT through an unopened cursor"
+          );
+          // literal keys are checked when parsed, bound ones only here
+          let key = r[*key].to_key().ok_or_else(|| match r[*key].to_numeric() {
+            Value::Integer(n) if n < 0 => ExecErr::NegativeId("ID must be positive.".to_string()),
+            _ => ExecErr::DatatypeMismatch("Datatype mismatch.".to_string()),
+          })?;
+          // text too long is the only reason a row fails to build
+          let row = Row::build(key, &r[*data].to_text(), &r[data + 1].to_text())
+            .map_err(|e| ExecErr::StringTooLong(e.to_string()))?;
+          let Db::Write(table) = &mut db else {
+            unreachable!("a query does not insert");
+          };
+          self.cursor(*cursor).table().insert(table, &row)?;
+          self.changes += 1;
+        }
+        Op::Analyze { cursor } => {
+          debug_assert!(
+            self.cursors[*cursor].is_some(),
+            "ANALYZE through an unopened cursor"
+          );
+          let Db::Write(table) = &mut db else {
+            unreachable!("a query does not analyze");
+          };
+          table.analyze()?;
+        }
+        Op::IntegrityCk { cursor } => {
+          debug_assert!(
+            self.cursors[*cursor].is_some(),
+            "integrity check through an unopened cursor"
+          );
+          let mut problems = db.table().integrity_check();
+          if problems.is_empty() {
+            problems.push("ok".to_string());
+          }
+          let rows = problems.into_iter().map(|line| vec![Value::Text(line)]);
+          self.pending.extend(rows);
+          return Ok(self.pending.pop_front());
+        }
+        Op::Query { select, .. } => {
+          let pending = &mut self.pending;
+          exec::execute_select(select, db.scope().ctx, |row| pending.push_back(row))?;
+          if let Some(row) = self.pending.pop_front() {
+            return Ok(Some(row));
+          }
+        }
+        Op::Halt => {
+          self.pc -= 1; // stay halted
+          return Ok(None);
+        }
+      }
+    }
+  }
+
+  fn list_op(&mut self) -> Option<Vec<Value>> {
+    let op = self.program.ops.get(self.pc)?;
+    self.pc += 1;
+    Some(op.explain(self.pc - 1, &self.program.tables))
+  }
+
+  /// The next line of the plan, as ids counted from 1 and the id of the line it nests in, or
+  /// 0 at the top.
+  fn list_plan_line(&mut self) -> Option<Vec<Value>> {
+    let plan = &self.program.plan;
+    let (depth, detail) = plan.get(self.pc)?;
+    let parent = plan[..self.pc]
+      .iter()
+      .rposition(|(d, _)| d + 1 == *depth)
+      .map_or(0, |idx| idx + 1);
+    self.pc += 1;
+    Some(vec![
+      Value::Integer(self.pc as i64),
+      Value::Integer(parent as i64),
+      Value::Text(detail.clone()),
+    ])
+  }
+
+  fn open(&mut self, cursor: usize, position: Position) {
+    self.cursors[cursor] = Some(VmCursor {
+      position,
+      row: None,
+      null_row: false,
+    });
+  }
+
+  fn cursor(&mut self, cursor: usize) -> &mut VmCursor {
+    self.cursors[cursor].as_mut().expect("cursor is open")
+  }
+
+  /// Column `column` of the row under `cursor`, reading the row on first use.
+  fn column(&mut self, table: &Table, cursor: usize, column: usize) -> Result<Value, ExecErr> {
+    let cur = self.cursor(cursor);
+    if cur.null_row {
+      return Ok(Value::Null);
+    }
+    if let Position::Rows { rows, at } = &cur.position {
+      return Ok(rows[*at][column].clone());
+    }
+    if cur.row.is_none() {
+      cur.row = Some(cur.table().value(table)?.to_values());
+    }
+    Ok(cur.row.as_ref().unwrap()[column].clone())
+  }
+}
+
+impl VmCursor {
+  /// The position of a cursor over the table, which only such a cursor seeks or moves
+  /// backwards on.
+  fn table(&mut self) -> &mut Cursor {
+    match &mut self.position {
+      Position::Table(position) => position,
+      _ => unreachable!("only the users table is read by key"),
+    }
+  }
+}
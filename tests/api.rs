@@ -0,0 +1,1062 @@
+use serde::{Deserialize, Serialize};
+use serde_bytes::ByteBuf;
+use sqlite_rs::error::{
+  SQLITE_CANTOPEN, SQLITE_CONSTRAINT, SQLITE_CORRUPT, SQLITE_ERROR, SQLITE_MISMATCH, SQLITE_MISUSE,
+  SQLITE_NOTADB, SQLITE_RANGE, SQLITE_TOOBIG,
+};
+use sqlite_rs::{Connection, Options, Value};
+use std::collections::HashMap;
+use std::error::Error;
+
+#[test]
+fn prepared_statements_with_parameters() {
+  let filename = "prepared_statements_with_parameters.db";
+  let _ = std::fs::remove_file(filename);
+  let mut conn = Connection::open(filename).unwrap();
+
+  // one compiled insert, run once per row
+  let mut insert = conn.prepare("insert ? :name :name").unwrap();
+  assert_eq!(insert.bind_parameter_count(), 2);
+  assert_eq!(insert.bind_parameter_name(1), None);
+  assert_eq!(insert.bind_parameter_index(":name"), Some(2));
+  for (id, name) in [(1, "alice"), (2, "bob's"), (3, "carol")] {
+    insert.bind_int(1, id).unwrap();
+    insert.bind_text(2, name).unwrap();
+    assert_eq!(insert.step(&mut conn).unwrap(), None);
+    insert.reset();
+  }
+  insert.bind_int(1, -1).unwrap();
+  let err = insert.step(&mut conn).unwrap_err();
+  assert_eq!(
+    format!("{err:?}"),
+    "ExecErr(NegativeId(\"ID must be positive.\"))"
+  );
+
+  let mut select = conn
+    .prepare("select id, username from users where id >= ?1 and id < ?2")
+    .unwrap();
+  assert!(select.bind_int(3, 0).is_err());
+  select.bind_int(1, 2).unwrap();
+  select.bind_int(2, 10).unwrap();
+  let mut rows = vec![];
+  while let Some(row) = select.step(&mut conn).unwrap() {
+    rows.push(row);
+  }
+  assert_eq!(
+    rows,
+    [
+      vec![Value::Integer(2), Value::Text("bob's".to_string())],
+      vec![Value::Integer(3), Value::Text("carol".to_string())],
+    ]
+  );
+  // bindings wait for a reset, and survive it
+  assert!(select.bind_int(1, 3).is_err());
+  select.reset();
+  select.bind_int(1, 3).unwrap();
+  assert_eq!(
+    select.step(&mut conn).unwrap().unwrap()[0],
+    Value::Integer(3)
+  );
+  assert_eq!(select.step(&mut conn).unwrap(), None);
+
+  // a statement does not run with a parameter unbound
+  select.reset();
+  select.clear_bindings().unwrap();
+  select.bind_int(1, 3).unwrap();
+  let err = select.step(&mut conn).unwrap_err();
+  assert_eq!(err.code(), SQLITE_MISUSE);
+  assert_eq!(err.to_string(), "Parameter ?2 is not bound.");
+  let mut insert = conn.prepare("insert ? :name x@y").unwrap();
+  insert.bind_int(1, 4).unwrap();
+  let err = insert.step(&mut conn).unwrap_err();
+  assert_eq!(err.to_string(), "Parameter :name is not bound.");
+  // only `prepare` takes parameters; `execute` stores the text as written
+  assert_eq!(conn.execute("insert 4 :name ?").unwrap(), 1);
+  assert_eq!(
+    conn
+      .query("select username, email from users where id = 4")
+      .unwrap()
+      .next()
+      .unwrap()
+      .unwrap()
+      .values(),
+    &[
+      Value::Text(":name".to_string()),
+      Value::Text("?".to_string())
+    ]
+  );
+
+  // parameters reach queries run by the tree-walking executor too
+  let mut count = conn
+    .prepare("select count(*) from users where username <> :skip")
+    .unwrap();
+  count.bind_text(1, "alice").unwrap();
+  assert_eq!(
+    count.step(&mut conn).unwrap(),
+    Some(vec![Value::Integer(3)])
+  );
+
+  let _ = std::fs::remove_file(filename);
+}
+
+#[test]
+fn connection_execute_and_query() {
+  let filename = "connection_execute_and_query.db";
+  let _ = std::fs::remove_file(filename);
+  let mut conn = Connection::open(filename).unwrap();
+
+  assert_eq!(conn.execute("insert 1 alice a@x.com").unwrap(), 1);
+  assert_eq!(conn.execute("insert 2 bob b@x.com").unwrap(), 1);
+  assert_eq!(conn.execute("select * from users").unwrap(), 0);
+  assert!(conn.execute("insert 1 alice a@x.com").is_err());
+
+  let rows = conn
+    .query("select id, username as name, email || '!' from users")
+    .unwrap();
+  assert_eq!(rows.column_names(), ["id", "name", "column3"]);
+  let rows: Vec<_> = rows.collect::<Result<_, _>>().unwrap();
+  assert_eq!(rows.len(), 2);
+  assert_eq!(rows[1].get(0), Some(&Value::Integer(2)));
+  assert_eq!(
+    rows[1].get_by_name("NAME"),
+    Some(&Value::Text("bob".to_string()))
+  );
+  assert_eq!(
+    rows[0].values(),
+    [
+      Value::Integer(1),
+      Value::Text("alice".to_string()),
+      Value::Text("a@x.com!".to_string()),
+    ]
+  );
+
+  // an error ends the rows
+  let mut rows = conn
+    .query("select (select id, username from users b where b.id = a.id) from users a")
+    .unwrap();
+  assert!(matches!(rows.next(), Some(Err(_))));
+  assert!(rows.next().is_none());
+  assert!(conn.query("select nosuch from users").is_err());
+
+  conn.close().unwrap();
+  let mut conn = Connection::open(filename).unwrap();
+  let count = conn.query("select count(*) from users").unwrap().next();
+  assert_eq!(count.unwrap().unwrap().into_values(), [Value::Integer(2)]);
+
+  let _ = std::fs::remove_file(filename);
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct User {
+  id: u32,
+  username: String,
+  email: Option<String>,
+}
+
+#[test]
+fn rows_to_and_from_structs() {
+  let filename = "rows_to_and_from_structs.db";
+  let _ = std::fs::remove_file(filename);
+  let mut conn = Connection::open(filename).unwrap();
+
+  let alice = User {
+    id: 1,
+    username: "alice".to_string(),
+    email: Some("a@x.com".to_string()),
+  };
+  assert_eq!(conn.insert(&alice).unwrap(), 1);
+  // maps work too, and column names ignore case; text converts to an id as in SQL
+  let bob = HashMap::from([("id", "2"), ("USERNAME", "bob")]);
+  assert_eq!(conn.insert(&bob).unwrap(), 1);
+  assert!(conn.insert(&alice).is_err());
+  let err = conn.insert(&HashMap::from([("id", 3), ("age", 40)]));
+  assert_eq!(
+    format!("{:?}", err.unwrap_err()),
+    "ExecErr(NoSuchColumn(\"No such column: age.\"))"
+  );
+  let err = conn.insert(&HashMap::from([("username", "carol")]));
+  assert_eq!(
+    format!("{:?}", err.unwrap_err()),
+    "ExecErr(MappingError(\"Missing field: id.\"))"
+  );
+  assert!(conn.insert(&(4, "dave")).is_err());
+
+  let users: Vec<User> = conn.query_as("select * from users").unwrap();
+  assert_eq!(
+    users,
+    [
+      alice,
+      User {
+        id: 2,
+        username: "bob".to_string(),
+        email: Some(String::new()),
+      }
+    ]
+  );
+
+  // columns are matched by name, so aliases pick the field
+  #[derive(Debug, PartialEq, Deserialize)]
+  struct Name {
+    name: ByteBuf,
+    upper: bool,
+  }
+  let names: Vec<Name> = conn
+    .query_as("select id > 1 as upper, username as name from users")
+    .unwrap();
+  assert_eq!(names[1].name.as_ref(), b"bob");
+  assert!(!names[0].upper && names[1].upper);
+
+  // tuples take the columns in order, and a single column reads as a plain value
+  let pairs: Vec<(i64, String)> = conn.query_as("select id, username from users").unwrap();
+  assert_eq!(pairs, [(1, "alice".to_string()), (2, "bob".to_string())]);
+  let ids: Vec<u8> = conn.query_as("select id from users").unwrap();
+  assert_eq!(ids, [1, 2]);
+  let count: Vec<f64> = conn.query_as("select count(*) from users").unwrap();
+  assert_eq!(count, [2.0]);
+
+  let err = conn.query_as::<u8>("select id, email from users");
+  assert_eq!(
+    format!("{:?}", err.unwrap_err()),
+    "ExecErr(MappingError(\"Cannot read a row of 2 columns as a single value.\"))"
+  );
+  let err = conn.query_as::<User>("select id, email from users");
+  assert_eq!(
+    format!("{:?}", err.unwrap_err()),
+    "ExecErr(MappingError(\"missing field `username`\"))"
+  );
+
+  let _ = std::fs::remove_file(filename);
+}
+
+#[test]
+fn error_codes_and_sources() {
+  // a directory cannot be opened as a database; the OS error is kept as the source
+  let err = Connection::open("tests").err().unwrap();
+  assert_eq!(err.code(), SQLITE_CANTOPEN);
+  assert_eq!(err.to_string(), "Unable to open file.");
+  let io = err.source().unwrap().downcast_ref::<std::io::Error>();
+  assert!(io.is_some());
+
+  let filename = "error_codes_and_sources.db";
+  let _ = std::fs::remove_file(filename);
+  let mut conn = Connection::open(filename).unwrap();
+  conn.execute("insert 1 alice a@x.com").unwrap();
+
+  let err = conn.execute("insert 1 bob b@x.com").unwrap_err();
+  assert_eq!(
+    (err.code(), err.to_string()),
+    (SQLITE_CONSTRAINT, "Duplicated key".to_string())
+  );
+  assert!(err.source().is_none());
+  let err = conn.execute("insert -1 bob b@x.com").unwrap_err();
+  assert_eq!(err.code(), SQLITE_CONSTRAINT);
+  let err = conn
+    .execute(&format!("insert 2 {} b@x.com", "b".repeat(33)))
+    .unwrap_err();
+  assert_eq!(err.code(), SQLITE_TOOBIG);
+  // bound values fail with the same codes as literal ones
+  let mut insert = conn.prepare("insert ? ? b@x.com").unwrap();
+  let bound = [
+    (
+      Value::Integer(-1),
+      "bob",
+      SQLITE_CONSTRAINT,
+      "ID must be positive.",
+    ),
+    (
+      Value::Integer(2),
+      &"b".repeat(33),
+      SQLITE_TOOBIG,
+      "String too long",
+    ),
+    (
+      Value::Real(2.5),
+      "bob",
+      SQLITE_MISMATCH,
+      "Datatype mismatch.",
+    ),
+  ];
+  for (id, username, code, msg) in bound {
+    insert.reset();
+    insert.bind_value(1, id).unwrap();
+    insert.bind_text(2, username).unwrap();
+    let err = insert.step(&mut conn).unwrap_err();
+    assert_eq!((err.code(), err.to_string()), (code, msg.to_string()));
+  }
+  #[derive(Serialize)]
+  struct User {
+    id: i64,
+  }
+  let err = conn.insert(&User { id: -1 }).unwrap_err();
+  assert_eq!(err.code(), SQLITE_CONSTRAINT);
+  assert_eq!(
+    conn.execute("select nosuch from users").unwrap_err().code(),
+    SQLITE_ERROR
+  );
+  assert_eq!(conn.execute("selec").unwrap_err().code(), SQLITE_ERROR);
+
+  let mut stmt = conn.prepare("select * from users where id = ?").unwrap();
+  assert_eq!(stmt.bind_int(2, 1).unwrap_err().code(), SQLITE_RANGE);
+  stmt.bind_int(1, 1).unwrap();
+  stmt.step(&mut conn).unwrap();
+  let err = stmt.bind_int(1, 2).unwrap_err();
+  assert_eq!(err.code(), SQLITE_MISUSE);
+  assert_eq!(err.to_string(), "Statement must be reset before binding.");
+
+  // a statement runs only against the connection that prepared it
+  let other = "error_codes_and_sources_other.db";
+  let _ = std::fs::remove_file(other);
+  let mut other_conn = Connection::open(other).unwrap();
+  stmt.reset();
+  let err = stmt.step(&mut other_conn).unwrap_err();
+  assert_eq!(err.code(), SQLITE_MISUSE);
+  assert_eq!(
+    err.to_string(),
+    "Statement was prepared on another connection."
+  );
+  assert!(stmt.step(&mut conn).unwrap().is_some());
+  let _ = std::fs::remove_file(other);
+
+  let _ = std::fs::remove_file(filename);
+}
+
+#[test]
+fn corrupt_pages_are_errors() {
+  let filename = "corrupt_pages_are_errors.db";
+  let _ = std::fs::remove_file(filename);
+  let mut conn = Connection::open(filename).unwrap();
+  for id in 1..=20 {
+    conn
+      .execute(&format!("insert {id} user{id} person{id}@x.com"))
+      .unwrap();
+  }
+  conn.close().unwrap();
+  let good = std::fs::read(filename).unwrap();
+
+  // (offset from page 0, bytes) to overwrite, and the error to expect; the file header takes
+  // the page before it
+  const PAGE: usize = 4096;
+  let cases: [(usize, &[u8], &str); 7] = [
+    (0, &[7], "page 0: unknown node type 7"),
+    (
+      6,
+      &[0, 0, 0, 0],
+      "page 0: 0 keys, outside the 1 to 509 an intern node holds",
+    ),
+    (
+      10,
+      &[0, 0, 0, 90],
+      "page 0: it points to page 90, past the end of the file",
+    ),
+    (10, &[0, 0, 0, 0], "page 0: child 1 points back to page 0"),
+    (
+      PAGE + 1,
+      &[2],
+      "page 1: byte 2 at offset 1 is not a boolean",
+    ),
+    (
+      PAGE + 10,
+      &[0, 0, 1, 0],
+      "page 1: 256 cells, more than the 13 a leaf holds",
+    ),
+    (PAGE + 22, &[0xff], "page 1: cell 0: username is not UTF-8"),
+  ];
+  for (offset, bytes, reason) in cases {
+    let mut bad = good.clone();
+    bad[PAGE + offset..PAGE + offset + bytes.len()].copy_from_slice(bytes);
+    std::fs::write(filename, &bad).unwrap();
+    let mut conn = Connection::open(filename).unwrap();
+    let err = conn.query_as::<(i64,)>("select id from users").unwrap_err();
+    assert_eq!(err.code(), SQLITE_CORRUPT);
+    assert_eq!(
+      err.to_string(),
+      format!("Database file is corrupt: {reason}.")
+    );
+    // only pages read intact are cached, so closing leaves the damage as it was
+    conn.close().unwrap();
+  }
+
+  // a leaf whose next is itself: counting goes down the intern nodes, not along the chain
+  let mut bad = good.clone();
+  bad[2 * PAGE + 6..2 * PAGE + 10].copy_from_slice(&[0, 0, 0, 1]);
+  std::fs::write(filename, &bad).unwrap();
+  let mut conn = Connection::open(filename).unwrap();
+  let count = conn.query_as::<(i64,)>("select count(*) from users");
+  assert_eq!(count.unwrap(), [(20,)]);
+  conn.close().unwrap();
+
+  std::fs::write(filename, &good[..good.len() - 1]).unwrap();
+  let err = Connection::open(filename).err().unwrap();
+  assert_eq!(
+    err.to_string(),
+    "Database file is corrupt: page 2: the file ends partway through the page."
+  );
+
+  let _ = std::fs::remove_file(filename);
+}
+
+#[test]
+fn page_checksums() {
+  let filename = "page_checksums.db";
+  let _ = std::fs::remove_file(filename);
+  let options = Options {
+    checksums: true,
+    ..Options::default()
+  };
+  let mut conn = Connection::open_with(filename, options).unwrap();
+  assert!(conn.checksums());
+  for id in 1..=20 {
+    conn
+      .execute(&format!("insert {id} user{id} person{id}@x.com"))
+      .unwrap();
+  }
+  conn.close().unwrap();
+
+  // the setting is kept in the file, whatever the options of a later open
+  let mut conn = Connection::open(filename).unwrap();
+  assert!(conn.checksums());
+  let ids: Vec<u32> = conn.query_as("select id from users").unwrap();
+  assert_eq!(ids, (1..=20).collect::<Vec<_>>());
+  conn.close().unwrap();
+
+  // a flipped bit in a row is caught, though the page still parses
+  let good = std::fs::read(filename).unwrap();
+  let mut bad = good.clone();
+  bad[2 * 4096 + 22] ^= 0x01; // 'u' of "user14" in page 1, after the header, becomes 't'
+  std::fs::write(filename, &bad).unwrap();
+  let mut conn = Connection::open(filename).unwrap();
+  let err = conn.query_as::<(u32,)>("select id from users").unwrap_err();
+  assert_eq!(err.code(), SQLITE_CORRUPT);
+  assert_eq!(
+    err.to_string(),
+    "Database file is corrupt: page 1: checksum mismatch."
+  );
+  conn.close().unwrap();
+
+  // a page written without one, in a file that has them
+  bad = good.clone();
+  bad[3 * 4096 + 4091..4 * 4096].fill(0);
+  std::fs::write(filename, &bad).unwrap();
+  let mut conn = Connection::open(filename).unwrap();
+  let err = conn.query_as::<(u32,)>("select id from users").unwrap_err();
+  assert_eq!(
+    err.to_string(),
+    "Database file is corrupt: page 2: no checksum."
+  );
+  conn.close().unwrap();
+
+  // the header says whether pages carry checksums, so clearing the flag of page 0, or the
+  // header's own, turns nothing off
+  for (offset, reason) in [
+    (4096 + 4091, "page 0: no checksum"),
+    (21, "page 0: a checksum in a file without them"),
+  ] {
+    bad = good.clone();
+    bad[offset] = 0;
+    std::fs::write(filename, &bad).unwrap();
+    let mut conn = Connection::open(filename).unwrap();
+    let err = conn.query_as::<(u32,)>("select id from users").unwrap_err();
+    assert_eq!(
+      err.to_string(),
+      format!("Database file is corrupt: {reason}.")
+    );
+    conn.close().unwrap();
+  }
+  let _ = std::fs::remove_file(filename);
+
+  // without checksums the same damage goes unnoticed
+  let mut conn = Connection::open(filename).unwrap();
+  assert!(!conn.checksums());
+  for id in 1..=20 {
+    conn
+      .execute(&format!("insert {id} user{id} person{id}@x.com"))
+      .unwrap();
+  }
+  conn.close().unwrap();
+  let mut bad = std::fs::read(filename).unwrap();
+  bad[2 * 4096 + 22] ^= 0x01;
+  std::fs::write(filename, &bad).unwrap();
+  let mut conn = Connection::open(filename).unwrap();
+  let names: Vec<String> = conn
+    .query_as("select username from users where id = 14")
+    .unwrap();
+  assert_eq!(names, ["tser14"]);
+
+  let _ = std::fs::remove_file(filename);
+}
+
+#[test]
+fn integrity_check_finds_damage() {
+  let filename = "integrity_check_finds_damage.db";
+  let _ = std::fs::remove_file(filename);
+  let mut conn = Connection::open(filename).unwrap();
+  for id in 1..=20 {
+    conn
+      .execute(&format!("insert {id} user{id} person{id}@x.com"))
+      .unwrap();
+  }
+  let ok: Vec<String> = conn.query_as("pragma integrity_check").unwrap();
+  assert_eq!(ok, ["ok"]);
+  conn.close().unwrap();
+  let good = std::fs::read(filename).unwrap();
+
+  // the root (page 0) holds leaf 2 (keys 1 to 13) under separator 13, and leaf 1 (keys 14
+  // to 20) as its right child
+  const PAGE: usize = 4096;
+  let be = |n: u32| n.to_be_bytes().to_vec();
+  type Writes = Vec<(usize, Vec<u8>)>; // offsets from page 0, and the bytes to put there
+  let cases: Vec<(Writes, Vec<&str>)> = vec![
+    (
+      vec![(18, be(6))],
+      vec!["Page 2: key 7 of cell 6 is above the separator 6"],
+    ),
+    (
+      vec![(PAGE + 2, be(2))],
+      vec!["Page 1: parent is page 2, expected page 0"],
+    ),
+    (
+      vec![(2 * PAGE + 6, be(u32::MAX))],
+      vec!["Page 2: next leaf is none, expected page 1"],
+    ),
+    (
+      vec![(PAGE + 14, be(5)), (PAGE + 18, be(5))],
+      vec!["Page 1: key 5 of cell 0 is out of order"],
+    ),
+    (
+      vec![(10, be(2))],
+      vec![
+        "Page 2: referenced more than once",
+        "Page 2: next leaf is page 1, expected none",
+        "Page 1: never used",
+      ],
+    ),
+    (
+      vec![(PAGE, vec![7])],
+      vec![
+        "Page 1: unknown node type 7",
+        "Page 2: next leaf is page 1, expected none",
+      ],
+    ),
+    (vec![(3 * PAGE, vec![0; PAGE])], vec!["Page 3: never used"]),
+  ];
+  for (writes, problems) in cases {
+    let mut bad = good.clone();
+    for (offset, bytes) in writes {
+      let offset = PAGE + offset; // past the file header
+      bad.resize(bad.len().max(offset + bytes.len()), 0);
+      bad[offset..offset + bytes.len()].copy_from_slice(&bytes);
+    }
+    std::fs::write(filename, &bad).unwrap();
+    let mut conn = Connection::open(filename).unwrap();
+    assert_eq!(conn.integrity_check(), problems);
+    let rows: Vec<String> = conn.query_as("pragma integrity_check").unwrap();
+    assert_eq!(rows, problems);
+  }
+
+  let _ = std::fs::remove_file(filename);
+}
+
+/// xorshift64*, so that the random orders below are the same on every run.
+struct Rng(u64);
+
+impl Rng {
+  fn next(&mut self) -> u64 {
+    self.0 ^= self.0 >> 12;
+    self.0 ^= self.0 << 25;
+    self.0 ^= self.0 >> 27;
+    self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+  }
+
+  fn shuffle<T>(&mut self, items: &mut [T]) {
+    for i in (1..items.len()).rev() {
+      items.swap(i, (self.next() % (i as u64 + 1)) as usize);
+    }
+  }
+}
+
+/// Insert `keys` in order through one prepared statement, checking the tree's invariants
+/// every `every` rows, then read them all back in key order.
+fn insert_and_check(filename: &str, keys: &[u32], every: usize) {
+  let _ = std::fs::remove_file(filename);
+  let mut conn = Connection::open(filename).unwrap();
+  let mut insert = conn.prepare("insert ? ? ?").unwrap();
+  for (i, &key) in keys.iter().enumerate() {
+    insert.bind_int(1, key.into()).unwrap();
+    insert.bind_text(2, &format!("user{key}")).unwrap();
+    insert.bind_text(3, &format!("person{key}@x.com")).unwrap();
+    insert.step(&mut conn).unwrap();
+    insert.reset();
+    if (i + 1) % every == 0 {
+      assert_eq!(
+        conn.integrity_check(),
+        Vec::<String>::new(),
+        "after {} rows",
+        i + 1
+      );
+    }
+  }
+  assert_eq!(conn.integrity_check(), Vec::<String>::new());
+
+  let mut sorted = keys.to_vec();
+  sorted.sort_unstable();
+  let ids: Vec<u32> = conn.query_as("select id from users").unwrap();
+  assert!(ids == sorted, "rows out of order or missing");
+  let name: Vec<String> = conn
+    .query_as(&format!(
+      "select username from users where id = {}",
+      sorted[sorted.len() / 2]
+    ))
+    .unwrap();
+  assert_eq!(name, [format!("user{}", sorted[sorted.len() / 2])]);
+  conn.close().unwrap();
+
+  // and the same tree comes back from the file
+  let conn = Connection::open(filename).unwrap();
+  assert_eq!(conn.integrity_check(), Vec::<String>::new());
+  drop(conn);
+  let _ = std::fs::remove_file(filename);
+}
+
+#[test]
+fn random_inserts_keep_the_tree_sound() {
+  let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+  let mut keys: Vec<u32> = (0..200_000).map(|i| i * 3).collect();
+  rng.shuffle(&mut keys);
+  insert_and_check("random_inserts_keep_the_tree_sound.db", &keys, 25_000);
+}
+
+#[test]
+fn sequential_inserts_keep_the_tree_sound() {
+  let keys: Vec<u32> = (0..100_000).collect();
+  insert_and_check("ascending_inserts_keep_the_tree_sound.db", &keys, 20_000);
+  let keys: Vec<u32> = (0..100_000).rev().collect();
+  insert_and_check("descending_inserts_keep_the_tree_sound.db", &keys, 20_000);
+}
+
+#[test]
+fn cursor_seeks_and_steps() {
+  let filename = "cursor_seeks_and_steps.db";
+  let _ = std::fs::remove_file(filename);
+  let mut conn = Connection::open(filename).unwrap();
+
+  let mut cursor = conn.cursor();
+  cursor.first().unwrap();
+  assert!(!cursor.is_valid());
+  cursor.last().unwrap();
+  assert_eq!(cursor.key(), None);
+  assert_eq!(cursor.value().unwrap(), None);
+
+  // enough rows for two levels of intern nodes, each insert leaving the cursor on its row
+  for id in (1..=20_000).rev().map(|i| i * 2) {
+    cursor
+      .insert(id, &format!("user{id}"), &format!("user{id}@x"))
+      .unwrap();
+    assert_eq!(cursor.key(), Some(id));
+  }
+  let err = cursor.insert(2, "again", "again@x").unwrap_err();
+  assert_eq!(err.code(), SQLITE_CONSTRAINT);
+
+  let mut seek = |ge: bool, id: u32| {
+    if ge {
+      cursor.seek_ge(id).unwrap();
+    } else {
+      cursor.seek_le(id).unwrap();
+    }
+    cursor.key()
+  };
+  assert_eq!(seek(true, 0), Some(2));
+  assert_eq!(seek(true, 5), Some(6));
+  assert_eq!(seek(true, 6), Some(6));
+  assert_eq!(seek(true, 40_000), Some(40_000));
+  assert_eq!(seek(true, 40_001), None);
+  assert_eq!(seek(false, 5), Some(4));
+  assert_eq!(seek(false, 6), Some(6));
+  assert_eq!(seek(false, 1), None);
+  assert_eq!(seek(false, u32::MAX), Some(40_000));
+
+  cursor.seek_ge(7).unwrap();
+  let row = cursor.value().unwrap().unwrap();
+  assert_eq!(row.get_by_name("id"), Some(&Value::Integer(8)));
+  assert_eq!(
+    row.get_by_name("email"),
+    Some(&Value::Text("user8@x".into()))
+  );
+
+  // stepping through every leaf, both ways
+  let mut keys = vec![];
+  cursor.first().unwrap();
+  while let Some(id) = cursor.key() {
+    keys.push(id);
+    cursor.next().unwrap();
+  }
+  assert!(keys.iter().copied().eq((1..=20_000).map(|i| i * 2)));
+  cursor.next().unwrap();
+  assert!(!cursor.is_valid());
+  keys.clear();
+  cursor.last().unwrap();
+  while let Some(id) = cursor.key() {
+    keys.push(id);
+    cursor.prev().unwrap();
+  }
+  assert!(keys.iter().copied().eq((1..=20_000).rev().map(|i| i * 2)));
+
+  // inserting behind each row while walking forward, splitting leaves under the cursor
+  keys.clear();
+  cursor.first().unwrap();
+  while let Some(id) = cursor.key() {
+    keys.push(id);
+    if id % 2 == 0 {
+      cursor.insert(id + 1, "odd", "odd@x").unwrap();
+      assert_eq!(cursor.key(), Some(id + 1));
+      cursor.prev().unwrap();
+      assert_eq!(cursor.key(), Some(id));
+      cursor.next().unwrap();
+    }
+    cursor.next().unwrap();
+  }
+  assert!(keys.iter().copied().eq((1..=20_000).map(|i| i * 2)));
+  drop(cursor);
+
+  let ids: Vec<u32> = conn.query_as("select id from users").unwrap();
+  assert!(ids.iter().copied().eq(2..=40_001));
+  assert_eq!(conn.integrity_check(), Vec::<String>::new());
+  drop(conn);
+  let _ = std::fs::remove_file(filename);
+}
+
+#[test]
+fn descending_scans_match_a_sort() {
+  let filename = "descending_scans_match_a_sort.db";
+  let _ = std::fs::remove_file(filename);
+  let mut conn = Connection::open(filename).unwrap();
+  let mut keys: Vec<u32> = (0..3000).map(|i| i * 2).collect();
+  Rng(0x2545_f491_4f6c_dd1d).shuffle(&mut keys);
+  let mut cursor = conn.cursor();
+  for &id in &keys {
+    cursor.insert(id, "user", "user@x").unwrap();
+  }
+  drop(cursor);
+
+  // reading backwards by key, checked against the sort an expression on the key needs
+  for cond in [
+    "1",
+    "id > 100",
+    "id >= 100 and id < 2001",
+    "id > 99.5 and id <= 4000.5",
+    "id >= 5998",
+    "id > 5998",
+    "id < 0",
+    "id <= 0",
+    "id < -3",
+    "id <= 1000000000000",
+    "id > 'a'",
+    "id < 'a' and id >= 5000",
+  ] {
+    let desc: Vec<i64> = conn
+      .query_as(&format!(
+        "select id from users where {cond} order by id desc"
+      ))
+      .unwrap();
+    let sorted: Vec<i64> = conn
+      .query_as(&format!(
+        "select id from users where {cond} order by id + 0 desc"
+      ))
+      .unwrap();
+    assert_eq!(desc, sorted, "where {cond}");
+    let mut asc: Vec<i64> = conn
+      .query_as(&format!("select id from users where {cond} order by id"))
+      .unwrap();
+    asc.reverse();
+    assert_eq!(desc, asc, "where {cond}");
+  }
+
+  let mut stmt = conn
+    .prepare("select id from users where id >= ? and id < ? order by id desc")
+    .unwrap();
+  stmt.bind_double(1, 9.5).unwrap();
+  stmt.bind_double(2, 16.0).unwrap();
+  let mut ids = vec![];
+  while let Some(row) = stmt.step(&mut conn).unwrap() {
+    ids.push(row[0].clone());
+  }
+  assert_eq!(ids, [14, 12, 10].map(Value::Integer));
+  drop(conn);
+  let _ = std::fs::remove_file(filename);
+}
+
+#[test]
+fn bulk_load_builds_a_compact_tree() {
+  let filename = "bulk_load_builds_a_compact_tree.db";
+  let pages = |filename| std::fs::metadata(filename).unwrap().len() / 4096 - 1; // less the header
+  let rows = |n: u32| (0..n).map(|id| (id, format!("user{id}"), format!("user{id}@x")));
+
+  // a leaf for every 13 rows, 16 intern nodes over them and the root
+  let _ = std::fs::remove_file(filename);
+  let mut conn = Connection::open(filename).unwrap();
+  assert_eq!(conn.bulk_load(rows(100_000), 1.0).unwrap(), 100_000);
+  assert_eq!(conn.integrity_check(), Vec::<String>::new());
+  conn.close().unwrap();
+  assert_eq!(pages(filename), 1 + 100_000u64.div_ceil(13) + 16);
+  let mut conn = Connection::open(filename).unwrap();
+  let ids: Vec<u32> = conn.query_as("select id from users").unwrap();
+  assert!(ids.iter().copied().eq(0..100_000));
+  let err = conn.bulk_load(rows(1), 1.0).unwrap_err();
+  assert_eq!(err.code(), SQLITE_MISUSE);
+
+  // inserting splits the full leaves as usual
+  let mut rng = Rng(7);
+  for _ in 0..2000 {
+    let id = 100_000 + (rng.next() % 100_000) as u32;
+    let _ = conn.execute(&format!("insert {id} late late@x"));
+  }
+  assert_eq!(conn.integrity_check(), Vec::<String>::new());
+  drop(conn);
+
+  // half full nodes take 7 rows or 255 children each
+  let _ = std::fs::remove_file(filename);
+  let mut conn = Connection::open(filename).unwrap();
+  conn.bulk_load(rows(10_000), 0.5).unwrap();
+  assert_eq!(conn.integrity_check(), Vec::<String>::new());
+  conn.close().unwrap();
+  assert_eq!(pages(filename), 1 + 10_000u64.div_ceil(7) + 6);
+
+  // a root leaf, then a root over two leaves
+  for (n, expected) in [(0, 1), (1, 1), (13, 1), (14, 3)] {
+    let _ = std::fs::remove_file(filename);
+    let mut conn = Connection::open(filename).unwrap();
+    conn.bulk_load(rows(n), 1.0).unwrap();
+    assert_eq!(conn.integrity_check(), Vec::<String>::new());
+    let count: Vec<u32> = conn.query_as("select count(*) from users").unwrap();
+    assert_eq!(count, [n]);
+    conn.close().unwrap();
+    assert_eq!(pages(filename), expected, "{n} rows");
+  }
+
+  // bad input leaves the table empty
+  let _ = std::fs::remove_file(filename);
+  let mut conn = Connection::open(filename).unwrap();
+  let row = |id, username: &str| (id, username.to_string(), "a".to_string());
+  for (tail, fill, code) in [
+    (row(50, "a"), 1.0, SQLITE_MISUSE),
+    (row(99, "a"), 1.0, SQLITE_CONSTRAINT),
+    (row(100, &"x".repeat(33)), 1.0, SQLITE_TOOBIG),
+    (row(100, "a"), 0.0, SQLITE_MISUSE),
+  ] {
+    let err = conn.bulk_load(rows(100).chain([tail]), fill).unwrap_err();
+    assert_eq!(err.code(), code);
+  }
+  assert_eq!(conn.integrity_check(), Vec::<String>::new());
+  conn.close().unwrap();
+  assert_eq!(pages(filename), 1);
+  let _ = std::fs::remove_file(filename);
+}
+
+#[test]
+fn ascending_inserts_fill_their_leaves() {
+  let filename = "ascending_inserts_fill_their_leaves.db";
+  let _ = std::fs::remove_file(filename);
+  let mut conn = Connection::open(filename).unwrap();
+  let mut insert = conn.prepare("insert ? ? ?").unwrap();
+  for id in 0..10_000 {
+    insert.reset();
+    insert.bind_int(1, id).unwrap();
+    insert.bind_text(2, "user").unwrap();
+    insert.bind_text(3, "user@x").unwrap();
+    insert.step(&mut conn).unwrap();
+  }
+
+  // every leaf but the last is full, and only a few intern nodes sit over them
+  let tree = conn.btree().unwrap();
+  let leaves: Vec<_> = tree
+    .lines()
+    .filter(|l| l.trim().starts_with("leaf"))
+    .collect();
+  assert_eq!(leaves.len(), 10_000usize.div_ceil(13));
+  assert!(leaves[..leaves.len() - 1]
+    .iter()
+    .all(|l| l.trim() == "leaf (size 13)"));
+  assert_eq!(conn.integrity_check(), Vec::<String>::new());
+  conn.close().unwrap();
+  let pages = std::fs::metadata(filename).unwrap().len() / 4096 - 1;
+  assert!(pages <= 770 + 4, "{pages} pages");
+  let _ = std::fs::remove_file(filename);
+}
+
+#[test]
+fn page_size_is_chosen_at_creation() {
+  let filename = "page_size_is_chosen_at_creation.db";
+  let with_size = |page_size| Options {
+    page_size: Some(page_size),
+    ..Options::default()
+  };
+  let mut keys: Vec<u32> = (0..300).collect();
+  Rng(0x6a09_e667_f3bc_c908).shuffle(&mut keys);
+
+  for (page_size, max_cells) in [(512, 1), (65536, 222)] {
+    let _ = std::fs::remove_file(filename);
+    let mut conn = Connection::open_with(filename, with_size(page_size)).unwrap();
+    assert_eq!(
+      (conn.page_size(), conn.leaf_max_cells()),
+      (page_size, max_cells)
+    );
+    for &id in &keys {
+      conn
+        .execute(&format!("insert {id} user{id} person{id}@x.com"))
+        .unwrap();
+    }
+    assert_eq!(conn.integrity_check(), Vec::<String>::new());
+    conn.close().unwrap();
+    let len = std::fs::metadata(filename).unwrap().len() as usize;
+    assert_eq!(len % page_size, 0);
+
+    // the size is kept in the header, whatever the options of a later open
+    let mut conn = Connection::open_with(filename, with_size(1024)).unwrap();
+    assert_eq!(conn.page_size(), page_size);
+    let ids: Vec<u32> = conn.query_as("select id from users").unwrap();
+    assert_eq!(ids, (0..300).collect::<Vec<_>>());
+    conn.close().unwrap();
+  }
+
+  // a format 1 file, from before the header: 4096-byte pages from its first byte, and in the
+  // root the largest key under every child, the right one included
+  let _ = std::fs::remove_file(filename);
+  let mut conn = Connection::open(filename).unwrap();
+  for id in 1..=20 {
+    conn
+      .execute(&format!("insert {id} user{id} person{id}@x.com"))
+      .unwrap();
+  }
+  conn.close().unwrap();
+  let file = std::fs::read(filename).unwrap();
+  let mut legacy = file[4096..].to_vec();
+  let (right, cell) = (legacy[10..14].to_vec(), legacy[14..22].to_vec());
+  legacy[6..10].copy_from_slice(&2u32.to_be_bytes());
+  legacy[10..18].copy_from_slice(&cell);
+  legacy[18..22].copy_from_slice(&right);
+  legacy[22..26].copy_from_slice(&20u32.to_be_bytes());
+  std::fs::write(filename, &legacy).unwrap();
+  for _ in 0..2 {
+    // converted on the first open, and written back with a header on close
+    let mut conn = Connection::open_with(filename, with_size(512)).unwrap();
+    assert_eq!(conn.page_size(), 4096);
+    assert_eq!(conn.integrity_check(), Vec::<String>::new());
+    let ids: Vec<u32> = conn.query_as("select id from users").unwrap();
+    assert_eq!(ids, (1..=20).collect::<Vec<_>>());
+    conn.close().unwrap();
+  }
+  assert_eq!(std::fs::read(filename).unwrap(), file);
+
+  // a file with neither a header nor the pages of a format 1 file
+  for (bytes, reason) in [
+    (
+      &b"hello"[..],
+      "page 0: the file ends partway through the page",
+    ),
+    (&[b'x'; 8192][..], "page 0: unknown checksum flag 120"),
+  ] {
+    std::fs::write(filename, bytes).unwrap();
+    let err = Connection::open(filename).err().unwrap();
+    assert_eq!(err.code(), SQLITE_NOTADB);
+    assert_eq!(
+      err.to_string(),
+      format!("File is not a database: it has no header, and as a format 1 file, {reason}.")
+    );
+  }
+
+  // a header from a later format
+  let mut bad = file.clone();
+  bad[20] = 3;
+  std::fs::write(filename, &bad).unwrap();
+  let err = Connection::open(filename).err().unwrap();
+  assert_eq!(err.code(), SQLITE_NOTADB);
+  assert_eq!(
+    err.to_string(),
+    "File is not a database: its header gives format version 3, not 2."
+  );
+
+  // a header giving a size no database has
+  let mut bad = file.clone();
+  bad[16..20].copy_from_slice(&1000u32.to_be_bytes());
+  std::fs::write(filename, &bad).unwrap();
+  let err = Connection::open(filename).err().unwrap();
+  assert_eq!(err.code(), SQLITE_NOTADB);
+  assert_eq!(
+    err.to_string(),
+    "File is not a database: its header gives page size 1000."
+  );
+
+  for page_size in [256, 1000, 131_072] {
+    let err = Connection::open_with(filename, with_size(page_size))
+      .err()
+      .unwrap();
+    assert_eq!(err.code(), SQLITE_MISUSE);
+    assert_eq!(
+      err.to_string(),
+      format!("Page size must be a power of two from 512 to 65536, not {page_size}.")
+    );
+  }
+  let _ = std::fs::remove_file(filename);
+}
+
+#[test]
+fn dropping_the_connection_keeps_its_rows() {
+  let filename = "dropping_the_connection_keeps_its_rows.db";
+  let _ = std::fs::remove_file(filename);
+  let mut conn = Connection::open(filename).unwrap();
+  for id in 1..=100 {
+    conn
+      .execute(&format!("insert {id} user{id} person{id}@x.com"))
+      .unwrap();
+  }
+  drop(conn);
+
+  let mut conn = Connection::open(filename).unwrap();
+  let count: Vec<i64> = conn.query_as("select count(*) from users").unwrap();
+  assert_eq!(count, [100]);
+  let _ = std::fs::remove_file(filename);
+}
+
+#[test]
+fn queries_compute_no_more_rows_than_are_taken() {
+  let filename = "queries_compute_no_more_rows_than_are_taken.db";
+  let _ = std::fs::remove_file(filename);
+  let mut conn = Connection::open(filename).unwrap();
+  conn.execute("insert 1 alice a@x.com").unwrap();
+
+  // each of these goes on forever, so only streaming lets the caller stop after three rows
+  let count = "with recursive r(n) as (select 1 union all select n + 1 from r)";
+  for (sql, expected) in [
+    ("select n from r", [1, 2, 3]),
+    ("select n from r union select 1", [1, 2, 3]),
+    ("select 0 union all select n from r", [0, 1, 2]),
+    ("select r.n from r join users u on u.id <= r.n", [1, 2, 3]),
+  ] {
+    let rows: Vec<_> = conn
+      .query(&format!("{count} {sql}"))
+      .unwrap()
+      .take(3)
+      .map(|row| row.unwrap().into_values())
+      .collect();
+    let expected: Vec<_> = expected.map(|n| vec![Value::Integer(n)]).into();
+    assert_eq!(rows, expected, "{sql}");
+  }
+  let _ = std::fs::remove_file(filename);
+}
+
+#[test]
+fn analyze_estimates_distinct_values_of_a_large_table() {
+  let filename = "analyze_estimates_distinct_values_of_a_large_table.db";
+  let _ = std::fs::remove_file(filename);
+  let mut conn = Connection::open(filename).unwrap();
+  let rows = (0..20_000u32).map(|id| (id, format!("user{}", id % 40), format!("user{id}@x")));
+  assert_eq!(conn.bulk_load(rows, 1.0).unwrap(), 20_000);
+  conn.execute("analyze").unwrap();
+
+  // the key and the 40 usernames are counted exactly, the 20000 emails estimated
+  let stat: Vec<String> = conn.query_as("select stat from sqlite_stat1").unwrap();
+  let stat: Vec<&str> = stat.iter().map(String::as_str).collect();
+  assert_eq!(stat[..3], ["20000", "20000 1", "20000 500"]);
+  assert!(["20000 1", "20000 2"].contains(&stat[3]), "{stat:?}");
+  drop(conn);
+  let _ = std::fs::remove_file(filename);
+}
@@ -25,6 +25,32 @@ fn insert_and_retrieve_a_row() {
   );
 }
 
+#[test]
+fn insert_stores_parameter_like_text_as_written() {
+  let filename = "insert_stores_parameter_like_text_as_written.db";
+
+  let mut cmd = Command::cargo_bin("sqlite_rs").unwrap();
+  let assert = cmd
+    .arg(filename)
+    .write_stdin(["insert 1 :bob ?2", "insert ? bob x@y", "select", ".exit"].join("\n"))
+    .assert();
+
+  let _ = std::fs::remove_file(filename);
+
+  assert
+    .success()
+    .stdout(
+      [
+        "db > Executed.",
+        "db > db > (1, \":bob\", \"?2\")",
+        "Executed.",
+        "db > ",
+      ]
+      .join("\n"),
+    )
+    .stderr("Syntax error. Could not parse statement.\n");
+}
+
 #[test]
 #[ignore]
 fn table_is_full() {
@@ -217,11 +243,11 @@ fn allows_printing_out_the_structure_of_2_leaf_node_btree() {
   let assert = cmd.arg(filename).write_stdin(script).assert();
 
   let _ = std::fs::remove_file(filename);
+  // ascending ids split the full leaf off whole, leaving the new row on its own
   let mut expect: String = (0..14).map(|_| "db > Executed.\n").collect();
-  expect.push_str("db > Tree:\nintern (size 2)\n  leaf (size 7)\n");
-  expect.push_str(&(0..7).map(|i| format!("    - {i}\n")).collect::<String>());
-  expect.push_str("  leaf (size 7)\n");
-  expect.push_str(&(7..14).map(|i| format!("    - {i}\n")).collect::<String>());
+  expect.push_str("db > Tree:\nintern (size 2)\n  leaf (size 13)\n");
+  expect.push_str(&(0..13).map(|i| format!("    - {i}\n")).collect::<String>());
+  expect.push_str("  leaf (size 1)\n    - 13\n");
   expect.push_str("\nExecuted.\ndb > ");
   assert.success().stdout(expect);
 }
@@ -230,7 +256,7 @@ fn allows_printing_out_the_structure_of_2_leaf_node_btree() {
 fn allows_printing_out_the_structure_of_3_leaf_node_btree() {
   let filename = "allows_printing_out_the_structure_of_3_leaf_node_btree.db";
   let mut cmd = Command::cargo_bin("sqlite_rs").unwrap();
-  let mut script: String = (0..21)
+  let mut script: String = (0..27)
     .map(|i| format!("insert {i} user{i} person{i}@example.com\n"))
     .collect();
   script.push_str(".btree\n.exit");
@@ -238,13 +264,12 @@ fn allows_printing_out_the_structure_of_3_leaf_node_btree() {
 
   let _ = std::fs::remove_file(filename);
 
-  let mut expect: String = (0..21).map(|_| "db > Executed.\n").collect();
-  expect.push_str("db > Tree:\nintern (size 3)\n  leaf (size 7)\n");
-  expect.push_str(&(0..7).map(|i| format!("    - {i}\n")).collect::<String>());
-  expect.push_str("  leaf (size 7)\n");
-  expect.push_str(&(7..14).map(|i| format!("    - {i}\n")).collect::<String>());
-  expect.push_str("  leaf (size 7)\n");
-  expect.push_str(&(14..21).map(|i| format!("    - {i}\n")).collect::<String>());
+  let mut expect: String = (0..27).map(|_| "db > Executed.\n").collect();
+  expect.push_str("db > Tree:\nintern (size 3)\n  leaf (size 13)\n");
+  expect.push_str(&(0..13).map(|i| format!("    - {i}\n")).collect::<String>());
+  expect.push_str("  leaf (size 13)\n");
+  expect.push_str(&(13..26).map(|i| format!("    - {i}\n")).collect::<String>());
+  expect.push_str("  leaf (size 1)\n    - 26\n");
   expect.push_str("\nExecuted.\ndb > ");
   assert.success().stdout(expect);
 }
@@ -276,3 +301,1131 @@ fn prints_all_rows_in_a_multi_level_tree() {
   expect.push_str("Executed.\ndb > ");
   assert.success().stdout(expect);
 }
+
+#[test]
+fn select_with_limit_and_offset() {
+  let filename = "select_with_limit_and_offset.db";
+  let mut cmd = Command::cargo_bin("sqlite_rs").unwrap();
+  let mut script: String = (0..30)
+    .map(|i| format!("insert {i} user{i} person{i}@example.com\n"))
+    .collect();
+  script.push_str("select * from users limit 3 offset 12\n.exit");
+  let assert = cmd.arg(filename).write_stdin(script).assert();
+
+  let _ = std::fs::remove_file(filename);
+
+  let mut expect: String = (0..30).map(|_| "db > Executed.\n").collect();
+  expect.push_str("db > ");
+  expect.push_str(
+    &(12..15)
+      .map(|i| format!("({i}, \"user{i}\", \"person{i}@example.com\")\n"))
+      .collect::<String>(),
+  );
+  expect.push_str("Executed.\ndb > ");
+  assert.success().stdout(expect);
+}
+
+#[test]
+fn select_with_limit_comma_form_and_offset_past_end() {
+  let filename = "select_with_limit_comma_form_and_offset_past_end.db";
+  let mut cmd = Command::cargo_bin("sqlite_rs").unwrap();
+  let assert = cmd
+    .arg(filename)
+    .write_stdin(
+      [
+        "insert 1 user1 person1@example.com",
+        "insert 2 user2 person2@example.com",
+        "insert 3 user3 person3@example.com",
+        "select limit 1, 5",
+        "select limit -1 offset 10",
+        "select limit",
+        ".exit",
+      ]
+      .join("\n"),
+    )
+    .assert();
+
+  let _ = std::fs::remove_file(filename);
+
+  assert
+    .success()
+    .stdout(
+      [
+        "db > Executed.",
+        "db > Executed.",
+        "db > Executed.",
+        "db > (2, \"user2\", \"person2@example.com\")",
+        "(3, \"user3\", \"person3@example.com\")",
+        "Executed.",
+        "db > Executed.",
+        "db > db > ",
+      ]
+      .join("\n"),
+    )
+    .stderr("Syntax error: incomplete input.\n");
+}
+
+#[test]
+fn aggregates_with_group_by_and_having() {
+  let filename = "aggregates_with_group_by_and_having.db";
+  let mut cmd = Command::cargo_bin("sqlite_rs").unwrap();
+  let assert = cmd
+    .arg(filename)
+    .write_stdin(
+      [
+        "insert 1 alice a@x.com",
+        "insert 2 bob b@x.com",
+        "insert 3 alice c@x.com",
+        "insert 4 carol d@x.com",
+        "select username, count(*), min(id), max(id), sum(id), avg(id) from users group by username",
+        "select username name, count(*) n from users group by name having n > 1",
+        ".exit",
+      ]
+      .join("\n"),
+    )
+    .assert();
+
+  let _ = std::fs::remove_file(filename);
+
+  assert.success().stdout(
+    [
+      "db > Executed.",
+      "db > Executed.",
+      "db > Executed.",
+      "db > Executed.",
+      "db > (\"alice\", 2, 1, 3, 4, 2.0)",
+      "(\"bob\", 1, 2, 2, 2, 2.0)",
+      "(\"carol\", 1, 4, 4, 4, 4.0)",
+      "Executed.",
+      "db > (\"alice\", 2)",
+      "Executed.",
+      "db > ",
+    ]
+    .join("\n"),
+  );
+}
+
+#[test]
+fn aggregates_over_empty_input_follow_null_semantics() {
+  let filename = "aggregates_over_empty_input_follow_null_semantics.db";
+  let mut cmd = Command::cargo_bin("sqlite_rs").unwrap();
+  let assert = cmd
+    .arg(filename)
+    .write_stdin(
+      [
+        "insert 1 alice a@x.com",
+        "select count(*), count(id), sum(id), avg(id), min(id), max(id) from users where id > 1",
+        "select count(*) from users where id > 1 group by username",
+        "select id from users where count(*) > 0",
+        ".exit",
+      ]
+      .join("\n"),
+    )
+    .assert();
+
+  let _ = std::fs::remove_file(filename);
+
+  assert
+    .success()
+    .stdout(
+      [
+        "db > Executed.",
+        "db > (0, 0, NULL, NULL, NULL, NULL)",
+        "Executed.",
+        "db > Executed.",
+        "db > db > ",
+      ]
+      .join("\n"),
+    )
+    .stderr("Misuse of aggregate function count().\n");
+}
+
+#[test]
+fn arithmetic_overflows_to_real_and_text_reads_as_its_numeric_prefix() {
+  let filename = "arithmetic_overflows_to_real_and_text_reads_as_its_numeric_prefix.db";
+  let mut cmd = Command::cargo_bin("sqlite_rs").unwrap();
+  let assert = cmd
+    .arg(filename)
+    .write_stdin(
+      [
+        "select 9223372036854775807 + 1, -9223372036854775807 - 10, 4294967296 * 4294967296, 7 * 6",
+        "select (-9223372036854775807 - 1) / -1, (-9223372036854775807 - 1) % -1",
+        "select '1.2.3' + 0, '12abc' * 2, ' 1e3x' + 0, '1e' + 0, '.5' + 0, '-.' + 0, 'abc' + 1",
+        "with t(n) as (select 9223372036854775807 union all select 1) select sum(n) from t",
+        ".exit",
+      ]
+      .join("\n"),
+    )
+    .assert();
+
+  let _ = std::fs::remove_file(filename);
+
+  assert
+    .success()
+    .stdout(
+      [
+        "db > (9223372036854776000, -9223372036854776000, 18446744073709552000, 42)",
+        "Executed.",
+        "db > (9223372036854776000, 0)",
+        "Executed.",
+        "db > (1.2, 24, 1000.0, 1, 0.5, 0, 1)",
+        "Executed.",
+        "db > db > ",
+      ]
+      .join("\n"),
+    )
+    .stderr("integer overflow\n");
+}
+
+#[test]
+fn count_and_min_max_of_id_over_multi_level_tree() {
+  let filename = "count_and_min_max_of_id_over_multi_level_tree.db";
+  let mut cmd = Command::cargo_bin("sqlite_rs").unwrap();
+  let mut script = "select count(*), min(id), max(id) from users\n".to_string();
+  script.extend(
+    (0..50)
+      .map(|i| (i * 37) % 50 + 1)
+      .map(|i| format!("insert {i} u{i} e{i}\n")),
+  );
+  script.push_str("select count(*), min(id), max(id), count(id) from users\n.exit");
+  let assert = cmd.arg(filename).write_stdin(script).assert();
+
+  let _ = std::fs::remove_file(filename);
+
+  let mut expect = "db > (0, NULL, NULL)\nExecuted.\n".to_string();
+  expect.push_str(&(0..50).map(|_| "db > Executed.\n").collect::<String>());
+  expect.push_str("db > (50, 1, 50, 50)\nExecuted.\ndb > ");
+  assert.success().stdout(expect);
+}
+
+#[test]
+fn inner_and_left_joins() {
+  let filename = "inner_and_left_joins.db";
+  let mut cmd = Command::cargo_bin("sqlite_rs").unwrap();
+  let assert = cmd
+    .arg(filename)
+    .write_stdin(
+      [
+        "insert 1 alice a@x.com",
+        "insert 2 bob b@x.com",
+        "insert 3 carol c@x.com",
+        "select a.id, b.username from users a join users b on b.id = a.id + 1",
+        "select a.id, b.id from users a left join users b on b.id = a.id + 1",
+        "select a.username, b.* from users a left join users b on a.id < b.id and b.id <> 2",
+        "select count(*) from users a cross join users b",
+        "select id from users a join users b on a.id = b.id",
+        ".exit",
+      ]
+      .join("\n"),
+    )
+    .assert();
+
+  let _ = std::fs::remove_file(filename);
+
+  assert
+    .success()
+    .stdout(
+      [
+        "db > Executed.",
+        "db > Executed.",
+        "db > Executed.",
+        "db > (1, \"bob\")",
+        "(2, \"carol\")",
+        "Executed.",
+        "db > (1, 2)",
+        "(2, 3)",
+        "(3, NULL)",
+        "Executed.",
+        "db > (\"alice\", 3, \"carol\", \"c@x.com\")",
+        "(\"bob\", 3, \"carol\", \"c@x.com\")",
+        "(\"carol\", NULL, NULL, NULL)",
+        "Executed.",
+        "db > (9)",
+        "Executed.",
+        "db > db > ",
+      ]
+      .join("\n"),
+    )
+    .stderr("Ambiguous column name: id.\n");
+}
+
+#[test]
+fn in_exists_and_scalar_subqueries() {
+  let filename = "in_exists_and_scalar_subqueries.db";
+  let mut cmd = Command::cargo_bin("sqlite_rs").unwrap();
+  let assert = cmd
+    .arg(filename)
+    .write_stdin(
+      [
+        "insert 1 alice a@x.com",
+        "insert 2 bob b@x.com",
+        "insert 3 alice c@x.com",
+        "select id from users where id in (select id + 1 from users where username = 'alice')",
+        "select id from users a where exists (select 1 from users b where b.username = a.username and b.id <> a.id)",
+        "select id, (select count(*) from users b where b.id < a.id) from users a",
+        "select id from users where id not in (select id from users where id > 1)",
+        "select 2 in (select null), 2 not in (1, null), null in (select id from users where id > 9)",
+        "select (select id, username from users)",
+        ".exit",
+      ]
+      .join("\n"),
+    )
+    .assert();
+
+  let _ = std::fs::remove_file(filename);
+
+  assert
+    .success()
+    .stdout(
+      [
+        "db > Executed.",
+        "db > Executed.",
+        "db > Executed.",
+        "db > (2)",
+        "Executed.",
+        "db > (1)",
+        "(3)",
+        "Executed.",
+        "db > (1, 0)",
+        "(2, 1)",
+        "(3, 2)",
+        "Executed.",
+        "db > (1)",
+        "Executed.",
+        "db > (NULL, NULL, 0)",
+        "Executed.",
+        "db > db > ",
+      ]
+      .join("\n"),
+    )
+    .stderr("Sub-select returns 2 columns - expected 1.\n");
+}
+
+#[test]
+fn common_table_expressions_and_subqueries_in_from() {
+  let filename = "common_table_expressions_and_subqueries_in_from.db";
+  let mut cmd = Command::cargo_bin("sqlite_rs").unwrap();
+  let assert = cmd
+    .arg(filename)
+    .write_stdin(
+      [
+        "insert 1 alice a@x.com",
+        "insert 2 bob b@x.com",
+        "insert 3 carol c@x.com",
+        "with big as (select id, username from users where id > 1) select b.username, u.email from big b join users u on u.id = b.id",
+        "with users as (select * from users where id < 3) select count(*) from users",
+        "select t.n from (select id * 10 as n from users) as t where t.n > 10",
+        "with a(x) as (select 1), b as (select x + 1 y from a) select * from a, b",
+        "with t(a, b) as (select 1) select * from t",
+        ".exit",
+      ]
+      .join("\n"),
+    )
+    .assert();
+
+  let _ = std::fs::remove_file(filename);
+
+  assert
+    .success()
+    .stdout(
+      [
+        "db > Executed.",
+        "db > Executed.",
+        "db > Executed.",
+        "db > (\"bob\", \"b@x.com\")",
+        "(\"carol\", \"c@x.com\")",
+        "Executed.",
+        "db > (2)",
+        "Executed.",
+        "db > (20)",
+        "(30)",
+        "Executed.",
+        "db > (1, 2)",
+        "Executed.",
+        "db > db > ",
+      ]
+      .join("\n"),
+    )
+    .stderr("Table t has 1 values for 2 columns.\n");
+}
+
+#[test]
+fn recursive_common_table_expressions() {
+  let filename = "recursive_common_table_expressions.db";
+  let mut cmd = Command::cargo_bin("sqlite_rs").unwrap();
+  let assert = cmd
+    .arg(filename)
+    .write_stdin(
+      [
+        "insert 1 alice a@x.com",
+        "insert 2 bob b@x.com",
+        "with recursive cnt(x) as (select 1 union all select x + 1 from cnt where x < 4) select x from cnt",
+        "with recursive cnt(x) as (select 1 union all select x + 1 from cnt limit 3) select x, x * x from cnt",
+        "with recursive c(x) as (select 1 union select x % 3 + 1 from c) select * from c",
+        "with recursive c(x) as (select 1 union all select x + 1 from c where x < 3) select id, (select count(*) from c where x <= id) from users",
+        "with recursive c(n) as (select 1 union all select n + 1 from c) select n from c limit 3",
+        "with recursive c(n) as (select 1 union all select n + 1 from c) select n * 10 from c where n % 2 = 0 limit 2 offset 1",
+        "select exists (with recursive c(n) as (select 1 union all select n + 1 from c) select n from c)",
+        // an arm after the recursive one that does not read the CTE would run for every row
+        "with recursive r(n) as (select 1 union all select n+1 from r where n<3 union all select 9) select n from r",
+        ".exit",
+      ]
+      .join("\n"),
+    )
+    .assert();
+
+  let _ = std::fs::remove_file(filename);
+
+  assert
+    .success()
+    .stdout(
+      [
+        "db > Executed.",
+        "db > Executed.",
+        "db > (1)",
+        "(2)",
+        "(3)",
+        "(4)",
+        "Executed.",
+        "db > (1, 1)",
+        "(2, 4)",
+        "(3, 9)",
+        "Executed.",
+        "db > (1)",
+        "(2)",
+        "(3)",
+        "Executed.",
+        "db > (1, 1)",
+        "(2, 2)",
+        "Executed.",
+        "db > (1)",
+        "(2)",
+        "(3)",
+        "Executed.",
+        "db > (40)",
+        "(60)",
+        "Executed.",
+        "db > (1)",
+        "Executed.",
+        "db > db > ",
+      ]
+      .join("\n"),
+    )
+    .stderr("Circular reference: r.\n");
+}
+
+#[test]
+fn compound_selects_with_order_by_and_limit() {
+  let filename = "compound_selects_with_order_by_and_limit.db";
+  let mut cmd = Command::cargo_bin("sqlite_rs").unwrap();
+  let assert = cmd
+    .arg(filename)
+    .write_stdin(
+      [
+        "insert 1 alice a@x.com",
+        "insert 2 bob b@x.com",
+        "insert 3 alice c@x.com",
+        "select username from users union select username from users where id > 1",
+        "select username from users where id < 3 union all select username from users where id > 1",
+        "select id from users intersect select id + 1 from users",
+        "select id from users except select id + 1 from users",
+        "select username name, id from users union all select 'zed', 0 order by name desc, 2 limit 2 offset 1",
+        "select id from users union select 1, 2",
+        "select id from users union select id from users order by email",
+        ".exit",
+      ]
+      .join("\n"),
+    )
+    .assert();
+
+  let _ = std::fs::remove_file(filename);
+
+  assert
+    .success()
+    .stdout(
+      [
+        "db > Executed.",
+        "db > Executed.",
+        "db > Executed.",
+        "db > (\"alice\")",
+        "(\"bob\")",
+        "Executed.",
+        "db > (\"alice\")",
+        "(\"bob\")",
+        "(\"bob\")",
+        "(\"alice\")",
+        "Executed.",
+        "db > (2)",
+        "(3)",
+        "Executed.",
+        "db > (1)",
+        "Executed.",
+        "db > (\"bob\", 2)",
+        "(\"alice\", 1)",
+        "Executed.",
+        "db > db > db > ",
+      ]
+      .join("\n"),
+    )
+    .stderr(
+      [
+        "SELECTs to the left and right of UNION do not have the same number of result columns.",
+        "ORDER BY term does not match any column in the result set.\n",
+      ]
+      .join("\n"),
+    );
+}
+
+#[test]
+fn order_by_expressions_aliases_and_aggregates() {
+  let filename = "order_by_expressions_aliases_and_aggregates.db";
+  let mut cmd = Command::cargo_bin("sqlite_rs").unwrap();
+  let assert = cmd
+    .arg(filename)
+    .write_stdin(
+      [
+        "insert 1 carol c@x.com",
+        "insert 2 alice a@x.com",
+        "insert 3 bob b@x.com",
+        "insert 4 alice d@x.com",
+        "select id from users order by username desc, id desc",
+        "select username, count(*) n from users group by username order by n desc, 1 limit 2",
+        "select id, email from users order by id * -1 limit 1",
+        "select id from users order by 2",
+        ".exit",
+      ]
+      .join("\n"),
+    )
+    .assert();
+
+  let _ = std::fs::remove_file(filename);
+
+  assert
+    .success()
+    .stdout(
+      [
+        "db > Executed.",
+        "db > Executed.",
+        "db > Executed.",
+        "db > Executed.",
+        "db > (1)",
+        "(3)",
+        "(4)",
+        "(2)",
+        "Executed.",
+        "db > (\"alice\", 2)",
+        "(\"bob\", 1)",
+        "Executed.",
+        "db > (4, \"d@x.com\")",
+        "Executed.",
+        "db > db > ",
+      ]
+      .join("\n"),
+    )
+    .stderr("ORDER BY term out of range - should be between 1 and 1.\n");
+}
+
+#[test]
+fn window_functions() {
+  let filename = "window_functions.db";
+  let mut cmd = Command::cargo_bin("sqlite_rs").unwrap();
+  let assert = cmd
+    .arg(filename)
+    .write_stdin(
+      [
+        "insert 1 carol c@x.com",
+        "insert 2 alice a@x.com",
+        "insert 3 bob b@x.com",
+        "insert 4 alice d@x.com",
+        "select id, row_number() over (order by username, id), rank() over (order by username), dense_rank() over (order by username) from users",
+        "select id, row_number() over (partition by username order by id desc), count(*) over (partition by username) from users",
+        "select id, lag(id) over (order by id), lead(id, 2, -1) over (order by id) from users",
+        "select id, sum(id) over (order by id), sum(id) over (order by id rows between 1 preceding and 1 following), last_value(id) over (order by id) from users",
+        "select username, count(*), rank() over (order by count(*) desc) from users group by username",
+        "select id from users where row_number() over () > 1",
+        "select sum(id) over (order by id rows between 1 following and 1 preceding) from users",
+        "select sum(id) over (order by id rows between current row and 1 preceding) from users",
+        ".exit",
+      ]
+      .join("\n"),
+    )
+    .assert();
+
+  let _ = std::fs::remove_file(filename);
+
+  assert
+    .success()
+    .stdout(
+      [
+        "db > Executed.",
+        "db > Executed.",
+        "db > Executed.",
+        "db > Executed.",
+        "db > (1, 4, 4, 3)",
+        "(2, 1, 1, 1)",
+        "(3, 3, 3, 2)",
+        "(4, 2, 1, 1)",
+        "Executed.",
+        "db > (1, 1, 1)",
+        "(2, 2, 2)",
+        "(3, 1, 1)",
+        "(4, 1, 2)",
+        "Executed.",
+        "db > (1, NULL, 3)",
+        "(2, 1, 4)",
+        "(3, 2, -1)",
+        "(4, 3, -1)",
+        "Executed.",
+        "db > (1, 1, 3, 1)",
+        "(2, 3, 6, 2)",
+        "(3, 6, 9, 3)",
+        "(4, 10, 7, 4)",
+        "Executed.",
+        "db > (\"carol\", 1, 2)",
+        "(\"alice\", 2, 1)",
+        "(\"bob\", 1, 2)",
+        "Executed.",
+        "db > db > db > db > ",
+      ]
+      .join("\n"),
+    )
+    .stderr(
+      [
+        "Misuse of window function row_number().",
+        "Unsupported frame specification.",
+        "Unsupported frame specification.\n",
+      ]
+      .join("\n"),
+    );
+}
+
+#[test]
+fn compiled_scans_joins_and_paging() {
+  let filename = "compiled_scans_joins_and_paging.db";
+  let mut cmd = Command::cargo_bin("sqlite_rs").unwrap();
+  let assert = cmd
+    .arg(filename)
+    .write_stdin(
+      [
+        "select nosuch from users",
+        "insert 1 alice a@x.com",
+        "insert 2 bob b@x.com",
+        "insert 3 carol c@x.com",
+        "select a.id, b.username, c.id from users a left join users b on b.id = a.id + 1 left join users c on c.id = b.id + 1",
+        "select id, id in (1, null), 0 and 1 / 0 from users where id % 2 = 1 limit 1 offset 1",
+        "select a.id, b.id from users a left join users b on b.id > a.id and b.id < 3 limit 2",
+        ".exit",
+      ]
+      .join("\n"),
+    )
+    .assert();
+
+  let _ = std::fs::remove_file(filename);
+
+  assert
+    .success()
+    .stdout(
+      [
+        "db > db > Executed.",
+        "db > Executed.",
+        "db > Executed.",
+        "db > (1, \"bob\", 3)",
+        "(2, \"carol\", NULL)",
+        "(3, NULL, NULL)",
+        "Executed.",
+        "db > (3, NULL, 0)",
+        "Executed.",
+        "db > (1, 2)",
+        "(2, NULL)",
+        "Executed.",
+        "db > ",
+      ]
+      .join("\n"),
+    )
+    .stderr("No such column: nosuch.\n");
+}
+
+#[test]
+fn compiled_ctes_subqueries_and_correlated_columns() {
+  let filename = "compiled_ctes_subqueries_and_correlated_columns.db";
+  let mut cmd = Command::cargo_bin("sqlite_rs").unwrap();
+  let assert = cmd
+    .arg(filename)
+    .write_stdin(
+      [
+        "insert 1 alice a@x.com",
+        "insert 2 bob b@x.com",
+        "insert 3 carol c@x.com",
+        "with t(x) as (select 2) select u.id, t.x from users u left join t on t.x = u.id",
+        "select s.n, u.username from (select 3 as n union all select 1) s join users u on u.id = s.n",
+        "select id, (select count(*) from users b where b.id < a.id) from users a where exists (select 1 from users c where c.id = a.id + 1)",
+        ".exit",
+      ]
+      .join("\n"),
+    )
+    .assert();
+
+  let _ = std::fs::remove_file(filename);
+
+  assert.success().stdout(
+    [
+      "db > Executed.",
+      "db > Executed.",
+      "db > Executed.",
+      "db > (1, NULL)",
+      "(2, 2)",
+      "(3, NULL)",
+      "Executed.",
+      "db > (3, \"carol\")",
+      "(1, \"alice\")",
+      "Executed.",
+      "db > (1, 0)",
+      "(2, 1)",
+      "Executed.",
+      "db > ",
+    ]
+    .join("\n"),
+  );
+}
+
+#[test]
+fn order_by_id_reads_the_tree_backwards() {
+  let filename = "order_by_id_reads_the_tree_backwards.db";
+  let mut cmd = Command::cargo_bin("sqlite_rs").unwrap();
+  let mut script: Vec<_> = (1..=40)
+    .map(|i| format!("insert {i} user{i} person{i}@example.com"))
+    .collect();
+  script.extend(
+    [
+      "select id from users where id >= 18 and id < 22 order by id desc",
+      "select id from users where id < 3.5 order by users.id desc",
+      "select id from users order by id desc limit 2 offset 1",
+      "select id from users where id > 38 order by id",
+      "select username as id, id from users order by id desc limit 1",
+      "explain query plan select * from users where id >= 18 and id < 22 order by id desc",
+      ".exit",
+    ]
+    .map(str::to_string),
+  );
+  let assert = cmd.arg(filename).write_stdin(script.join("\n")).assert();
+
+  let _ = std::fs::remove_file(filename);
+
+  let mut expected = vec!["db > Executed."; 40];
+  expected.extend([
+    "db > (21)",
+    "(20)",
+    "(19)",
+    "(18)",
+    "Executed.",
+    "db > (3)",
+    "(2)",
+    "(1)",
+    "Executed.",
+    "db > (39)",
+    "(38)",
+    "Executed.",
+    "db > (39)",
+    "(40)",
+    "Executed.",
+    "db > (\"user9\", 9)",
+    "Executed.",
+    "db > QUERY PLAN",
+    "`--SEARCH users USING PRIMARY KEY (id>=? AND id<?)",
+    "Executed.",
+    "db > ",
+  ]);
+  assert.success().stdout(expected.join("\n"));
+}
+
+#[test]
+fn explain_and_explain_query_plan() {
+  let filename = "explain_and_explain_query_plan.db";
+  let mut cmd = Command::cargo_bin("sqlite_rs").unwrap();
+  let assert = cmd
+    .arg(filename)
+    .write_stdin(
+      [
+        "insert 1 alice a@x.com",
+        "insert 2 bob b@x.com",
+        "insert 3 carol c@x.com",
+        "explain select username from users where id = 2",
+        "explain query plan select * from users",
+        "explain query plan select * from users where id > 1 and id <= 3",
+        "explain query plan select a.id from users a join users b on b.id = a.id + 1",
+        "explain query plan with t as (select id from users) select * from t where id in (select id from users) order by id",
+        "select id from users where id > 1.5 and id < 3",
+        // queries the executor runs are planned the same way
+        "explain query plan select count(*) from users where id = 3",
+        "select count(*), max(username) from users where id >= 2 and id < 3",
+        "explain query plan with t(n) as (select 3) select * from users, t where id = n",
+        "explain query plan select (select count(*) from users b where b.id < a.id) from users a",
+        ".exit",
+      ]
+      .join("\n"),
+    )
+    .assert();
+
+  let _ = std::fs::remove_file(filename);
+
+  assert.success().stdout(
+    [
+      "db > Executed.",
+      "db > Executed.",
+      "db > Executed.",
+      "db > addr  opcode         p1    p2    p3    p4             comment",
+      "----  -------------  ----  ----  ----  -------------  -------------",
+      "0     OpenRead       0     0     0     users          root=0",
+      "1     Integer        2     0     0                    r[0]=2",
+      "2     SeekRowid      0     9     0                    intkey=r[0]",
+      "3     Rowid          0     2     0                    r[2]=id",
+      "4     Integer        2     3     0                    r[3]=2",
+      "5     Eq             2     3     1                    r[1]=r[2]==r[3]",
+      "6     IfNot          1     9     1                    if !r[1] goto 9",
+      "7     Column         0     1     4                    r[4]=username",
+      "8     ResultRow      4     1     0                    output=r[4..4]",
+      "9     Halt           0     0     0",
+      "Executed.",
+      "db > QUERY PLAN",
+      "`--SCAN users",
+      "Executed.",
+      "db > QUERY PLAN",
+      "`--SEARCH users USING PRIMARY KEY (id>? AND id<=?)",
+      "Executed.",
+      "db > QUERY PLAN",
+      "|--SCAN a",
+      "`--SEARCH b USING PRIMARY KEY (id=?)",
+      "Executed.",
+      "db > QUERY PLAN",
+      "|--MATERIALIZE t",
+      "|  `--SCAN users",
+      "|--SCAN t",
+      "|--LIST SUBQUERY",
+      "|  `--SCAN users",
+      "`--USE TEMP B-TREE FOR ORDER BY",
+      "Executed.",
+      "db > (2)",
+      "Executed.",
+      "db > QUERY PLAN",
+      "`--SEARCH users USING PRIMARY KEY (id=?)",
+      "Executed.",
+      "db > (1, \"bob\")",
+      "Executed.",
+      "db > QUERY PLAN",
+      "|--MATERIALIZE t",
+      "|  `--SCAN CONSTANT ROW",
+      "|--SCAN t",
+      "`--SEARCH users USING PRIMARY KEY (id=?)",
+      "Executed.",
+      "db > QUERY PLAN",
+      "|--SCAN a",
+      "`--CORRELATED SCALAR SUBQUERY",
+      "   `--SEARCH b USING PRIMARY KEY (id<?)",
+      "Executed.",
+      "db > ",
+    ]
+    .join("\n"),
+  );
+}
+
+#[test]
+fn explain_of_queries_the_executor_runs() {
+  let filename = "explain_of_queries_the_executor_runs.db";
+  let mut cmd = Command::cargo_bin("sqlite_rs").unwrap();
+  let assert = cmd
+    .arg(filename)
+    .write_stdin(
+      [
+        "explain select count(*) from users",
+        "explain select username from users order by username",
+        "explain select id from users intersect select 1",
+        "explain with t(x) as (select 1) select x from t",
+        ".exit",
+      ]
+      .join("\n"),
+    )
+    .assert();
+
+  let _ = std::fs::remove_file(filename);
+
+  assert.success().stdout(
+    [
+      "db > addr  opcode         p1    p2    p3    p4             comment",
+      "----  -------------  ----  ----  ----  -------------  -------------",
+      "0     Query          0     0     0     aggregate      all rows by the executor",
+      "1     Halt           0     0     0",
+      "Executed.",
+      "db > addr  opcode         p1    p2    p3    p4             comment",
+      "----  -------------  ----  ----  ----  -------------  -------------",
+      "0     Query          0     0     0     ORDER BY       all rows by the executor",
+      "1     Halt           0     0     0",
+      "Executed.",
+      "db > addr  opcode         p1    p2    p3    p4             comment",
+      "----  -------------  ----  ----  ----  -------------  -------------",
+      "0     Query          0     0     0     INTERSECT      all rows by the executor",
+      "1     Halt           0     0     0",
+      "Executed.",
+      "db > addr  opcode         p1    p2    p3    p4             comment",
+      "----  -------------  ----  ----  ----  -------------  -------------",
+      "0     OpenRows       0     0     0     t              materialize",
+      "1     Rewind         0     5     0",
+      "2     Column         0     0     0                    r[0]=x",
+      "3     ResultRow      0     1     0                    output=r[0..0]",
+      "4     Next           0     2     0",
+      "5     Halt           0     0     0",
+      "Executed.",
+      "db > ",
+    ]
+    .join("\n"),
+  );
+}
+
+#[test]
+fn analyze_and_join_order() {
+  let filename = "analyze_and_join_order.db";
+  let mut cmd = Command::cargo_bin("sqlite_rs").unwrap();
+  let assert = cmd
+    .arg(filename)
+    .write_stdin(
+      [
+        "insert 1 alice a@x.com",
+        "insert 2 bob b@x.com",
+        "insert 3 alice c@x.com",
+        "select * from sqlite_stat1",
+        "analyze",
+        "select * from sqlite_stat1",
+        "explain query plan select * from users a join users b on a.id = b.id where b.id = 3",
+        "select a.email, b.email from users a join users b on a.id = b.id where b.id = 3",
+        "explain query plan select * from users a join users b on a.username = b.username where b.id > 2",
+        "select a.id, b.id from users a join users b on a.username = b.username where b.id > 2",
+        "explain query plan select * from users a left join users b on a.id = b.id where b.id = 3",
+        ".exit",
+      ]
+      .join("\n"),
+    )
+    .assert();
+
+  // the stats are kept in the file, for the planner of a later session
+  let reopened = Command::cargo_bin("sqlite_rs")
+    .unwrap()
+    .arg(filename)
+    .write_stdin(
+      [
+        "select * from sqlite_stat1 where idx = 'username'",
+        "explain query plan select * from users a join users b on a.id = b.id where b.id = 3",
+        ".exit",
+      ]
+      .join("\n"),
+    )
+    .assert();
+
+  let _ = std::fs::remove_file(filename);
+
+  assert
+    .success()
+    .stdout(
+      [
+        "db > Executed.",
+        "db > Executed.",
+        "db > Executed.",
+        "db > db > Executed.",
+        "db > (\"users\", NULL, \"3\")",
+        "(\"users\", \"id\", \"3 1\")",
+        "(\"users\", \"username\", \"3 2\")",
+        "(\"users\", \"email\", \"3 1\")",
+        "Executed.",
+        // the table pinned by WHERE goes first, and drives the seek into the other
+        "db > QUERY PLAN",
+        "|--SEARCH b USING PRIMARY KEY (id=?)",
+        "`--SEARCH a USING PRIMARY KEY (id=?)",
+        "Executed.",
+        "db > (\"c@x.com\", \"c@x.com\")",
+        "Executed.",
+        "db > QUERY PLAN",
+        "|--SEARCH b USING PRIMARY KEY (id>?)",
+        "`--SCAN a",
+        "Executed.",
+        "db > (1, 3)",
+        "(3, 3)",
+        "Executed.",
+        // the inner side of a LEFT JOIN stays inner
+        "db > QUERY PLAN",
+        "|--SCAN a",
+        "`--SEARCH b USING PRIMARY KEY (id=?)",
+        "Executed.",
+        "db > ",
+      ]
+      .join("\n"),
+    )
+    .stderr("No such table: sqlite_stat1.\n");
+  reopened.success().stdout(
+    [
+      "db > (\"users\", \"username\", \"3 2\")",
+      "Executed.",
+      "db > QUERY PLAN",
+      "|--SEARCH b USING PRIMARY KEY (id=?)",
+      "`--SEARCH a USING PRIMARY KEY (id=?)",
+      "Executed.",
+      "db > ",
+    ]
+    .join("\n"),
+  );
+}
+
+#[test]
+fn integrity_check_of_a_sound_file() {
+  let filename = "integrity_check_of_a_sound_file.db";
+  let mut script: Vec<_> = (1..=30)
+    .rev()
+    .map(|i| format!("insert {i} user{i} person{i}@example.com"))
+    .collect();
+  script.extend(
+    [
+      "pragma integrity_check",
+      ".check",
+      "pragma quick_check",
+      ".exit",
+    ]
+    .map(str::to_string),
+  );
+  let mut cmd = Command::cargo_bin("sqlite_rs").unwrap();
+  let assert = cmd.arg(filename).write_stdin(script.join("\n")).assert();
+
+  let _ = std::fs::remove_file(filename);
+
+  let mut expected = vec!["db > Executed."; 30];
+  expected.extend([
+    "db > (\"ok\")",
+    "Executed.",
+    "db > ok",
+    "Executed.",
+    "db > db > ",
+  ]);
+  assert
+    .success()
+    .stdout(expected.join("\n"))
+    .stderr("Unknown pragma: quick_check.\n");
+}
+
+#[test]
+fn import_rows_from_a_file() {
+  let filename = "import_rows_from_a_file.db";
+  let csv = "import_rows_from_a_file.csv";
+  let rows: String = (1..=13)
+    .map(|i| format!("{i},user{i},user{i}@x\n"))
+    .collect();
+  std::fs::write(csv, rows + "\n").unwrap();
+  let bad = "import_rows_from_a_file.bad.csv";
+  std::fs::write(bad, "14,user14,user14@x\n15,no email\n").unwrap();
+  let sorted = "import_rows_from_a_file.sorted.csv";
+  let rows: String = (1..=100)
+    .map(|i| format!("{i},user{i},user{i}@x\n"))
+    .collect();
+  std::fs::write(sorted, rows).unwrap();
+
+  let mut cmd = Command::cargo_bin("sqlite_rs").unwrap();
+  let assert = cmd
+    .arg(filename)
+    .write_stdin(
+      [
+        &format!(".import {csv}"),
+        &format!(".import {bad}"),
+        "select count(*) from users",
+        "insert 5 again again@x",
+        ".check",
+        &format!(".import --sorted {sorted}"),
+        ".import --fill 0.5",
+        ".exit",
+      ]
+      .join("\n"),
+    )
+    .assert();
+  let _ = std::fs::remove_file(filename);
+
+  // a bulk load into a fresh file
+  let mut cmd = Command::cargo_bin("sqlite_rs").unwrap();
+  let bulk = cmd
+    .arg(filename)
+    .write_stdin(
+      [
+        &format!(".import --sorted --fill 0.5 {sorted}"),
+        "select count(*), min(id), max(id) from users",
+        ".check",
+        ".exit",
+      ]
+      .join("\n"),
+    )
+    .assert();
+
+  let _ = std::fs::remove_file(filename);
+  let _ = std::fs::remove_file(csv);
+  let _ = std::fs::remove_file(bad);
+  let _ = std::fs::remove_file(sorted);
+
+  assert
+    .success()
+    .stdout(
+      [
+        "db > Executed.",
+        "db > db > (13)",
+        "Executed.",
+        "db > db > ok",
+        "Executed.",
+        "db > db > db > ",
+      ]
+      .join("\n"),
+    )
+    .stderr(
+      [
+        "import_rows_from_a_file.bad.csv:2: expected id,username,email.",
+        "Duplicated key",
+        "Bulk load needs an empty table.",
+        "Usage: .import [--sorted [--fill F]] FILE",
+        "",
+      ]
+      .join("\n"),
+    );
+  bulk.success().stdout(
+    [
+      "db > Executed.",
+      "db > (100, 1, 100)",
+      "Executed.",
+      "db > ok",
+      "Executed.",
+      "db > ",
+    ]
+    .join("\n"),
+  );
+}
+
+#[test]
+fn page_size_option_sizes_the_nodes() {
+  let filename = "page_size_option_sizes_the_nodes.db";
+  let mut cmd = Command::cargo_bin("sqlite_rs").unwrap();
+  let mut script: String = (1..=4)
+    .map(|i| format!("insert {i} user{i} person{i}@example.com\n"))
+    .collect();
+  script.push_str(".constants\n.btree\n.exit");
+  let assert = cmd
+    .args([filename, "--page-size", "1024"])
+    .write_stdin(script)
+    .assert();
+
+  let _ = std::fs::remove_file(filename);
+  // a 1024-byte page holds 3 rows, so the fourth starts a second leaf
+  let mut expect: String = (1..=4).map(|_| "db > Executed.\n").collect();
+  expect.push_str("db > Constants:\nROW_SIZE:                  291\n");
+  expect.push_str("PAGE_SIZE:                 1024\nLEAF_NODE_MAX_CELLS:       3\n");
+  expect.push_str("Executed.\ndb > Tree:\nintern (size 2)\n");
+  expect.push_str("  leaf (size 3)\n    - 1\n    - 2\n    - 3\n  leaf (size 1)\n    - 4\n");
+  expect.push_str("\nExecuted.\ndb > ");
+  assert.success().stdout(expect);
+
+  let mut cmd = Command::cargo_bin("sqlite_rs").unwrap();
+  cmd
+    .args([filename, "--page-size", "1000"])
+    .assert()
+    .failure()
+    .stderr("Page size must be a power of two from 512 to 65536, not 1000.\n");
+  let _ = std::fs::remove_file(filename);
+}